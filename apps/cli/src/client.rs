@@ -0,0 +1,154 @@
+use reqwest::header::AUTHORIZATION;
+use reqwest::{Method, RequestBuilder, Response, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+pub type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug, Deserialize)]
+pub struct Agent {
+    pub id: String,
+    pub name: String,
+    pub working_dir: String,
+    pub model: String,
+    #[serde(default)]
+    pub running: bool,
+    #[serde(default)]
+    pub total_cost_usd: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Terminal {
+    pub id: String,
+    pub working_dir: String,
+}
+
+/// HTTP and WebSocket access to one server with one token
+#[derive(Clone)]
+pub struct Client {
+    base: Url,
+    token: String,
+    http: reqwest::Client,
+    tls: Arc<rustls::ClientConfig>,
+}
+
+impl Client {
+    pub fn new(server: &str, token: String, fingerprint: Option<&str>) -> Result<Self, String> {
+        let base = Url::parse(server).map_err(|e| format!("Invalid server URL {}: {}", server, e))?;
+        if !matches!(base.scheme(), "http" | "https") {
+            return Err(format!("Server URL must be http or https: {}", server));
+        }
+        let tls = crate::tls::client_config(fingerprint)?;
+        let http = reqwest::Client::builder()
+            .use_preconfigured_tls((*tls).clone())
+            .build()
+            .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+
+        Ok(Self { base, token, http, tls })
+    }
+
+    fn url(&self, path: &str) -> Result<Url, String> {
+        self.base.join(path).map_err(|e| format!("Invalid path {}: {}", path, e))
+    }
+
+    pub fn request(&self, method: Method, path: &str) -> Result<RequestBuilder, String> {
+        Ok(self.http.request(method, self.url(path)?).bearer_auth(&self.token))
+    }
+
+    /// Send a request, turning error statuses into their message
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, String> {
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Failed to reach {}: {}", self.base, e))?;
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            Err(Self::error(response).await)
+        }
+    }
+
+    /// Message for an error response
+    pub async fn error(response: Response) -> String {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        // Sandbox refusals are JSON with a message; everything else is plain text
+        let message = serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|json| json.get("message").and_then(|m| m.as_str()).map(str::to_string))
+            .unwrap_or(body);
+        match message.trim() {
+            "" => format!("Server returned {}", status),
+            message => format!("Server returned {}: {}", status, message),
+        }
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, String> {
+        self.json(self.request(Method::GET, path)?).await
+    }
+
+    pub async fn get_query<T: DeserializeOwned>(&self, path: &str, query: &impl Serialize) -> Result<T, String> {
+        self.json(self.request(Method::GET, path)?.query(query)).await
+    }
+
+    pub async fn post<T: DeserializeOwned>(&self, path: &str, body: &impl Serialize) -> Result<T, String> {
+        self.json(self.request(Method::POST, path)?.json(body)).await
+    }
+
+    pub async fn delete(&self, path: &str) -> Result<(), String> {
+        self.send(self.request(Method::DELETE, path)?).await.map(|_| ())
+    }
+
+    async fn json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, String> {
+        self.send(request)
+            .await?
+            .json()
+            .await
+            .map_err(|e| format!("Unexpected response from server: {}", e))
+    }
+
+    /// Open `/ws`. It starts out receiving every broadcast until a `subscribe` is sent.
+    pub async fn websocket(&self) -> Result<Socket, String> {
+        let mut url = self.url("/ws")?;
+        let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+        url.set_scheme(scheme).map_err(|_| "Invalid WebSocket URL".to_string())?;
+
+        let mut request = url
+            .as_str()
+            .into_client_request()
+            .map_err(|e| format!("Invalid WebSocket URL: {}", e))?;
+        let auth = format!("Bearer {}", self.token)
+            .parse()
+            .map_err(|_| "Auth token isn't a valid header value".to_string())?;
+        request.headers_mut().insert(AUTHORIZATION, auth);
+
+        let connector = tokio_tungstenite::Connector::Rustls(self.tls.clone());
+        let (socket, _) = tokio_tungstenite::connect_async_tls_with_config(request, None, false, Some(connector))
+            .await
+            .map_err(|e| format!("Failed to open WebSocket: {}", e))?;
+        Ok(socket)
+    }
+
+    /// Find an agent by id, name or unique id prefix
+    pub async fn find_agent(&self, query: &str) -> Result<Agent, String> {
+        let mut agents: Vec<Agent> = self.get("/api/agents").await?;
+        if let Some(index) = agents.iter().position(|a| a.id == query) {
+            return Ok(agents.swap_remove(index));
+        }
+
+        let mut matches: Vec<Agent> = agents.into_iter().filter(|a| a.name == query || a.id.starts_with(query)).collect();
+        match matches.len() {
+            0 => Err(format!("No agent matches {}", query)),
+            1 => Ok(matches.remove(0)),
+            _ => Err(format!(
+                "{} matches several agents: {}",
+                query,
+                matches.iter().map(|a| a.id.as_str()).collect::<Vec<_>>().join(", ")
+            )),
+        }
+    }
+}
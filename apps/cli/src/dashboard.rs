@@ -0,0 +1,455 @@
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use futures::{SinkExt, StreamExt};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{Frame, Terminal};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::client::{Agent, Client};
+use crate::render::{short_id, tool_summary, Renderer};
+
+/// Output lines kept per agent
+const MAX_OUTPUT_LINES: usize = 2000;
+
+/// How often the agent list is reloaded to pick up agents created or killed elsewhere
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+struct AgentView {
+    id: String,
+    name: String,
+    model: String,
+    working_dir: String,
+    status: String,
+    /// Latest tool call of the current run
+    tool: Option<String>,
+    cost: f64,
+    output: VecDeque<String>,
+}
+
+impl AgentView {
+    fn new(agent: Agent) -> Self {
+        Self {
+            status: if agent.running { "working" } else { "idle" }.to_string(),
+            id: agent.id,
+            name: agent.name,
+            model: agent.model,
+            working_dir: agent.working_dir,
+            tool: None,
+            cost: agent.total_cost_usd,
+            output: VecDeque::new(),
+        }
+    }
+
+    fn push_line(&mut self, line: String) {
+        if self.output.len() == MAX_OUTPUT_LINES {
+            self.output.pop_front();
+        }
+        self.output.push_back(line);
+    }
+}
+
+enum Mode {
+    Normal,
+    /// Typing a message for the selected agent
+    Compose(String),
+}
+
+/// Results of requests made in the background
+enum Update {
+    Agents(Vec<Agent>),
+    Notice(String),
+}
+
+struct Dashboard {
+    agents: Vec<AgentView>,
+    selected: usize,
+    /// Lines scrolled up from the bottom of the output pane; 0 follows new output
+    scroll: usize,
+    mode: Mode,
+    notice: String,
+    renderer: Renderer,
+}
+
+impl Dashboard {
+    fn selected(&self) -> Option<&AgentView> {
+        self.agents.get(self.selected)
+    }
+
+    /// Merge a fresh agent list, keeping the output already collected
+    fn set_agents(&mut self, agents: Vec<Agent>) {
+        let selected_id = self.selected().map(|agent| agent.id.clone());
+        let mut previous = std::mem::take(&mut self.agents);
+
+        for agent in agents {
+            let view = match previous.iter().position(|view| view.id == agent.id) {
+                Some(index) => {
+                    let mut view = previous.swap_remove(index);
+                    view.name = agent.name;
+                    view.model = agent.model;
+                    view.cost = agent.total_cost_usd;
+                    view
+                }
+                None => AgentView::new(agent),
+            };
+            self.agents.push(view);
+        }
+        self.agents.sort_by(|a, b| a.name.cmp(&b.name));
+        self.selected = selected_id
+            .and_then(|id| self.agents.iter().position(|agent| agent.id == id))
+            .unwrap_or(0)
+            .min(self.agents.len().saturating_sub(1));
+    }
+
+    /// Apply a `/ws` broadcast. Returns false for agents not in the list yet.
+    fn apply(&mut self, message: &Value) -> bool {
+        let field = |name: &str| message.get(name).and_then(|v| v.as_str()).unwrap_or_default();
+        if field("type") == "conflict-detected" {
+            self.notice = format!(
+                "Conflict: {} and {} both modified {}",
+                field("agent_name"),
+                field("other_agent_name"),
+                field("file_path")
+            );
+            return true;
+        }
+
+        let Some(agent) = self.agents.iter_mut().find(|agent| agent.id == field("agent_id")) else {
+            return false;
+        };
+        match field("type") {
+            "agent-status" => {
+                agent.status = field("status").to_string();
+                if matches!(agent.status.as_str(), "idle" | "error" | "exited") {
+                    agent.tool = None;
+                }
+            }
+            "agent-output" if field("stream") == "stderr" => agent.push_line(format!("stderr: {}", field("data"))),
+            "agent-output" => {
+                let Ok(line) = serde_json::from_str::<Value>(field("data")) else {
+                    agent.push_line(field("data").to_string());
+                    return true;
+                };
+                let blocks = line.pointer("/message/content").and_then(|c| c.as_array());
+                for block in blocks.into_iter().flatten() {
+                    if block.get("type").and_then(|t| t.as_str()) == Some("tool_use") {
+                        let name = block.get("name").and_then(|n| n.as_str()).unwrap_or("tool");
+                        agent.tool = Some(format!("{} {}", name, tool_summary(block.get("input").unwrap_or(&Value::Null))));
+                    }
+                }
+                if line.get("type").and_then(|t| t.as_str()) == Some("result") {
+                    agent.cost += line.get("total_cost_usd").and_then(|c| c.as_f64()).unwrap_or_default();
+                }
+                for text in self.renderer.stream_json(&line) {
+                    for text_line in text.lines() {
+                        agent.push_line(text_line.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, footer] = Layout::vertical([Constraint::Min(5), Constraint::Length(2)]).areas(frame.area());
+        let [list_area, detail_area] = Layout::horizontal([Constraint::Length(36), Constraint::Min(20)]).areas(main);
+
+        let items: Vec<ListItem> = self
+            .agents
+            .iter()
+            .map(|agent| {
+                ListItem::new(Line::from(vec![
+                    Span::raw(format!("{:<16.16} ", agent.name)),
+                    Span::styled(format!("{:<9}", capitalize(&agent.status)), status_style(&agent.status)),
+                    Span::styled(format!("${:.2}", agent.cost), Style::default().fg(Color::DarkGray)),
+                ]))
+            })
+            .collect();
+        let total: f64 = self.agents.iter().map(|agent| agent.cost).sum();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(format!(" Agents  ${:.2} ", total)))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected((!self.agents.is_empty()).then_some(self.selected));
+        frame.render_stateful_widget(list, list_area, &mut state);
+
+        match self.selected() {
+            Some(agent) => self.draw_agent(frame, agent, detail_area),
+            None => frame.render_widget(
+                Paragraph::new("No agents. Create one with `va agents create`.")
+                    .block(Block::default().borders(Borders::ALL)),
+                detail_area,
+            ),
+        }
+
+        let help = match &self.mode {
+            Mode::Normal => Line::from(Span::styled(
+                "↑/↓ switch  m message  s stop  PgUp/PgDn scroll  End follow  r refresh  q quit",
+                Style::default().fg(Color::DarkGray),
+            )),
+            Mode::Compose(text) => Line::from(vec![
+                Span::styled("message> ", Style::default().fg(Color::Cyan)),
+                Span::raw(text.as_str()),
+                Span::styled("█", Style::default().fg(Color::DarkGray)),
+            ]),
+        };
+        frame.render_widget(Paragraph::new(vec![Line::from(self.notice.as_str()), help]), footer);
+    }
+
+    fn draw_agent(&self, frame: &mut Frame, agent: &AgentView, area: Rect) {
+        let [info_area, output_area] = Layout::vertical([Constraint::Length(5), Constraint::Min(3)]).areas(area);
+
+        let label = |text: &'static str| Span::styled(text, Style::default().fg(Color::DarkGray));
+        let info = vec![
+            Line::from(vec![
+                label("status "),
+                Span::styled(capitalize(&agent.status), status_style(&agent.status)),
+                label("  cost "),
+                Span::raw(format!("${:.4}", agent.cost)),
+                label("  model "),
+                Span::raw(agent.model.as_str()),
+            ]),
+            Line::from(vec![label("tool   "), Span::raw(agent.tool.as_deref().unwrap_or("-"))]),
+            Line::from(vec![label("dir    "), Span::raw(agent.working_dir.as_str())]),
+        ];
+        let title = format!(" {} ({}) ", agent.name, short_id(&agent.id));
+        frame.render_widget(Paragraph::new(info).block(Block::default().borders(Borders::ALL).title(title)), info_area);
+
+        let height = output_area.height.saturating_sub(2) as usize;
+        let end = agent.output.len().saturating_sub(self.scroll);
+        let start = end.saturating_sub(height);
+        let lines: Vec<Line> = agent
+            .output
+            .range(start..end)
+            .map(|line| Line::styled(line.as_str(), output_style(line)))
+            .collect();
+        let title = if self.scroll > 0 { format!(" Output (↑{}) ", self.scroll) } else { " Output ".to_string() };
+        frame.render_widget(Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)), output_area);
+    }
+}
+
+fn capitalize(status: &str) -> String {
+    let mut chars = status.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn status_style(status: &str) -> Style {
+    let color = match status {
+        "thinking" => Color::Yellow,
+        "working" => Color::Green,
+        "error" => Color::Red,
+        _ => Color::DarkGray,
+    };
+    Style::default().fg(color)
+}
+
+/// Colors for the renderer's line shapes
+fn output_style(line: &str) -> Style {
+    if line.starts_with('⏺') {
+        Style::default().fg(Color::Cyan)
+    } else if line.starts_with("  ⎿") || line.starts_with("session ") || line.starts_with('✻') {
+        Style::default().fg(Color::DarkGray)
+    } else if line.starts_with("stderr: ") || line.starts_with('✗') {
+        Style::default().fg(Color::Red)
+    } else if line.starts_with('✓') {
+        Style::default().fg(Color::Green)
+    } else {
+        Style::default()
+    }
+}
+
+/// Leaves the alternate screen and raw mode however the dashboard exits
+struct Screen;
+
+impl Screen {
+    fn enter() -> Result<Self, String> {
+        terminal::enable_raw_mode().map_err(|e| format!("Failed to enable raw mode: {}", e))?;
+        let screen = Self;
+        execute!(std::io::stdout(), EnterAlternateScreen).map_err(|e| format!("Failed to set up the screen: {}", e))?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(std::io::stdout(), LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn spawn_request<F>(updates: &mpsc::UnboundedSender<Update>, request: F)
+where
+    F: std::future::Future<Output = Update> + Send + 'static,
+{
+    let updates = updates.clone();
+    tokio::spawn(async move {
+        let _ = updates.send(request.await);
+    });
+}
+
+fn refresh(client: &Client, updates: &mpsc::UnboundedSender<Update>) {
+    let client = client.clone();
+    spawn_request(updates, async move {
+        match client.get("/api/agents").await {
+            Ok(agents) => Update::Agents(agents),
+            Err(e) => Update::Notice(e),
+        }
+    });
+}
+
+/// Show every agent's status, current tool, cost and output until the user quits
+pub async fn run(client: &Client) -> Result<(), String> {
+    let agents: Vec<Agent> = client.get("/api/agents").await?;
+    let socket = client.websocket().await?;
+    let (mut sink, mut socket) = socket.split();
+    let subscribe = json!({ "type": "subscribe", "events": ["agent-output", "agent-status", "conflict-detected"] });
+    sink.send(Message::Text(subscribe.to_string()))
+        .await
+        .map_err(|e| format!("Failed to subscribe: {}", e))?;
+
+    let mut dashboard = Dashboard {
+        agents: Vec::new(),
+        selected: 0,
+        scroll: 0,
+        mode: Mode::Normal,
+        notice: String::new(),
+        renderer: Renderer::plain(),
+    };
+    dashboard.set_agents(agents);
+
+    let _screen = Screen::enter()?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(std::io::stdout()))
+        .map_err(|e| format!("Failed to set up the terminal: {}", e))?;
+    let mut keys = EventStream::new();
+    let mut ticker = tokio::time::interval(REFRESH_INTERVAL);
+    let (updates_tx, mut updates) = mpsc::unbounded_channel();
+
+    loop {
+        terminal
+            .draw(|frame| dashboard.draw(frame))
+            .map_err(|e| format!("Failed to draw: {}", e))?;
+
+        tokio::select! {
+            event = keys.next() => match event {
+                Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                    if !handle_key(&mut dashboard, key, client, &updates_tx) {
+                        return Ok(());
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(format!("Failed to read the keyboard: {}", e)),
+                None => return Ok(()),
+            },
+            message = socket.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    if let Ok(message) = serde_json::from_str::<Value>(&text) {
+                        if !dashboard.apply(&message) {
+                            refresh(client, &updates_tx);
+                        }
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(format!("Lost the connection to the server: {}", e)),
+                None => return Err("The server closed the connection".to_string()),
+            },
+            Some(update) = updates.recv() => match update {
+                Update::Agents(agents) => dashboard.set_agents(agents),
+                Update::Notice(notice) => dashboard.notice = notice,
+            },
+            _ = ticker.tick() => refresh(client, &updates_tx),
+        }
+    }
+}
+
+/// Returns false to quit
+fn handle_key(dashboard: &mut Dashboard, key: KeyEvent, client: &Client, updates: &mpsc::UnboundedSender<Update>) -> bool {
+    if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+        return false;
+    }
+
+    if let Mode::Compose(text) = &mut dashboard.mode {
+        match key.code {
+            KeyCode::Esc => dashboard.mode = Mode::Normal,
+            KeyCode::Backspace => {
+                text.pop();
+            }
+            KeyCode::Char(c) => text.push(c),
+            KeyCode::Enter => {
+                let message = std::mem::take(text);
+                dashboard.mode = Mode::Normal;
+                if let (Some(agent), false) = (dashboard.selected(), message.trim().is_empty()) {
+                    let client = client.clone();
+                    let (id, name) = (agent.id.clone(), agent.name.clone());
+                    dashboard.scroll = 0;
+                    spawn_request(updates, async move {
+                        let sent: Result<Value, String> =
+                            client.post(&format!("/api/agents/{}/messages", id), &json!({ "message": message })).await;
+                        Update::Notice(match sent {
+                            Ok(_) => format!("Sent to {}", name),
+                            Err(e) => e,
+                        })
+                    });
+                }
+            }
+            _ => {}
+        }
+        return true;
+    }
+
+    let count = dashboard.agents.len();
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => return false,
+        KeyCode::Down | KeyCode::Char('j') | KeyCode::Tab if count > 0 => {
+            dashboard.selected = (dashboard.selected + 1) % count;
+            dashboard.scroll = 0;
+        }
+        KeyCode::Up | KeyCode::Char('k') | KeyCode::BackTab if count > 0 => {
+            dashboard.selected = (dashboard.selected + count - 1) % count;
+            dashboard.scroll = 0;
+        }
+        KeyCode::Char(c @ '1'..='9') => {
+            let index = c as usize - '1' as usize;
+            if index < count {
+                dashboard.selected = index;
+                dashboard.scroll = 0;
+            }
+        }
+        KeyCode::Char('m') | KeyCode::Enter if count > 0 => dashboard.mode = Mode::Compose(String::new()),
+        KeyCode::Char('s') => {
+            if let Some(agent) = dashboard.selected() {
+                let client = client.clone();
+                let (id, name) = (agent.id.clone(), agent.name.clone());
+                spawn_request(updates, async move {
+                    let stopped = match client.request(reqwest::Method::POST, &format!("/api/agents/{}/stop", id)) {
+                        Ok(request) => client.send(request).await.map(|_| ()),
+                        Err(e) => Err(e),
+                    };
+                    Update::Notice(match stopped {
+                        Ok(()) => format!("Stopped {}", name),
+                        Err(e) => e,
+                    })
+                });
+            }
+        }
+        KeyCode::PageUp => {
+            let lines = dashboard.selected().map_or(0, |agent| agent.output.len());
+            dashboard.scroll = (dashboard.scroll + 10).min(lines.saturating_sub(1));
+        }
+        KeyCode::PageDown => dashboard.scroll = dashboard.scroll.saturating_sub(10),
+        KeyCode::End | KeyCode::Char('G') => dashboard.scroll = 0,
+        KeyCode::Char('r') => refresh(client, updates),
+        _ => {}
+    }
+    true
+}
@@ -0,0 +1,160 @@
+use futures::StreamExt;
+use reqwest::header::ACCEPT;
+use reqwest::Method;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::client::Client;
+use crate::render::Renderer;
+
+/// Wait before reconnecting a dropped event stream
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Exit code for runs stopped by their timeout, as `timeout(1)` uses
+const EXIT_TIMED_OUT: i32 = 124;
+
+/// Exit code for runs stopped with Ctrl-C
+const EXIT_STOPPED: i32 = 130;
+
+/// Splits a byte stream into lines, holding back the unterminated tail
+#[derive(Default)]
+struct Lines {
+    buf: Vec<u8>,
+}
+
+impl Lines {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buf.extend_from_slice(chunk);
+        let mut lines = Vec::new();
+        while let Some(end) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line[..end]);
+            lines.push(line.trim_end_matches('\r').to_string());
+        }
+        lines
+    }
+}
+
+/// Send a message through `/run` and render the agent's events until it finishes.
+/// Ctrl-C stops the agent; the exit code reflects how the run ended.
+pub async fn send(client: &Client, agent_id: &str, message: &str, timeout_secs: Option<u64>) -> Result<i32, String> {
+    let body = json!({ "message": message, "stream": true, "timeout_secs": timeout_secs });
+    let request = client
+        .request(Method::POST, &format!("/api/agents/{}/run", agent_id))?
+        .header(ACCEPT, "application/x-ndjson")
+        .json(&body);
+    let mut stream = client.send(request).await?.bytes_stream();
+
+    let renderer = Renderer::new();
+    let mut lines = Lines::default();
+    let mut stopping = false;
+    loop {
+        let chunk = tokio::select! {
+            chunk = stream.next() => chunk,
+            _ = tokio::signal::ctrl_c(), if !stopping => {
+                stopping = true;
+                eprintln!("Stopping the agent…");
+                let stop = client.request(Method::POST, &format!("/api/agents/{}/stop", agent_id))?;
+                client.send(stop).await?;
+                continue;
+            }
+        };
+        let chunk = match chunk {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(e)) => return Err(format!("Lost the connection to the server: {}", e)),
+            None => return Err("The server closed the stream before the run finished".to_string()),
+        };
+
+        for line in lines.push(&chunk) {
+            let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                continue;
+            };
+            if message.get("type").and_then(|t| t.as_str()) != Some("run-result") {
+                renderer.event(&message, false);
+                continue;
+            }
+
+            renderer.run_result(&message);
+            return Ok(match message.get("status").and_then(|s| s.as_str()) {
+                Some("completed") => 0,
+                Some("timed_out") => EXIT_TIMED_OUT,
+                Some("stopped") => EXIT_STOPPED,
+                _ => message
+                    .get("exit_code")
+                    .and_then(|c| c.as_i64())
+                    .and_then(|c| i32::try_from(c).ok())
+                    .filter(|c| *c != 0)
+                    .unwrap_or(1),
+            });
+        }
+    }
+}
+
+/// Whether a broadcast is about an agent. Output for one agent is printed unlabeled, so
+/// anything else a server sends on its stream is dropped rather than passed off as its own.
+fn concerns(message: &Value, agent_id: &str) -> bool {
+    ["agent_id", "other_agent_id"]
+        .iter()
+        .any(|field| message.get(field).and_then(|v| v.as_str()) == Some(agent_id))
+}
+
+/// Follow an agent's event stream, or every agent's when `agent_id` is None, reconnecting
+/// with `Last-Event-ID` so nothing still in the server's backlog is missed
+pub async fn tail(client: &Client, agent_id: Option<&str>, events: Option<&str>) -> Result<(), String> {
+    let path = match agent_id {
+        Some(id) => format!("/api/agents/{}/events", id),
+        None => "/api/events".to_string(),
+    };
+    let renderer = Renderer::new();
+    let mut last_event_id: Option<String> = None;
+
+    loop {
+        let mut request = client.request(Method::GET, &path)?.header(ACCEPT, "text/event-stream");
+        if let Some(events) = events {
+            request = request.query(&[("events", events)]);
+        }
+        if let Some(id) = &last_event_id {
+            request = request.header("Last-Event-ID", id);
+        }
+
+        // Connection failures are retried, but refusals like a 404 or 403 are final
+        let response = match request.send().await {
+            Ok(response) if !response.status().is_success() => return Err(Client::error(response).await),
+            Ok(response) => response,
+            Err(e) => {
+                eprintln!("Failed to reach the server ({}), retrying…", e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+
+        let mut stream = response.bytes_stream();
+        let mut lines = Lines::default();
+        let mut data = String::new();
+        while let Some(chunk) = stream.next().await {
+            let Ok(chunk) = chunk else {
+                break;
+            };
+            for line in lines.push(&chunk) {
+                if let Some(value) = line.strip_prefix("data:") {
+                    data.push_str(value.trim_start());
+                } else if let Some(id) = line.strip_prefix("id:") {
+                    last_event_id = Some(id.trim().to_string());
+                } else if line.starts_with(": some events") {
+                    // The server's notice that the backlog no longer reaches back that far
+                    eprintln!("Some events were missed while disconnected");
+                } else if line.is_empty() && !data.is_empty() {
+                    if let Ok(message) = serde_json::from_str::<Value>(&data) {
+                        if agent_id.is_none_or(|id| concerns(&message, id)) {
+                            renderer.event(&message, agent_id.is_none());
+                        }
+                    }
+                    data.clear();
+                }
+            }
+        }
+
+        eprintln!("Event stream ended, reconnecting…");
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
@@ -0,0 +1,404 @@
+mod client;
+mod dashboard;
+mod events;
+mod render;
+mod terminal;
+mod tls;
+
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::Read;
+use std::path::PathBuf;
+
+use client::{Agent, Client, Terminal};
+use render::{short_id, truncate, Renderer};
+
+/// Drive a Virtual Agency server from the command line
+#[derive(Debug, Parser)]
+#[command(name = "va", version, about = "Command-line client for Virtual Agency")]
+struct Cli {
+    /// Server address
+    #[arg(long, env = "VA_SERVER", default_value = "http://127.0.0.1:3001", global = true)]
+    server: String,
+
+    /// Auth token or paired device token (default: the server's own token from --data-dir)
+    #[arg(long, env = "VA_TOKEN", hide_env_values = true, global = true)]
+    token: Option<String>,
+
+    /// Server data directory to read `auth.token` from when --token isn't given
+    #[arg(long, env = "VIRTUAL_AGENCY_DATA_DIR", global = true)]
+    data_dir: Option<PathBuf>,
+
+    /// SHA-256 fingerprint of the server's self-signed certificate, as printed at startup
+    #[arg(long, env = "VA_FINGERPRINT", global = true)]
+    fingerprint: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List, create and kill agents
+    #[command(subcommand)]
+    Agents(AgentsCommand),
+    /// Send a message to an agent and show its output until it finishes
+    Send {
+        /// Agent id, name or id prefix
+        agent: String,
+        /// The message; `-` reads it from stdin
+        #[arg(required = true, num_args = 1..)]
+        message: Vec<String>,
+        /// Seconds before the server stops the run
+        #[arg(long)]
+        timeout: Option<u64>,
+        /// Return once the message is accepted instead of following the run
+        #[arg(long)]
+        detach: bool,
+    },
+    /// Follow an agent's output live, or every agent's
+    Tail {
+        /// Agent id, name or id prefix
+        agent: Option<String>,
+        /// Comma-separated event kinds, e.g. `agent-output,agent-status`
+        #[arg(long)]
+        events: Option<String>,
+    },
+    /// List, create and attach to terminals
+    #[command(subcommand)]
+    Terminal(TerminalCommand),
+    /// Browse past Claude sessions in a directory
+    #[command(subcommand)]
+    History(HistoryCommand),
+    /// Full-screen view of every agent's status, current tool, cost and output
+    #[command(alias = "top")]
+    Dashboard,
+}
+
+#[derive(Debug, Subcommand)]
+enum AgentsCommand {
+    #[command(alias = "ls")]
+    List,
+    Create {
+        name: String,
+        /// Working directory on the server (default: the current directory)
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        #[arg(long)]
+        model: Option<String>,
+        /// Template providing defaults for the other settings
+        #[arg(long)]
+        template: Option<String>,
+        #[arg(long)]
+        persona: Option<String>,
+        /// Resume this Claude session
+        #[arg(long)]
+        session: Option<String>,
+    },
+    Kill {
+        /// Agent id, name or id prefix
+        agent: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum TerminalCommand {
+    #[command(alias = "ls")]
+    List,
+    /// Attach to a terminal, creating one when no id is given. Ctrl-] detaches.
+    Attach {
+        id: Option<String>,
+        /// Working directory of a new terminal (default: the agent's, or the current directory)
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        /// Give a new terminal this agent's environment and working directory
+        #[arg(long)]
+        agent: Option<String>,
+    },
+    Kill {
+        id: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum HistoryCommand {
+    /// List sessions, newest first
+    #[command(alias = "ls")]
+    List {
+        /// Project directory (default: the agent's, or the current directory)
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        #[arg(long)]
+        agent: Option<String>,
+    },
+    /// Print a session's transcript
+    Show {
+        session: String,
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        #[arg(long)]
+        agent: Option<String>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct SessionSummary {
+    session_id: String,
+    first_prompt: Option<String>,
+    summary: Option<String>,
+    updated_at: Option<String>,
+    message_count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct SessionMessage {
+    role: String,
+    timestamp: Option<String>,
+    text: String,
+    tool_calls: Vec<ToolCall>,
+    tool_results: Vec<ToolResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolCall {
+    name: String,
+    input: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolResult {
+    content: String,
+    is_error: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct SessionDetail {
+    #[serde(flatten)]
+    summary: SessionSummary,
+    messages: Vec<SessionMessage>,
+}
+
+/// `--token`, else the token the server generated in its data dir
+fn load_token(cli: &Cli) -> Result<String, String> {
+    if let Some(token) = &cli.token {
+        return Ok(token.clone());
+    }
+
+    let data_dir = cli
+        .data_dir
+        .clone()
+        .or_else(|| dirs::data_dir().map(|dir| dir.join("virtual-agency")))
+        .ok_or_else(|| "No data directory; pass --token".to_string())?;
+    let path = data_dir.join("auth.token");
+    let token = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {} ({}); pass --token or set VA_TOKEN", path.display(), e))?;
+    Ok(token.trim().to_string())
+}
+
+fn absolute_dir(dir: Option<PathBuf>) -> Result<String, String> {
+    let dir = match dir {
+        Some(dir) => dir,
+        None => std::env::current_dir().map_err(|e| format!("Failed to read current directory: {}", e))?,
+    };
+    std::path::absolute(&dir)
+        .map(|dir| dir.to_string_lossy().into_owned())
+        .map_err(|e| format!("Invalid directory {}: {}", dir.display(), e))
+}
+
+/// `--dir`, else the agent's working directory, else the current directory
+async fn project_dir(client: &Client, dir: Option<PathBuf>, agent: Option<&str>) -> Result<String, String> {
+    match (dir, agent) {
+        (None, Some(agent)) => Ok(client.find_agent(agent).await?.working_dir),
+        (dir, _) => absolute_dir(dir),
+    }
+}
+
+async fn run_agents(client: &Client, command: AgentsCommand) -> Result<i32, String> {
+    match command {
+        AgentsCommand::List => {
+            let agents: Vec<Agent> = client.get("/api/agents").await?;
+            if agents.is_empty() {
+                println!("No agents");
+            }
+            for agent in agents {
+                println!("{}  {:<20} {:<8} {}", short_id(&agent.id), agent.name, agent.model, agent.working_dir);
+            }
+        }
+        AgentsCommand::Create {
+            name,
+            dir,
+            model,
+            template,
+            persona,
+            session,
+        } => {
+            let body = json!({
+                "name": name,
+                "working_dir": absolute_dir(dir)?,
+                "model": model,
+                "template_id": template,
+                "persona_id": persona,
+                "session_id": session,
+            });
+            let agent: Value = client.post("/api/agents", &body).await?;
+            println!("{}", agent.get("id").and_then(|id| id.as_str()).unwrap_or_default());
+            if let Some(overlapping) = agent.get("overlapping_agents").and_then(|o| o.as_array()) {
+                for other in overlapping {
+                    let name = other.get("agent_name").and_then(|n| n.as_str()).unwrap_or("another agent");
+                    eprintln!("warning: {} works in an overlapping directory", name);
+                }
+            }
+        }
+        AgentsCommand::Kill { agent } => {
+            let agent = client.find_agent(&agent).await?;
+            client.delete(&format!("/api/agents/{}", agent.id)).await?;
+            eprintln!("Killed {} ({})", agent.name, short_id(&agent.id));
+        }
+    }
+    Ok(0)
+}
+
+async fn run_terminal(client: &Client, command: TerminalCommand) -> Result<i32, String> {
+    match command {
+        TerminalCommand::List => {
+            let terminals: Vec<Terminal> = client.get("/api/terminals").await?;
+            if terminals.is_empty() {
+                println!("No terminals");
+            }
+            for terminal in terminals {
+                println!("{}  {}", terminal.id, terminal.working_dir);
+            }
+        }
+        TerminalCommand::Attach { id: Some(id), .. } => terminal::attach(client, &id).await?,
+        TerminalCommand::Attach { id: None, dir, agent } => {
+            let agent = match agent {
+                Some(agent) => Some(client.find_agent(&agent).await?),
+                None => None,
+            };
+            let working_dir = match (dir, &agent) {
+                (None, Some(agent)) => agent.working_dir.clone(),
+                (dir, _) => absolute_dir(dir)?,
+            };
+            let (cols, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+            let body = json!({
+                "working_dir": working_dir,
+                "cols": cols,
+                "rows": rows,
+                "agent_id": agent.map(|agent| agent.id),
+            });
+            let terminal: Terminal = client.post("/api/terminals", &body).await?;
+            terminal::attach(client, &terminal.id).await?;
+        }
+        TerminalCommand::Kill { id } => {
+            client.delete(&format!("/api/terminals/{}", id)).await?;
+            eprintln!("Killed terminal {}", id);
+        }
+    }
+    Ok(0)
+}
+
+async fn run_history(client: &Client, command: HistoryCommand) -> Result<i32, String> {
+    match command {
+        HistoryCommand::List { dir, agent } => {
+            let dir = project_dir(client, dir, agent.as_deref()).await?;
+            let sessions: Vec<SessionSummary> = client.get_query("/api/sessions", &[("working_dir", &dir)]).await?;
+            if sessions.is_empty() {
+                println!("No sessions in {}", dir);
+            }
+            for session in sessions {
+                let title = session.summary.or(session.first_prompt).unwrap_or_default();
+                println!(
+                    "{}  {:<20} {:>4} msgs  {}",
+                    session.session_id,
+                    session.updated_at.as_deref().and_then(|t| t.get(..19)).unwrap_or("-"),
+                    session.message_count,
+                    truncate(title.lines().next().unwrap_or_default())
+                );
+            }
+        }
+        HistoryCommand::Show { session, dir, agent } => {
+            let dir = project_dir(client, dir, agent.as_deref()).await?;
+            let detail: SessionDetail = client.get_query(&format!("/api/sessions/{}", session), &[("working_dir", &dir)]).await?;
+
+            let renderer = Renderer::new();
+            if let Some(summary) = &detail.summary.summary {
+                println!("# {}\n", summary);
+            }
+            for message in detail.messages {
+                if !message.text.trim().is_empty() {
+                    println!("{} {}", message.role, message.timestamp.as_deref().unwrap_or_default());
+                    println!("{}\n", message.text.trim());
+                }
+                for call in &message.tool_calls {
+                    println!("{}", renderer.tool_call(&call.name, &call.input));
+                }
+                for result in &message.tool_results {
+                    println!("{}", renderer.tool_result(&result.content, result.is_error));
+                }
+            }
+        }
+    }
+    Ok(0)
+}
+
+async fn run(cli: Cli) -> Result<i32, String> {
+    let token = load_token(&cli)?;
+    let client = Client::new(&cli.server, token, cli.fingerprint.as_deref())?;
+
+    match cli.command {
+        Command::Agents(command) => run_agents(&client, command).await,
+        Command::Send {
+            agent,
+            message,
+            timeout,
+            detach,
+        } => {
+            let message = if message == ["-"] {
+                let mut message = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut message)
+                    .map_err(|e| format!("Failed to read the message from stdin: {}", e))?;
+                message
+            } else {
+                message.join(" ")
+            };
+            let agent = client.find_agent(&agent).await?;
+
+            if detach {
+                let _: Value = client
+                    .post(&format!("/api/agents/{}/messages", agent.id), &json!({ "message": message }))
+                    .await?;
+                eprintln!("Sent to {}; follow it with `va tail {}`", agent.name, short_id(&agent.id));
+                return Ok(0);
+            }
+            events::send(&client, &agent.id, &message, timeout).await
+        }
+        Command::Tail { agent, events } => {
+            let agent_id = match agent {
+                Some(agent) => Some(client.find_agent(&agent).await?.id),
+                None => None,
+            };
+            events::tail(&client, agent_id.as_deref(), events.as_deref()).await?;
+            Ok(0)
+        }
+        Command::Terminal(command) => run_terminal(&client, command).await,
+        Command::History(command) => run_history(&client, command).await,
+        Command::Dashboard => {
+            dashboard::run(&client).await?;
+            Ok(0)
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("va: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
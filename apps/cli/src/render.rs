@@ -0,0 +1,212 @@
+use crossterm::style::Stylize;
+use serde_json::Value;
+use std::io::IsTerminal;
+
+/// Longest tool argument or result line shown before it's cut off
+const MAX_SUMMARY_CHARS: usize = 100;
+
+/// Input fields that best describe a tool call, in order of preference
+const SUMMARY_FIELDS: [&str; 8] = ["command", "file_path", "path", "pattern", "url", "query", "description", "prompt"];
+
+/// Turns `/ws`, event stream and `/run` messages into readable terminal output
+pub struct Renderer {
+    color: bool,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Self {
+            color: std::io::stdout().is_terminal(),
+        }
+    }
+
+    /// Without colors, for the dashboard, which styles lines itself
+    pub fn plain() -> Self {
+        Self { color: false }
+    }
+
+    fn dim(&self, text: &str) -> String {
+        if self.color { text.dark_grey().to_string() } else { text.to_string() }
+    }
+
+    fn accent(&self, text: &str) -> String {
+        if self.color { text.cyan().bold().to_string() } else { text.to_string() }
+    }
+
+    fn error(&self, text: &str) -> String {
+        if self.color { text.red().to_string() } else { text.to_string() }
+    }
+
+    fn success(&self, text: &str) -> String {
+        if self.color { text.green().to_string() } else { text.to_string() }
+    }
+
+    /// Render one broadcast message; `show_agent` prefixes lines with the agent id
+    pub fn event(&self, message: &Value, show_agent: bool) {
+        let field = |name: &str| message.get(name).and_then(|v| v.as_str()).unwrap_or_default();
+        let prefix = if show_agent {
+            self.dim(&format!("[{}] ", short_id(field("agent_id"))))
+        } else {
+            String::new()
+        };
+
+        match field("type") {
+            "agent-output" if field("stream") == "stderr" => {
+                eprintln!("{}{}", prefix, self.error(field("data")));
+            }
+            "agent-output" => match serde_json::from_str::<Value>(field("data")) {
+                Ok(line) => {
+                    for text in self.stream_json(&line) {
+                        println!("{}{}", prefix, text);
+                    }
+                }
+                // The CLI occasionally prints plain text between JSON lines
+                Err(_) => println!("{}{}", prefix, field("data")),
+            },
+            "agent-status" => println!("{}{}", prefix, self.dim(&format!("… {}", field("status")))),
+            "conflict-detected" => eprintln!(
+                "{}{} {} was also modified by {}",
+                prefix,
+                self.error("conflict:"),
+                field("file_path"),
+                field("other_agent_name")
+            ),
+            _ => {}
+        }
+    }
+
+    /// Lines for one Claude CLI stream-json message
+    pub fn stream_json(&self, line: &Value) -> Vec<String> {
+        let content = || {
+            line.pointer("/message/content")
+                .and_then(|c| c.as_array())
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        match line.get("type").and_then(|t| t.as_str()) {
+            Some("system") if line.get("subtype").and_then(|s| s.as_str()) == Some("init") => {
+                let session = line.get("session_id").and_then(|s| s.as_str()).unwrap_or("?");
+                let model = line.get("model").and_then(|m| m.as_str()).unwrap_or("");
+                vec![self.dim(format!("session {} {}", session, model).trim_end())]
+            }
+            Some("assistant") => content()
+                .iter()
+                .filter_map(|block| match block.get("type").and_then(|t| t.as_str()) {
+                    Some("text") => block.get("text").and_then(|t| t.as_str()).map(str::to_string),
+                    Some("thinking") => block
+                        .get("thinking")
+                        .and_then(|t| t.as_str())
+                        .map(|thinking| self.dim(&format!("✻ {}", truncate(first_line(thinking))))),
+                    Some("tool_use") => Some(self.tool_call(
+                        block.get("name").and_then(|n| n.as_str()).unwrap_or("tool"),
+                        block.get("input").unwrap_or(&Value::Null),
+                    )),
+                    _ => None,
+                })
+                .collect(),
+            Some("user") => content()
+                .iter()
+                .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("tool_result"))
+                .map(|block| {
+                    let is_error = block.get("is_error").and_then(|e| e.as_bool()).unwrap_or(false);
+                    self.tool_result(&result_text(block.get("content").unwrap_or(&Value::Null)), is_error)
+                })
+                .collect(),
+            Some("result") => {
+                let is_error = line.get("is_error").and_then(|e| e.as_bool()).unwrap_or(false);
+                let mut details = Vec::new();
+                if let Some(turns) = line.get("num_turns").and_then(|t| t.as_u64()) {
+                    details.push(format!("{} turns", turns));
+                }
+                if let Some(ms) = line.get("duration_ms").and_then(|d| d.as_u64()) {
+                    details.push(format!("{:.1}s", ms as f64 / 1000.0));
+                }
+                if let Some(cost) = line.get("total_cost_usd").and_then(|c| c.as_f64()) {
+                    details.push(format!("${:.4}", cost));
+                }
+                let summary = if is_error { self.error("✗ failed") } else { self.success("✓ done") };
+                vec![format!("{} {}", summary, self.dim(&details.join(", ")))]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn tool_call(&self, name: &str, input: &Value) -> String {
+        format!("{} {}", self.accent(&format!("⏺ {}", name)), tool_summary(input))
+    }
+
+    pub fn tool_result(&self, text: &str, is_error: bool) -> String {
+        let lines = text.lines().count();
+        let mut summary = truncate(first_line(text));
+        if lines > 1 {
+            summary.push_str(&format!(" (+{} lines)", lines - 1));
+        }
+        let summary = format!("  ⎿ {}", summary);
+        if is_error { self.error(&summary) } else { self.dim(&summary) }
+    }
+
+    /// Final line of a `/run`; completed runs were already summarised by their result message
+    pub fn run_result(&self, result: &Value) {
+        let status = result.get("status").and_then(|s| s.as_str()).unwrap_or("failed");
+        match status {
+            "completed" => {}
+            "timed_out" => eprintln!("{}", self.error("✗ timed out, the agent was stopped")),
+            "stopped" => eprintln!("{}", self.error("✗ stopped")),
+            _ => match result.get("exit_code").and_then(|c| c.as_i64()) {
+                Some(code) => eprintln!("{}", self.error(&format!("✗ failed with exit code {}", code))),
+                None => eprintln!("{}", self.error("✗ failed")),
+            },
+        }
+
+        let changed: Vec<&str> = result
+            .get("changed_files")
+            .and_then(|f| f.as_array())
+            .map(|files| files.iter().filter_map(|f| f.as_str()).collect())
+            .unwrap_or_default();
+        if !changed.is_empty() {
+            println!("{}", self.dim(&format!("changed {}", changed.join(", "))));
+        }
+    }
+}
+
+/// The most telling input field, or the whole input as compact JSON
+pub fn tool_summary(input: &Value) -> String {
+    let summary = SUMMARY_FIELDS
+        .iter()
+        .find_map(|field| input.get(field).and_then(|v| v.as_str()))
+        .map(|value| first_line(value).to_string())
+        .unwrap_or_else(|| match input {
+            Value::Null => String::new(),
+            input => input.to_string(),
+        });
+    truncate(&summary)
+}
+
+/// Tool result content is either a string or a list of content blocks
+fn result_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|block| block.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+fn first_line(text: &str) -> &str {
+    text.trim().lines().next().unwrap_or_default()
+}
+
+pub fn truncate(text: &str) -> String {
+    match text.char_indices().nth(MAX_SUMMARY_CHARS) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+pub fn short_id(id: &str) -> &str {
+    id.get(..8).unwrap_or(id)
+}
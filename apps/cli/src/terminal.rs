@@ -0,0 +1,154 @@
+use crossterm::terminal;
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::io::Write;
+use tokio::io::AsyncReadExt;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::client::Client;
+
+/// Ctrl-], as in telnet; everything else is passed through to the PTY
+const DETACH_KEY: u8 = 0x1d;
+
+/// Puts the local terminal back in cooked mode however `attach` returns
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> Result<Self, String> {
+        terminal::enable_raw_mode().map_err(|e| format!("Failed to enable raw mode: {}", e))?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Local window size changes. Only Unix signals them; elsewhere the size is sent once on attach.
+struct Resizes {
+    #[cfg(unix)]
+    signal: tokio::signal::unix::Signal,
+}
+
+impl Resizes {
+    fn watch() -> Result<Self, String> {
+        Ok(Self {
+            #[cfg(unix)]
+            signal: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::window_change())
+                .map_err(|e| format!("Failed to watch resizes: {}", e))?,
+        })
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        self.signal.recv().await;
+        #[cfg(not(unix))]
+        std::future::pending::<()>().await;
+    }
+}
+
+fn resize_message(terminal_id: &str) -> Option<Message> {
+    let (cols, rows) = terminal::size().ok()?;
+    Some(Message::Text(
+        json!({ "type": "terminal-resize", "terminal_id": terminal_id, "cols": cols, "rows": rows }).to_string(),
+    ))
+}
+
+/// Split off the longest valid UTF-8 prefix, keeping a trailing partial character for the next read
+fn take_utf8(pending: &mut Vec<u8>) -> String {
+    let valid = match std::str::from_utf8(pending) {
+        Ok(_) => pending.len(),
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        // Not UTF-8 at all; send it replaced rather than stalling
+        Err(_) => pending.len(),
+    };
+    let text = String::from_utf8_lossy(&pending[..valid]).into_owned();
+    pending.drain(..valid);
+    text
+}
+
+/// Relay stdin and the PTY's output until the user detaches or the connection closes.
+/// The terminal keeps running on the server after detaching.
+pub async fn attach(client: &Client, terminal_id: &str) -> Result<(), String> {
+    let socket = client.websocket().await?;
+    let (mut sink, mut stream) = socket.split();
+
+    let subscribe = json!({ "type": "subscribe", "terminals": [terminal_id] });
+    sink.send(Message::Text(subscribe.to_string()))
+        .await
+        .map_err(|e| format!("Failed to subscribe: {}", e))?;
+    if let Some(resize) = resize_message(terminal_id) {
+        let _ = sink.send(resize).await;
+    }
+
+    eprintln!("Attached to terminal {}. Press Ctrl-] to detach.", terminal_id);
+    let raw_mode = RawMode::enable()?;
+    let mut stdin = tokio::io::stdin();
+    let mut stdout = std::io::stdout();
+    let mut resized = Resizes::watch()?;
+    let mut buf = [0u8; 4096];
+    let mut pending = Vec::new();
+
+    let reason = loop {
+        tokio::select! {
+            read = stdin.read(&mut buf) => {
+                let n = match read {
+                    Ok(0) => break "Stdin closed".to_string(),
+                    Ok(n) => n,
+                    Err(e) => break format!("Failed to read stdin: {}", e),
+                };
+                let (input, detach) = match buf[..n].iter().position(|b| *b == DETACH_KEY) {
+                    Some(at) => (&buf[..at], true),
+                    None => (&buf[..n], false),
+                };
+                pending.extend_from_slice(input);
+                let data = take_utf8(&mut pending);
+                if !data.is_empty() {
+                    let input = json!({ "type": "terminal-input", "terminal_id": terminal_id, "data": data });
+                    if sink.send(Message::Text(input.to_string())).await.is_err() {
+                        break "Connection closed".to_string();
+                    }
+                }
+                if detach {
+                    break "Detached".to_string();
+                }
+            }
+            _ = resized.recv() => {
+                if let Some(resize) = resize_message(terminal_id) {
+                    let _ = sink.send(resize).await;
+                }
+            }
+            message = stream.next() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => break "Connection closed".to_string(),
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => break format!("Connection lost: {}", e),
+                };
+                let Ok(message) = serde_json::from_str::<Value>(&text) else {
+                    continue;
+                };
+                let field = |name: &str| message.get(name).and_then(|v| v.as_str()).unwrap_or_default();
+                match field("type") {
+                    "terminal-output" if field("terminal_id") == terminal_id => {
+                        let _ = stdout.write_all(field("data").as_bytes());
+                        let _ = stdout.flush();
+                    }
+                    // Raw mode needs explicit carriage returns
+                    "error" => {
+                        let _ = write!(stdout, "\r\n[va] {}\r\n", field("message"));
+                        let _ = stdout.flush();
+                    }
+                    _ => {}
+                }
+            }
+        }
+    };
+
+    drop(raw_mode);
+    let _ = sink.close().await;
+    eprintln!("\n[va] {}. Reattach with `va terminal attach {}`", reason, terminal_id);
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use super::AgentProcess;
+use super::{AgentProcess, AgentSettings, AgentSettingsUpdate};
 use std::collections::HashMap;
 use tauri::AppHandle;
 
@@ -18,15 +18,14 @@ impl AgentManager {
         id: String,
         working_dir: String,
         app_handle: AppHandle,
-        model: String,
-        thinking_enabled: bool,
+        settings: AgentSettings,
         session_id: Option<String>,
     ) -> Result<(), String> {
         if self.agents.contains_key(&id) {
             return Err("Agent with this ID already exists".to_string());
         }
 
-        let agent = AgentProcess::new(id.clone(), working_dir, app_handle, model, thinking_enabled, session_id)?;
+        let agent = AgentProcess::new(id.clone(), working_dir, app_handle, settings, session_id)?;
         self.agents.insert(id, agent);
         Ok(())
     }
@@ -52,15 +51,10 @@ impl AgentManager {
         self.agents.keys().cloned().collect()
     }
 
-    pub fn update_agent_settings(
-        &mut self,
-        id: &str,
-        model: Option<String>,
-        thinking_enabled: Option<bool>,
-    ) -> Result<(), String> {
+    pub fn update_agent_settings(&mut self, id: &str, update: AgentSettingsUpdate) -> Result<(), String> {
         match self.agents.get_mut(id) {
             Some(agent) => {
-                agent.update_settings(model, thinking_enabled);
+                agent.update_settings(update);
                 Ok(())
             }
             None => Err("Agent not found".to_string()),
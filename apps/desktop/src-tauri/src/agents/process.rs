@@ -7,11 +7,39 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 use tauri::{AppHandle, Emitter};
 
+/// Agent settings that can be changed after creation
+#[derive(Debug, Clone)]
+pub struct AgentSettings {
+    pub model: String,
+    pub thinking_enabled: bool,
+    /// Replaces the CLI's default system prompt
+    pub system_prompt: Option<String>,
+    /// Appended to the CLI's default system prompt
+    pub append_system_prompt: Option<String>,
+    /// Tool permission rules, e.g. `Bash(git:*)`, passed as `--allowedTools`
+    pub allowed_tools: Vec<String>,
+    /// Tool permission rules passed as `--disallowedTools`
+    pub disallowed_tools: Vec<String>,
+    /// MCP server ids, passed to the CLI as `CLAUDE_MCP_SERVERS`
+    pub mcp_servers: Vec<String>,
+}
+
+/// Partial update of `AgentSettings`; empty prompt strings clear the prompt
+#[derive(Debug, Default)]
+pub struct AgentSettingsUpdate {
+    pub model: Option<String>,
+    pub thinking_enabled: Option<bool>,
+    pub system_prompt: Option<String>,
+    pub append_system_prompt: Option<String>,
+    pub allowed_tools: Option<Vec<String>>,
+    pub disallowed_tools: Option<Vec<String>>,
+    pub mcp_servers: Option<Vec<String>>,
+}
+
 pub struct AgentProcess {
     pub id: String,
     pub working_dir: String,
-    pub model: String,
-    pub thinking_enabled: bool,
+    pub settings: AgentSettings,
     session_id: Arc<Mutex<Option<String>>>,
     current_child: Arc<Mutex<Option<Child>>>,
     app_handle: AppHandle,
@@ -64,8 +92,7 @@ impl AgentProcess {
         id: String,
         working_dir: String,
         app_handle: AppHandle,
-        model: String,
-        thinking_enabled: bool,
+        settings: AgentSettings,
         initial_session_id: Option<String>,
     ) -> Result<Self, String> {
         // Verify claude CLI exists
@@ -74,8 +101,7 @@ impl AgentProcess {
         Ok(Self {
             id,
             working_dir,
-            model,
-            thinking_enabled,
+            settings,
             session_id: Arc::new(Mutex::new(initial_session_id)),
             current_child: Arc::new(Mutex::new(None)),
             app_handle,
@@ -113,26 +139,52 @@ impl AgentProcess {
         // Use -p (print) mode for non-interactive execution
         // Use --output-format stream-json for streaming responses
         // --verbose is required when using stream-json with -p
-        // --dangerously-skip-permissions allows file modifications without prompts
+        // --dangerously-skip-permissions allows file modifications without prompts, unless
+        // the agent has an allow-list
         let mut args = vec![
             "-p".to_string(),
             prompt,
             "--output-format".to_string(),
             "stream-json".to_string(),
             "--verbose".to_string(),
-            "--dangerously-skip-permissions".to_string(),
         ];
 
+        // Skipping permission prompts skips the allow-list too, so an agent limited to
+        // some tools runs with the others denied; disallowed tools are removed either way
+        if self.settings.allowed_tools.is_empty() {
+            args.push("--dangerously-skip-permissions".to_string());
+        }
+
         // Add model selection
         args.push("--model".to_string());
-        args.push(self.model.clone());
+        args.push(self.settings.model.clone());
 
         // Enable/disable extended thinking via CLI settings
-        if self.thinking_enabled {
+        if self.settings.thinking_enabled {
             args.push("--settings".to_string());
             args.push(r#"{"alwaysThinkingEnabled": true}"#.to_string());
         }
 
+        // Tool permission rules
+        if !self.settings.allowed_tools.is_empty() {
+            args.push("--allowedTools".to_string());
+            args.push(self.settings.allowed_tools.join(","));
+        }
+        if !self.settings.disallowed_tools.is_empty() {
+            args.push("--disallowedTools".to_string());
+            args.push(self.settings.disallowed_tools.join(","));
+        }
+
+        // Persona prompts are passed on every run so they apply to resumed sessions too
+        if let Some(ref prompt) = self.settings.system_prompt {
+            args.push("--system-prompt".to_string());
+            args.push(prompt.clone());
+        }
+        if let Some(ref prompt) = self.settings.append_system_prompt {
+            args.push("--append-system-prompt".to_string());
+            args.push(prompt.clone());
+        }
+
         // Check if we have a session ID for continuation
         let session_id_opt = self.session_id.lock().map_err(|e| e.to_string())?.clone();
         if let Some(ref sid) = session_id_opt {
@@ -150,6 +202,12 @@ impl AgentProcess {
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
 
+        // Claude CLI reads CLAUDE_MCP_SERVERS as a JSON array
+        if !self.settings.mcp_servers.is_empty() {
+            let mcp_config = serde_json::to_string(&self.settings.mcp_servers).unwrap_or_else(|_| "[]".to_string());
+            cmd.env("CLAUDE_MCP_SERVERS", mcp_config);
+        }
+
         // Spawn the claude process
         let mut child = match cmd.spawn()
         {
@@ -311,17 +369,32 @@ impl AgentProcess {
         Ok(())
     }
 
-    pub fn update_settings(&mut self, model: Option<String>, thinking_enabled: Option<bool>) {
-        if let Some(m) = model {
-            self.model = m;
+    pub fn update_settings(&mut self, update: AgentSettingsUpdate) {
+        if let Some(m) = update.model {
+            self.settings.model = m;
+        }
+        if let Some(t) = update.thinking_enabled {
+            self.settings.thinking_enabled = t;
+        }
+        if let Some(p) = update.system_prompt {
+            self.settings.system_prompt = Some(p).filter(|p| !p.is_empty());
+        }
+        if let Some(p) = update.append_system_prompt {
+            self.settings.append_system_prompt = Some(p).filter(|p| !p.is_empty());
+        }
+        if let Some(t) = update.allowed_tools {
+            self.settings.allowed_tools = t;
+        }
+        if let Some(t) = update.disallowed_tools {
+            self.settings.disallowed_tools = t;
         }
-        if let Some(t) = thinking_enabled {
-            self.thinking_enabled = t;
+        if let Some(s) = update.mcp_servers {
+            self.settings.mcp_servers = s;
         }
     }
 
     pub fn get_settings(&self) -> (String, bool) {
-        (self.model.clone(), self.thinking_enabled)
+        (self.settings.model.clone(), self.settings.thinking_enabled)
     }
 }
 
@@ -1,25 +1,56 @@
+use crate::agents::{AgentSettings, AgentSettingsUpdate};
+use crate::commands::templates::find_template;
 use crate::state::AppState;
 use tauri::{AppHandle, State};
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub fn create_agent(
     state: State<AppState>,
     app_handle: AppHandle,
     id: String,
     working_dir: String,
+    template_id: Option<String>,
     model: Option<String>,
     thinking_enabled: Option<bool>,
+    mcp_servers: Option<Vec<String>>,
+    system_prompt: Option<String>,
+    append_system_prompt: Option<String>,
+    allowed_tools: Option<Vec<String>>,
+    disallowed_tools: Option<Vec<String>>,
     session_id: Option<String>,
 ) -> Result<(), String> {
+    let template = template_id
+        .map(|template_id| find_template(&app_handle, &template_id))
+        .transpose()?;
+
+    // Explicit arguments override the template, which overrides the defaults
+    let settings = AgentSettings {
+        model: model
+            .or_else(|| template.as_ref().map(|t| t.model.clone()))
+            .unwrap_or_else(|| "sonnet".to_string()),
+        thinking_enabled: thinking_enabled
+            .or_else(|| template.as_ref().map(|t| t.thinking_enabled))
+            .unwrap_or(false),
+        system_prompt: system_prompt
+            .or_else(|| template.as_ref().and_then(|t| t.system_prompt.clone()))
+            .filter(|p| !p.is_empty()),
+        append_system_prompt: append_system_prompt
+            .or_else(|| template.as_ref().and_then(|t| t.append_system_prompt.clone()))
+            .filter(|p| !p.is_empty()),
+        allowed_tools: allowed_tools
+            .or_else(|| template.as_ref().map(|t| t.allowed_tools.clone()))
+            .unwrap_or_default(),
+        disallowed_tools: disallowed_tools
+            .or_else(|| template.as_ref().map(|t| t.disallowed_tools.clone()))
+            .unwrap_or_default(),
+        mcp_servers: mcp_servers
+            .or_else(|| template.as_ref().map(|t| t.mcp_servers.clone()))
+            .unwrap_or_default(),
+    };
+
     let mut manager = state.agent_manager.lock().map_err(|e| e.to_string())?;
-    manager.create_agent(
-        id,
-        working_dir,
-        app_handle,
-        model.unwrap_or_else(|| "sonnet".to_string()),
-        thinking_enabled.unwrap_or(false),
-        session_id,
-    )
+    manager.create_agent(id, working_dir, app_handle, settings, session_id)
 }
 
 #[tauri::command]
@@ -41,12 +72,29 @@ pub fn list_agents(state: State<AppState>) -> Result<Vec<String>, String> {
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub fn update_agent_settings(
     state: State<AppState>,
     id: String,
     model: Option<String>,
     thinking_enabled: Option<bool>,
+    system_prompt: Option<String>,
+    append_system_prompt: Option<String>,
+    allowed_tools: Option<Vec<String>>,
+    disallowed_tools: Option<Vec<String>>,
+    mcp_servers: Option<Vec<String>>,
 ) -> Result<(), String> {
     let mut manager = state.agent_manager.lock().map_err(|e| e.to_string())?;
-    manager.update_agent_settings(&id, model, thinking_enabled)
+    manager.update_agent_settings(
+        &id,
+        AgentSettingsUpdate {
+            model,
+            thinking_enabled,
+            system_prompt,
+            append_system_prompt,
+            allowed_tools,
+            disallowed_tools,
+            mcp_servers,
+        },
+    )
 }
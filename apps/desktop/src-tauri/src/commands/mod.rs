@@ -1,3 +1,4 @@
 pub mod agent;
 pub mod settings;
+pub mod templates;
 pub mod workspace;
@@ -0,0 +1,121 @@
+use std::fs;
+use std::path::PathBuf;
+use tauri::{AppHandle, Manager};
+
+pub use virtual_agency_templates::{AgentTemplate, TemplateFile};
+
+fn get_templates_path(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data dir: {}", e))?;
+
+    fs::create_dir_all(&app_data_dir)
+        .map_err(|e| format!("Failed to create app data dir: {}", e))?;
+
+    Ok(app_data_dir.join("templates.json"))
+}
+
+fn read_templates(app_handle: &AppHandle) -> Result<Vec<AgentTemplate>, String> {
+    let path = get_templates_path(app_handle)?;
+
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let contents =
+        fs::read_to_string(&path).map_err(|e| format!("Failed to read templates file: {}", e))?;
+
+    serde_json::from_str(&contents).map_err(|e| format!("Failed to parse templates file: {}", e))
+}
+
+fn write_templates(app_handle: &AppHandle, templates: &[AgentTemplate]) -> Result<(), String> {
+    let path = get_templates_path(app_handle)?;
+
+    let json = serde_json::to_string_pretty(templates)
+        .map_err(|e| format!("Failed to serialize templates: {}", e))?;
+
+    // Written beside the file and renamed over it, so a crash can't leave it truncated
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, json).map_err(|e| format!("Failed to write templates file: {}", e))?;
+    fs::rename(&tmp, &path).map_err(|e| format!("Failed to write templates file: {}", e))
+}
+
+/// Look up a template by id, for use when creating agents
+pub fn find_template(app_handle: &AppHandle, id: &str) -> Result<AgentTemplate, String> {
+    read_templates(app_handle)?
+        .into_iter()
+        .find(|t| t.id == id)
+        .ok_or_else(|| format!("Template not found: {}", id))
+}
+
+fn upsert_template(app_handle: &AppHandle, mut template: AgentTemplate) -> Result<AgentTemplate, String> {
+    if template.id.is_empty() {
+        template.id = uuid::Uuid::new_v4().to_string();
+    }
+
+    let mut templates = read_templates(app_handle)?;
+    match templates.iter_mut().find(|t| t.id == template.id) {
+        Some(existing) => *existing = template.clone(),
+        None => templates.push(template.clone()),
+    }
+    write_templates(app_handle, &templates)?;
+
+    Ok(template)
+}
+
+#[tauri::command]
+pub fn list_templates(app_handle: AppHandle) -> Result<Vec<AgentTemplate>, String> {
+    read_templates(&app_handle)
+}
+
+#[tauri::command]
+pub fn save_template(app_handle: AppHandle, template: AgentTemplate) -> Result<AgentTemplate, String> {
+    upsert_template(&app_handle, template)
+}
+
+#[tauri::command]
+pub fn delete_template(app_handle: AppHandle, id: String) -> Result<(), String> {
+    let mut templates = read_templates(&app_handle)?;
+    let len = templates.len();
+    templates.retain(|t| t.id != id);
+
+    if templates.len() == len {
+        return Err(format!("Template not found: {}", id));
+    }
+
+    write_templates(&app_handle, &templates)
+}
+
+#[tauri::command]
+pub fn export_template(app_handle: AppHandle, id: String, path: String) -> Result<(), String> {
+    let file = TemplateFile::new(find_template(&app_handle, &id)?);
+
+    let json = serde_json::to_string_pretty(&file)
+        .map_err(|e| format!("Failed to serialize template: {}", e))?;
+
+    fs::write(&path, json).map_err(|e| format!("Failed to write template file: {}", e))
+}
+
+#[tauri::command]
+pub fn import_template(
+    app_handle: AppHandle,
+    path: String,
+    overwrite: Option<bool>,
+) -> Result<AgentTemplate, String> {
+    let contents =
+        fs::read_to_string(&path).map_err(|e| format!("Failed to read template file: {}", e))?;
+
+    let file: TemplateFile = serde_json::from_str(&contents)
+        .map_err(|e| format!("Failed to parse template file: {}", e))?;
+
+    let template = file.into_template()?;
+    if !overwrite.unwrap_or(false)
+        && !template.id.is_empty()
+        && find_template(&app_handle, &template.id).is_ok()
+    {
+        return Err(format!("Template already exists: {}", template.id));
+    }
+
+    upsert_template(&app_handle, template)
+}
@@ -23,6 +23,11 @@ pub fn run() {
             commands::settings::save_settings,
             commands::settings::load_settings,
             commands::settings::get_settings_path_str,
+            commands::templates::list_templates,
+            commands::templates::save_template,
+            commands::templates::delete_template,
+            commands::templates::export_template,
+            commands::templates::import_template,
             commands::workspace::save_workspace,
             commands::workspace::load_workspace,
             commands::workspace::get_workspace_path_str,
@@ -1,5 +1,5 @@
 import { useState, useCallback, useRef, KeyboardEvent, ClipboardEvent, ChangeEvent, useEffect } from "react";
-import { sendMessage, stopAgent, isTauri, updateAgentSettings, ClaudeModel } from "../../lib/api";
+import { sendMessage, stopAgent, isTauri, updateAgentSettings, ClaudeModel, ApiError } from "../../lib/api";
 import { useChatStore } from "../../stores/chatStore";
 import { useAgentStore } from "../../stores/agentStore";
 import { convertFileSrc } from "@tauri-apps/api/core";
@@ -255,8 +255,12 @@ export function ChatPanel({ agentId }: ChatPanelProps) {
     }
   }, []);
 
+  // Check if agent is currently working (thinking or working status)
+  const isAgentWorking = agent?.status === "thinking" || agent?.status === "working";
+
   const handleSend = useCallback(async () => {
-    if ((!input.trim() && attachedImages.length === 0) || sending) return;
+    // The server rejects a message while the agent's previous run is still going
+    if ((!input.trim() && attachedImages.length === 0) || sending || isAgentWorking) return;
 
     const messageContent = input.trim() || "(image attached)";
     const imagesToSend = [...attachedImages];
@@ -284,13 +288,21 @@ export function ChatPanel({ agentId }: ChatPanelProps) {
       await sendMessage(agentId, messageContent, imagePaths);
       console.log("[ChatPanel] Message sent successfully");
     } catch (err) {
-      console.error("[ChatPanel] Failed to send message:", err);
-      // Reset agent status on error since the backend won't emit status events
-      updateAgent(agentId, { status: "error" });
+      if (err instanceof ApiError && err.status === 409) {
+        // A run started elsewhere is still going; keep the message so it can be sent after
+        console.warn("[ChatPanel] Agent is still running:", err.message);
+        updateAgent(agentId, { status: "working" });
+        setInput(input);
+        setAttachedImages(imagesToSend);
+      } else {
+        console.error("[ChatPanel] Failed to send message:", err);
+        // Reset agent status on error since the backend won't emit status events
+        updateAgent(agentId, { status: "error" });
+      }
     } finally {
       setSending(false);
     }
-  }, [agentId, input, attachedImages, sending, addUserMessage, updateAgent, clearDraft]);
+  }, [agentId, input, attachedImages, sending, isAgentWorking, addUserMessage, updateAgent, clearDraft]);
 
   const handleKeyDown = (e: KeyboardEvent<HTMLTextAreaElement>) => {
     if (e.key === "Enter" && !e.shiftKey) {
@@ -309,9 +321,6 @@ export function ChatPanel({ agentId }: ChatPanelProps) {
     }
   }, [agentId]);
 
-  // Check if agent is currently working (thinking or working status)
-  const isAgentWorking = agent?.status === "thinking" || agent?.status === "working";
-
   const handleImageSelect = useCallback(async () => {
     console.log("[ChatPanel] Opening file dialog, isTauri:", isTauri());
 
@@ -1,9 +1,7 @@
 import { useCallback, useEffect, useRef } from "react";
 import { useTerminalStore } from "../stores/terminalStore";
 import { disposeTerminalInstance } from "../stores/terminalInstanceStore";
-
-const SERVER_URL = import.meta.env.VITE_SERVER_URL || "http://127.0.0.1:3001";
-const WS_URL = import.meta.env.VITE_WS_URL || "ws://127.0.0.1:3001/ws";
+import { authHeaders, SERVER_URL, WS_URL, wsProtocols } from "../lib/api";
 
 export interface TerminalSession {
   id: string;
@@ -17,6 +15,13 @@ let wsListeners = 0;
 let wsGeneration = 0; // Tracks which WebSocket instance is "current"
 const outputCallbacksMap = new Map<string, (data: string) => void>();
 
+// Only output for terminals with a registered callback is sent to this connection
+function sendSubscription(type: "subscribe" | "unsubscribe", terminalIds: string[]) {
+  if (globalWs && globalWs.readyState === WebSocket.OPEN) {
+    globalWs.send(JSON.stringify({ type, terminals: terminalIds }));
+  }
+}
+
 // Stable empty array for when agent has no terminals
 const EMPTY_TERMINALS: TerminalSession[] = [];
 
@@ -40,10 +45,11 @@ export function useTerminals(agentId: string) {
 
       // Increment generation to invalidate any pending messages from old connections
       const currentGeneration = ++wsGeneration;
-      const ws = new WebSocket(WS_URL);
+      const ws = new WebSocket(WS_URL, wsProtocols());
 
       ws.onopen = () => {
         console.log("[useTerminals] WebSocket connected (gen:", currentGeneration, ")");
+        sendSubscription("subscribe", Array.from(outputCallbacksMap.keys()));
       };
 
       ws.onmessage = (event) => {
@@ -103,7 +109,7 @@ export function useTerminals(agentId: string) {
       try {
         const response = await fetch(`${SERVER_URL}/api/terminals`, {
           method: "POST",
-          headers: { "Content-Type": "application/json" },
+          headers: { "Content-Type": "application/json", ...authHeaders() },
           body: JSON.stringify({
             working_dir: workingDir,
             cols: 80,
@@ -140,6 +146,7 @@ export function useTerminals(agentId: string) {
     try {
       await fetch(`${SERVER_URL}/api/terminals/${terminalId}`, {
         method: "DELETE",
+        headers: authHeaders(),
       });
 
       // Dispose the xterm.js instance from the persistent store
@@ -188,11 +195,13 @@ export function useTerminals(agentId: string) {
     (terminalId: string, callback: (data: string) => void) => {
       outputCallbacksRef.current.set(terminalId, callback);
       outputCallbacksMap.set(terminalId, callback);
+      sendSubscription("subscribe", [terminalId]);
 
       // Return unregister function
       return () => {
         outputCallbacksRef.current.delete(terminalId);
         outputCallbacksMap.delete(terminalId);
+        sendSubscription("unsubscribe", [terminalId]);
       };
     },
     []
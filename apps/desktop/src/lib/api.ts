@@ -3,9 +3,80 @@
  * Automatically detects the environment and uses the appropriate backend.
  */
 
-// Server URL for browser mode
-const SERVER_URL = import.meta.env.VITE_SERVER_URL || 'http://127.0.0.1:3001';
-const WS_URL = import.meta.env.VITE_WS_URL || 'ws://127.0.0.1:3001/ws';
+// Server URL for browser mode; defaults to the host the UI was loaded from, so paired
+// devices on the LAN reach the same machine
+const SERVER_HOST = typeof window !== 'undefined' && window.location.hostname ? window.location.hostname : '127.0.0.1';
+// A UI served over HTTPS can only reach a server started with --tls
+const SERVER_SECURE = typeof window !== 'undefined' && window.location.protocol === 'https:';
+export const SERVER_URL = import.meta.env.VITE_SERVER_URL || `${SERVER_SECURE ? 'https' : 'http'}://${SERVER_HOST}:3001`;
+export const WS_URL = import.meta.env.VITE_WS_URL || `${SERVER_SECURE ? 'wss' : 'ws'}://${SERVER_HOST}:3001/ws`;
+
+// Auth token for the web server, read from a `?token=` link once and then remembered.
+// The server stores it in `auth.token` in its data directory.
+const AUTH_TOKEN_KEY = 'va-auth-token';
+
+export function getAuthToken(): string | null {
+  if (typeof window === 'undefined') return null;
+  const params = new URLSearchParams(window.location.search);
+  const fromUrl = params.get('token');
+  if (fromUrl) {
+    localStorage.setItem(AUTH_TOKEN_KEY, fromUrl);
+    // Keep the token out of the address bar and history
+    params.delete('token');
+    const query = params.toString();
+    window.history.replaceState(null, '', `${window.location.pathname}${query ? `?${query}` : ''}${window.location.hash}`);
+    return fromUrl;
+  }
+  return localStorage.getItem(AUTH_TOKEN_KEY) || import.meta.env.VITE_AUTH_TOKEN || null;
+}
+
+// Exchange a `?pair=` code from the server's pairing link for a device token
+export async function completePairing(): Promise<void> {
+  const params = new URLSearchParams(window.location.search);
+  const code = params.get('pair');
+  if (!code) return;
+
+  params.delete('pair');
+  const query = params.toString();
+  window.history.replaceState(null, '', `${window.location.pathname}${query ? `?${query}` : ''}${window.location.hash}`);
+
+  const response = await fetch(`${SERVER_URL}/api/devices/pair`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ code, name: navigator.userAgent }),
+  });
+  if (!response.ok) {
+    throw new Error(await response.text() || `HTTP ${response.status}`);
+  }
+  const { token } = await response.json();
+  localStorage.setItem(AUTH_TOKEN_KEY, token);
+}
+
+export interface DeviceInfo {
+  id: string;
+  name: string;
+  created_at: number;
+  last_seen_at?: number;
+}
+
+export async function listDevices(): Promise<DeviceInfo[]> {
+  return fetchApi<DeviceInfo[]>('/api/devices');
+}
+
+export async function revokeDevice(id: string): Promise<void> {
+  await fetchApi(`/api/devices/${id}`, { method: 'DELETE' });
+}
+
+export function authHeaders(): Record<string, string> {
+  const token = getAuthToken();
+  return token ? { Authorization: `Bearer ${token}` } : {};
+}
+
+// Browsers can't set headers on WebSocket requests, so the token travels as a subprotocol
+export function wsProtocols(): string[] {
+  const token = getAuthToken();
+  return token ? ['virtual-agency', `bearer.${token}`] : [];
+}
 
 // Detect if running in Tauri (v2 uses __TAURI_INTERNALS__)
 export function isTauri(): boolean {
@@ -22,10 +93,15 @@ function connectWebSocket() {
     return;
   }
 
-  ws = new WebSocket(WS_URL);
+  ws = new WebSocket(WS_URL, wsProtocols());
 
   ws.onopen = () => {
     console.log('[API] WebSocket connected');
+    // Terminal bytes go to the terminals' own connection
+    ws?.send(JSON.stringify({
+      type: 'subscribe',
+      events: ['agent-output', 'agent-status', 'conflict-detected'],
+    }));
     if (wsReconnectTimeout) {
       clearTimeout(wsReconnectTimeout);
       wsReconnectTimeout = null;
@@ -62,19 +138,31 @@ async function tauriInvoke<T>(cmd: string, args?: Record<string, unknown>): Prom
   return invoke(cmd, args);
 }
 
+// An error response from the server, with its HTTP status
+export class ApiError extends Error {
+  readonly status: number;
+
+  constructor(message: string, status: number) {
+    super(message);
+    this.name = "ApiError";
+    this.status = status;
+  }
+}
+
 // HTTP fetch helper for browser mode
 async function fetchApi<T>(path: string, options?: RequestInit): Promise<T> {
   const response = await fetch(`${SERVER_URL}${path}`, {
     ...options,
     headers: {
       'Content-Type': 'application/json',
+      ...authHeaders(),
       ...options?.headers,
     },
   });
 
   if (!response.ok) {
     const error = await response.text();
-    throw new Error(error || `HTTP ${response.status}`);
+    throw new ApiError(error || `HTTP ${response.status}`, response.status);
   }
 
   // Handle empty responses
@@ -92,17 +180,27 @@ export interface AgentOptions {
   thinkingEnabled?: boolean;
   mcpServers?: string[]; // Array of MCP server IDs
   sessionId?: string; // Session ID to resume conversation
+  systemPrompt?: string; // Replaces the CLI's default system prompt
+  appendSystemPrompt?: string; // Appended to the CLI's default system prompt
+  personaId?: string; // Persona from the server library (browser mode only)
+  templateId?: string; // Template providing defaults; the options above override it
 }
 
 // Agent APIs
 export async function createAgent(id: string, workingDir: string, options?: AgentOptions): Promise<void> {
-  const model = options?.model || "sonnet";
-  const thinkingEnabled = options?.thinkingEnabled || false;
-  const mcpServers = options?.mcpServers || [];
+  const templateId = options?.templateId;
+  // Leave unset fields to the template when one is used
+  const model = options?.model || (templateId ? undefined : "sonnet");
+  const thinkingEnabled = options?.thinkingEnabled ?? (templateId ? undefined : false);
+  const mcpServers = options?.mcpServers ?? (templateId ? undefined : []);
   const sessionId = options?.sessionId;
+  const systemPrompt = options?.systemPrompt;
+  const appendSystemPrompt = options?.appendSystemPrompt;
 
   if (isTauri()) {
-    return tauriInvoke("create_agent", { id, workingDir, model, thinkingEnabled, mcpServers, sessionId });
+    return tauriInvoke("create_agent", {
+      id, workingDir, templateId, model, thinkingEnabled, mcpServers, sessionId, systemPrompt, appendSystemPrompt,
+    });
   } else {
     // Pass the client-generated ID so the server uses it
     await fetchApi('/api/agents', {
@@ -111,10 +209,14 @@ export async function createAgent(id: string, workingDir: string, options?: Agen
         id,
         name: id,
         working_dir: workingDir,
+        template_id: templateId,
         model,
         thinking_enabled: thinkingEnabled,
         mcp_servers: mcpServers,
         session_id: sessionId,
+        system_prompt: systemPrompt,
+        append_system_prompt: appendSystemPrompt,
+        persona_id: options?.personaId,
       }),
     });
   }
@@ -232,7 +334,40 @@ async function blobUrlToBase64(blobUrl: string): Promise<{ base64: string; mimeT
   });
 }
 
-export async function sendMessage(id: string, message: string, images?: string[]): Promise<void> {
+export interface Attachment {
+  id: string;
+  mime_type: string;
+  size: number;
+  original_name?: string;
+  created_at: number;
+}
+
+// Upload files to the server's attachment store (browser mode only)
+export async function uploadAttachments(files: File[]): Promise<Attachment[]> {
+  const form = new FormData();
+  for (const file of files) {
+    form.append('file', file, file.name);
+  }
+
+  // Not using fetchApi: the browser must set the multipart Content-Type with its boundary
+  const response = await fetch(`${SERVER_URL}/api/attachments`, {
+    method: 'POST',
+    headers: authHeaders(),
+    body: form,
+  });
+  if (!response.ok) {
+    const error = await response.text();
+    throw new Error(error || `HTTP ${response.status}`);
+  }
+  return response.json();
+}
+
+export async function sendMessage(
+  id: string,
+  message: string,
+  images?: string[],
+  attachmentIds?: string[]
+): Promise<void> {
   if (isTauri()) {
     return tauriInvoke("send_message", { id, message, images: images || [] });
   } else {
@@ -256,7 +391,7 @@ export async function sendMessage(id: string, message: string, images?: string[]
 
     await fetchApi(`/api/agents/${id}/messages`, {
       method: 'POST',
-      body: JSON.stringify({ message, images: imageData }),
+      body: JSON.stringify({ message, images: imageData, attachment_ids: attachmentIds || [] }),
     });
   }
 }
@@ -270,24 +405,115 @@ export async function listAgents(): Promise<string[]> {
   }
 }
 
+export interface AgentPromptSettings {
+  systemPrompt?: string; // Empty string clears the prompt
+  appendSystemPrompt?: string; // Empty string clears the prompt
+  personaId?: string; // Browser mode only; empty string clears the persona
+}
+
 export async function updateAgentSettings(
   id: string,
   model?: ClaudeModel,
-  thinkingEnabled?: boolean
+  thinkingEnabled?: boolean,
+  prompts?: AgentPromptSettings
 ): Promise<void> {
+  const systemPrompt = prompts?.systemPrompt;
+  const appendSystemPrompt = prompts?.appendSystemPrompt;
+
   if (isTauri()) {
-    return tauriInvoke("update_agent_settings", { id, model, thinkingEnabled });
+    return tauriInvoke("update_agent_settings", { id, model, thinkingEnabled, systemPrompt, appendSystemPrompt });
   } else {
     await fetchApi(`/api/agents/${id}`, {
       method: 'PATCH',
       body: JSON.stringify({
         model,
         thinking_enabled: thinkingEnabled,
+        system_prompt: systemPrompt,
+        append_system_prompt: appendSystemPrompt,
+        persona_id: prompts?.personaId,
       }),
     });
   }
 }
 
+// Agent templates
+export interface AgentTemplate {
+  id: string; // Empty string lets the backend generate one
+  name: string;
+  description?: string;
+  model?: ClaudeModel;
+  thinking_enabled?: boolean;
+  mcp_servers?: string[];
+  allowed_tools?: string[];
+  disallowed_tools?: string[];
+  system_prompt?: string;
+  append_system_prompt?: string;
+  persona_id?: string; // Browser mode only
+}
+
+export async function listTemplates(): Promise<AgentTemplate[]> {
+  if (isTauri()) {
+    return tauriInvoke("list_templates");
+  } else {
+    return fetchApi<AgentTemplate[]>('/api/templates');
+  }
+}
+
+export async function saveTemplate(template: AgentTemplate): Promise<AgentTemplate> {
+  if (isTauri()) {
+    return tauriInvoke("save_template", { template });
+  } else if (template.id) {
+    return fetchApi<AgentTemplate>(`/api/templates/${template.id}`, {
+      method: 'PUT',
+      body: JSON.stringify(template),
+    });
+  } else {
+    return fetchApi<AgentTemplate>('/api/templates', {
+      method: 'POST',
+      body: JSON.stringify(template),
+    });
+  }
+}
+
+export async function deleteTemplate(id: string): Promise<void> {
+  if (isTauri()) {
+    return tauriInvoke("delete_template", { id });
+  } else {
+    await fetchApi(`/api/templates/${id}`, { method: 'DELETE' });
+  }
+}
+
+// Export a template as a standalone JSON file: written to `path` by the desktop app,
+// downloaded under that file name by the browser
+export async function exportTemplate(id: string, path: string): Promise<void> {
+  if (isTauri()) {
+    return tauriInvoke("export_template", { id, path });
+  } else {
+    const file = await fetchApi<unknown>(`/api/templates/${encodeURIComponent(id)}/export`);
+    const url = URL.createObjectURL(new Blob([JSON.stringify(file, null, 2)], { type: 'application/json' }));
+    const link = document.createElement('a');
+    link.href = url;
+    link.download = path.split(/[\\/]/).pop() || `${id}.template.json`;
+    link.click();
+    URL.revokeObjectURL(url);
+  }
+}
+
+// Import an exported template file: a path for the desktop app, a picked File or its
+// contents in the browser
+export async function importTemplate(file: string | File, overwrite = false): Promise<AgentTemplate> {
+  if (isTauri()) {
+    if (typeof file !== 'string') throw new Error('The desktop app imports templates from a file path');
+    return tauriInvoke("import_template", { path: file, overwrite });
+  } else {
+    const contents = typeof file === 'string' ? file : await file.text();
+    return fetchApi<AgentTemplate>(`/api/templates/import?overwrite=${overwrite}`, {
+      method: 'POST',
+      body: contents,
+    });
+  }
+}
+
 export interface CliStatus {
   installed: boolean;
   path: string | null;
@@ -315,6 +541,9 @@ export interface SavedAgent {
   model?: ClaudeModel;
   thinking_enabled?: boolean;
   session_id?: string; // Claude CLI session ID for conversation continuity
+  system_prompt?: string;
+  append_system_prompt?: string;
+  persona_id?: string;
 }
 
 export interface WorkspaceData {
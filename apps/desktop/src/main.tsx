@@ -2,6 +2,7 @@ import React from "react";
 import ReactDOM from "react-dom/client";
 import App from "./App";
 import { ErrorBoundary } from "./components/ErrorBoundary";
+import { completePairing, isTauri } from "./lib/api";
 import "./styles/globals.css";
 
 // Catch unhandled promise rejections
@@ -9,10 +10,17 @@ window.addEventListener("unhandledrejection", (event) => {
   console.error("Unhandled promise rejection:", event.reason);
 });
 
-ReactDOM.createRoot(document.getElementById("root")!).render(
-  <React.StrictMode>
-    <ErrorBoundary>
-      <App />
-    </ErrorBoundary>
-  </React.StrictMode>
-);
+// Devices opening a pairing link trade the code for a token before the app starts
+const pairing = isTauri()
+  ? Promise.resolve()
+  : completePairing().catch((err) => console.error("[pairing] Failed to pair device:", err));
+
+pairing.then(() => {
+  ReactDOM.createRoot(document.getElementById("root")!).render(
+    <React.StrictMode>
+      <ErrorBoundary>
+        <App />
+      </ErrorBoundary>
+    </React.StrictMode>
+  );
+});
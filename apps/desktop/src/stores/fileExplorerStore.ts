@@ -1,6 +1,5 @@
 import { create } from 'zustand';
-
-const API_BASE = import.meta.env.VITE_SERVER_URL || 'http://127.0.0.1:3001';
+import { authHeaders, SERVER_URL as API_BASE } from '../lib/api';
 
 export interface FileNode {
   name: string;
@@ -76,7 +75,9 @@ export const useFileExplorerStore = create<FileExplorerState>((set, get) => ({
 
     set({ isLoading: true, error: null });
     try {
-      const response = await fetch(`${API_BASE}/api/files/tree/${agentId}`);
+      const response = await fetch(`${API_BASE}/api/files/tree/${agentId}`, {
+        headers: authHeaders(),
+      });
       if (!response.ok) {
         throw new Error('Failed to load file tree');
       }
@@ -109,7 +110,7 @@ export const useFileExplorerStore = create<FileExplorerState>((set, get) => ({
     try {
       const response = await fetch(`${API_BASE}/api/files/read/${agentId}`, {
         method: 'POST',
-        headers: { 'Content-Type': 'application/json' },
+        headers: { 'Content-Type': 'application/json', ...authHeaders() },
         body: JSON.stringify({ path }),
       });
 
@@ -190,7 +191,7 @@ export const useFileExplorerStore = create<FileExplorerState>((set, get) => ({
     try {
       const response = await fetch(`${API_BASE}/api/files/write/${agentId}`, {
         method: 'POST',
-        headers: { 'Content-Type': 'application/json' },
+        headers: { 'Content-Type': 'application/json', ...authHeaders() },
         body: JSON.stringify({
           path: file.path,
           content: file.content,
@@ -28,6 +28,9 @@ function agentToSaved(agent: Agent): SavedAgent {
     model: agent.model,
     thinking_enabled: agent.thinkingEnabled,
     session_id: agent.sessionId,
+    system_prompt: agent.systemPrompt,
+    append_system_prompt: agent.appendSystemPrompt,
+    persona_id: agent.personaId,
   };
 }
 
@@ -55,6 +58,9 @@ function savedToAgent(saved: SavedAgent, index: number): Agent {
     model: saved.model,
     thinkingEnabled: saved.thinking_enabled,
     sessionId: saved.session_id,
+    systemPrompt: saved.system_prompt,
+    appendSystemPrompt: saved.append_system_prompt,
+    personaId: saved.persona_id,
   };
 }
 
@@ -103,6 +109,9 @@ export const useWorkspaceStore = create<WorkspaceState>((set) => ({
               model: agent.model,
               thinkingEnabled: agent.thinkingEnabled,
               sessionId: agent.sessionId, // Pass session ID to resume conversation
+              systemPrompt: agent.systemPrompt,
+              appendSystemPrompt: agent.appendSystemPrompt,
+              personaId: agent.personaId,
             });
             agentStore.addAgent(agent);
           } catch (err) {
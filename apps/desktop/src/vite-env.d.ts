@@ -3,6 +3,7 @@
 interface ImportMetaEnv {
   readonly VITE_SERVER_URL?: string;
   readonly VITE_WS_URL?: string;
+  readonly VITE_AUTH_TOKEN?: string;
 }
 
 interface ImportMeta {
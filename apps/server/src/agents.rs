@@ -1,13 +1,17 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, oneshot};
 
+use crate::conflicts::{self, ConflictTracker, OverlappingAgent};
+use crate::personas::PersonaStore;
+use crate::secrets::{self, SecretStore};
 use crate::BroadcastMessage;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +34,39 @@ pub struct AgentStatusChange {
     pub status: AgentStatus,
 }
 
+/// How a run ended, reported once its output has been read to the end
+#[derive(Debug, Clone, Default)]
+pub struct RunOutcome {
+    /// None when the run was stopped or killed
+    pub exit_code: Option<i32>,
+    /// The CLI's final `result` message
+    pub result: Option<serde_json::Value>,
+    /// Files written by tool calls during the run, in first-write order
+    pub changed_files: Vec<String>,
+}
+
+/// Why a run didn't start
+#[derive(Debug)]
+pub enum SendError {
+    /// The agent is still busy with an earlier run, which a new one would share a session with
+    Busy,
+    NotFound(String),
+    /// A secret the agent's settings reference doesn't exist
+    MissingSecret(String),
+    Failed(String),
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Busy => f.write_str("Agent is already running; wait for the run to end or stop it"),
+            SendError::NotFound(id) => write!(f, "Agent not found: {}", id),
+            SendError::MissingSecret(name) => write!(f, "Secret not found: {}", name),
+            SendError::Failed(e) => f.write_str(e),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum AgentStatus {
@@ -40,7 +77,15 @@ pub enum AgentStatus {
     Exited,
 }
 
-fn find_claude_cli() -> Result<PathBuf, String> {
+fn find_claude_cli(configured: Option<&Path>) -> Result<PathBuf, String> {
+    if let Some(path) = configured {
+        return if path.exists() {
+            Ok(path.to_path_buf())
+        } else {
+            Err(format!("Configured Claude CLI not found: {}", path.display()))
+        };
+    }
+
     let home = env::var("HOME").unwrap_or_default();
 
     let candidates = vec![
@@ -74,63 +119,176 @@ fn find_claude_cli() -> Result<PathBuf, String> {
     Err("Claude CLI not found. Install with: npm install -g @anthropic-ai/claude-code".to_string())
 }
 
+/// Agent settings that can be changed after creation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentSettings {
+    pub model: String,
+    pub thinking_enabled: bool,
+    pub mcp_servers: Vec<String>,
+    /// Tool permission rules, e.g. `Bash(git:*)`, passed as `--allowedTools`
+    #[serde(default)]
+    pub allowed_tools: Vec<String>,
+    /// Tool permission rules passed as `--disallowedTools`
+    #[serde(default)]
+    pub disallowed_tools: Vec<String>,
+    /// Replaces the CLI's default system prompt
+    pub system_prompt: Option<String>,
+    /// Appended to the CLI's default system prompt
+    pub append_system_prompt: Option<String>,
+    /// Persona from the server library whose prompts this agent uses
+    pub persona_id: Option<String>,
+    /// Plain environment variables set on every run
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Environment variables filled from the secret store, as variable name -> secret name
+    #[serde(default)]
+    pub secret_env: BTreeMap<String, String>,
+}
+
+/// Partial update of `AgentSettings`; empty prompt strings clear the prompt
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AgentSettingsUpdate {
+    pub model: Option<String>,
+    pub thinking_enabled: Option<bool>,
+    pub mcp_servers: Option<Vec<String>>,
+    pub allowed_tools: Option<Vec<String>>,
+    pub disallowed_tools: Option<Vec<String>>,
+    pub system_prompt: Option<String>,
+    pub append_system_prompt: Option<String>,
+    pub persona_id: Option<String>,
+    pub env: Option<BTreeMap<String, String>>,
+    pub secret_env: Option<BTreeMap<String, String>>,
+}
+
+/// Point-in-time view of an agent for listings
+#[derive(Debug, Clone)]
+pub struct AgentSnapshot {
+    pub id: String,
+    pub name: String,
+    pub working_dir: String,
+    pub settings: AgentSettings,
+    /// CLI session the agent's conversation lives in, once it has run
+    pub session_id: Option<String>,
+    /// A run is in progress
+    pub running: bool,
+    /// Summed `total_cost_usd` of the runs since the agent was created
+    pub total_cost_usd: f64,
+}
+
 pub struct AgentProcess {
     pub id: String,
     pub name: String,
     pub working_dir: String,
-    pub model: String,
-    pub thinking_enabled: bool,
-    pub mcp_servers: Vec<String>,
+    pub settings: AgentSettings,
     session_id: Arc<Mutex<Option<String>>>,
+    total_cost_usd: Arc<Mutex<f64>>,
+    /// Fork the resumed session on the next run instead of appending to it
+    fork_session: Arc<AtomicBool>,
     current_child: Arc<Mutex<Option<Child>>>,
     broadcast_tx: broadcast::Sender<BroadcastMessage>,
+    conflict_tracker: ConflictTracker,
+    persona_store: PersonaStore,
+    secret_store: SecretStore,
+    /// Configured CLI executable; searched for when unset
+    claude_path: Option<PathBuf>,
 }
 
 impl AgentProcess {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: String,
         name: String,
         working_dir: String,
-        model: String,
-        thinking_enabled: bool,
-        mcp_servers: Vec<String>,
+        settings: AgentSettings,
         broadcast_tx: broadcast::Sender<BroadcastMessage>,
         initial_session_id: Option<String>,
+        fork_session: bool,
+        conflict_tracker: ConflictTracker,
+        persona_store: PersonaStore,
+        secret_store: SecretStore,
+        claude_path: Option<PathBuf>,
     ) -> Result<Self, String> {
-        find_claude_cli()?;
+        find_claude_cli(claude_path.as_deref())?;
 
         Ok(Self {
             id,
             name,
             working_dir,
-            model,
-            thinking_enabled,
-            mcp_servers,
+            settings,
+            fork_session: Arc::new(AtomicBool::new(fork_session && initial_session_id.is_some())),
             session_id: Arc::new(Mutex::new(initial_session_id)),
+            total_cost_usd: Arc::new(Mutex::new(0.0)),
             current_child: Arc::new(Mutex::new(None)),
             broadcast_tx,
+            conflict_tracker,
+            persona_store,
+            secret_store,
+            claude_path,
         })
     }
 
-    pub fn send_message(&self, message: &str, images: &[String]) -> Result<(), String> {
-        let claude_path = find_claude_cli()?;
+    /// System prompts for the next run: the agent's own prompt overrides its persona's,
+    /// and appended prompts from both are combined
+    fn effective_prompts(&self) -> (Option<String>, Option<String>) {
+        let persona = self
+            .settings
+            .persona_id
+            .as_deref()
+            .and_then(|id| self.persona_store.get(id));
+
+        let system_prompt = self
+            .settings
+            .system_prompt
+            .clone()
+            .or_else(|| persona.as_ref().and_then(|p| p.system_prompt.clone()));
+
+        let append_parts: Vec<String> = [
+            persona.and_then(|p| p.append_system_prompt),
+            self.settings.append_system_prompt.clone(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let append_system_prompt = (!append_parts.is_empty()).then(|| append_parts.join("\n\n"));
+
+        (system_prompt, append_system_prompt)
+    }
+
+    /// Start a run; the returned receiver resolves when it ends. One run at a time: the
+    /// child slot stays locked until the new run is in it.
+    pub fn send_message(&self, message: &str, files: &[String]) -> Result<oneshot::Receiver<RunOutcome>, SendError> {
+        let mut current_child = self.current_child.lock().map_err(|e| SendError::Failed(e.to_string()))?;
+        if current_child.is_some() {
+            return Err(SendError::Busy);
+        }
+
+        let claude_path = find_claude_cli(self.claude_path.as_deref()).map_err(SendError::Failed)?;
 
-        if !images.is_empty() {
-            tracing::debug!("[AgentProcess] Received {} image(s): {:?}", images.len(), images);
+        if !files.is_empty() {
+            tracing::debug!("[AgentProcess] Received {} file(s): {:?}", files.len(), files);
         }
 
+        // Resolve secrets up front so a missing one fails the run before anything starts
+        if let Some(missing) = self.settings.secret_env.values().find(|name| !self.secret_store.contains(name)) {
+            return Err(SendError::MissingSecret(missing.clone()));
+        }
+        let env = self
+            .secret_store
+            .resolve_env(&self.settings.env, &self.settings.secret_env)
+            .map_err(SendError::Failed)?;
+
         // Emit thinking status
         let _ = self.broadcast_tx.send(BroadcastMessage::AgentStatus(AgentStatusChange {
             agent_id: self.id.clone(),
             status: AgentStatus::Thinking,
         }));
 
-        // Build the prompt with embedded image paths
-        let prompt = if images.is_empty() {
+        // Build the prompt with embedded file paths; the CLI reads images and PDFs from them
+        let prompt = if files.is_empty() {
             message.to_string()
         } else {
-            let image_paths = images.join(" ");
-            format!("Images attached: {}\n\n{}", image_paths, message)
+            let file_paths = files.join(" ");
+            format!("Files attached: {}\n\n{}", file_paths, message)
         };
 
         let mut args = vec![
@@ -139,18 +297,49 @@ impl AgentProcess {
             "--output-format".to_string(),
             "stream-json".to_string(),
             "--verbose".to_string(),
-            "--dangerously-skip-permissions".to_string(),
         ];
 
+        // Skipping permission prompts skips the allow-list too, so an agent limited to
+        // some tools runs with the others denied; disallowed tools are removed either way
+        if self.settings.allowed_tools.is_empty() {
+            args.push("--dangerously-skip-permissions".to_string());
+        }
+
         // Add model selection
         args.push("--model".to_string());
-        args.push(self.model.clone());
+        args.push(self.settings.model.clone());
+
+        if !self.settings.allowed_tools.is_empty() {
+            args.push("--allowedTools".to_string());
+            args.push(self.settings.allowed_tools.join(","));
+        }
+        if !self.settings.disallowed_tools.is_empty() {
+            args.push("--disallowedTools".to_string());
+            args.push(self.settings.disallowed_tools.join(","));
+        }
+
+        let (system_prompt, append_system_prompt) = self.effective_prompts();
+        if let Some(prompt) = system_prompt {
+            args.push("--system-prompt".to_string());
+            args.push(prompt);
+        }
+        if let Some(prompt) = append_system_prompt {
+            args.push("--append-system-prompt".to_string());
+            args.push(prompt);
+        }
 
         // Check for session continuation
-        let session_id_opt = self.session_id.lock().map_err(|e| e.to_string())?.clone();
+        let session_id_opt = self
+            .session_id
+            .lock()
+            .map_err(|e| SendError::Failed(e.to_string()))?
+            .clone();
         if let Some(ref sid) = session_id_opt {
             args.push("--resume".to_string());
             args.push(sid.clone());
+            if self.fork_session.load(Ordering::SeqCst) {
+                args.push("--fork-session".to_string());
+            }
         }
 
         tracing::debug!("[AgentProcess] Executing: {} {:?}", claude_path.display(), args);
@@ -160,20 +349,21 @@ impl AgentProcess {
             .args(&args)
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
+            .stderr(Stdio::piped())
+            .envs(&env);
 
         // Enable extended thinking via environment variable
-        if self.thinking_enabled {
+        if self.settings.thinking_enabled {
             cmd.env("MAX_THINKING_TOKENS", "31999");
         }
 
         // Configure MCP servers via environment variable
         // Claude CLI reads CLAUDE_MCP_SERVERS as a JSON array
-        if !self.mcp_servers.is_empty() {
-            let mcp_config = serde_json::to_string(&self.mcp_servers)
+        if !self.settings.mcp_servers.is_empty() {
+            let mcp_config = serde_json::to_string(&self.settings.mcp_servers)
                 .unwrap_or_else(|_| "[]".to_string());
             cmd.env("CLAUDE_MCP_SERVERS", mcp_config);
-            tracing::info!("[AgentProcess] Configured MCP servers: {:?}", self.mcp_servers);
+            tracing::info!("[AgentProcess] Configured MCP servers: {:?}", self.settings.mcp_servers);
         }
 
         let mut child = match cmd.spawn()
@@ -184,24 +374,33 @@ impl AgentProcess {
                     agent_id: self.id.clone(),
                     status: AgentStatus::Error,
                 }));
-                return Err(format!("Failed to spawn claude process: {}", e));
+                return Err(SendError::Failed(format!("Failed to spawn claude process: {}", e)));
             }
         };
 
         let stdout = child.stdout.take();
         let stderr = child.stderr.take();
+        let pid = child.id();
+        let (done_tx, done_rx) = oneshot::channel();
 
-        if let Ok(mut guard) = self.current_child.lock() {
-            *guard = Some(child);
-        }
+        *current_child = Some(child);
+        drop(current_child);
+        self.conflict_tracker.run_started(&self.id);
 
         // Spawn stdout reader thread
         if let Some(stdout_handle) = stdout {
             let agent_id = self.id.clone();
+            let agent_name = self.name.clone();
+            let working_dir = self.working_dir.clone();
             let tx = self.broadcast_tx.clone();
             let session_id_arc = Arc::clone(&self.session_id);
+            let total_cost_usd = Arc::clone(&self.total_cost_usd);
+            let fork_session = Arc::clone(&self.fork_session);
+            let conflict_tracker = self.conflict_tracker.clone();
+            let current_child = Arc::clone(&self.current_child);
 
             thread::spawn(move || {
+                let mut outcome = RunOutcome::default();
                 let reader = BufReader::new(stdout_handle);
                 for line in reader.lines() {
                     match line {
@@ -210,18 +409,32 @@ impl AgentProcess {
                             if let Ok(json) = serde_json::from_str::<serde_json::Value>(&data) {
                                 if let Some(sid) = json.get("session_id").and_then(|v| v.as_str()) {
                                     if let Ok(mut guard) = session_id_arc.lock() {
-                                        if guard.is_none() {
+                                        // A forked run reports its new session id; continue from the fork
+                                        if guard.is_none() || fork_session.swap(false, Ordering::SeqCst) {
                                             *guard = Some(sid.to_string());
                                         }
                                     }
                                 }
 
+                                conflict_tracker.observe_output(&agent_id, &agent_name, &working_dir, &json);
+                                for path in conflicts::modified_files(&json) {
+                                    if !outcome.changed_files.iter().any(|p| p == path) {
+                                        outcome.changed_files.push(path.to_string());
+                                    }
+                                }
+
                                 if let Some(msg_type) = json.get("type").and_then(|v| v.as_str()) {
                                     let status = match msg_type {
                                         "assistant" | "content_block_delta" | "content_block_start" => {
                                             Some(AgentStatus::Working)
                                         }
                                         "result" => {
+                                            outcome.result = Some(json.clone());
+                                            if let Some(cost) = json.get("total_cost_usd").and_then(|v| v.as_f64()) {
+                                                if let Ok(mut total) = total_cost_usd.lock() {
+                                                    *total += cost;
+                                                }
+                                            }
                                             if let Some(sid) = json.get("session_id").and_then(|v| v.as_str()) {
                                                 if let Ok(mut guard) = session_id_arc.lock() {
                                                     *guard = Some(sid.to_string());
@@ -255,10 +468,27 @@ impl AgentProcess {
                     }
                 }
 
+                // Reap the process unless stop() or kill() already took it
+                let child = current_child
+                    .lock()
+                    .ok()
+                    .and_then(|mut guard| match guard.as_ref() {
+                        Some(child) if child.id() == pid => guard.take(),
+                        _ => None,
+                    });
+                if let Some(mut child) = child {
+                    match child.wait() {
+                        Ok(status) => outcome.exit_code = status.code(),
+                        Err(e) => tracing::warn!("[AgentProcess] Failed to wait for run of {}: {}", agent_id, e),
+                    }
+                }
+                conflict_tracker.run_finished(&agent_id);
+
                 let _ = tx.send(BroadcastMessage::AgentStatus(AgentStatusChange {
                     agent_id: agent_id.clone(),
                     status: AgentStatus::Idle,
                 }));
+                let _ = done_tx.send(outcome);
             });
         }
 
@@ -285,7 +515,7 @@ impl AgentProcess {
             });
         }
 
-        Ok(())
+        Ok(done_rx)
     }
 
     /// Stop the current operation by killing the child process, but keep the agent alive
@@ -293,6 +523,8 @@ impl AgentProcess {
         if let Ok(mut guard) = self.current_child.lock() {
             if let Some(ref mut child) = *guard {
                 child.kill().map_err(|e| format!("Failed to stop process: {}", e))?;
+                // Reaped here, since the run's reader only reaps children still in the slot
+                let _ = child.wait();
                 *guard = None;
                 // Emit idle status after stopping
                 let _ = self.broadcast_tx.send(BroadcastMessage::AgentStatus(AgentStatusChange {
@@ -314,20 +546,41 @@ impl AgentProcess {
         Ok(())
     }
 
-    pub fn update_settings(&mut self, model: Option<String>, thinking_enabled: Option<bool>, mcp_servers: Option<Vec<String>>) {
-        if let Some(m) = model {
-            self.model = m;
+    pub fn update_settings(&mut self, update: AgentSettingsUpdate) {
+        if let Some(m) = update.model {
+            self.settings.model = m;
+        }
+        if let Some(t) = update.thinking_enabled {
+            self.settings.thinking_enabled = t;
+        }
+        if let Some(s) = update.mcp_servers {
+            self.settings.mcp_servers = s;
+        }
+        if let Some(t) = update.allowed_tools {
+            self.settings.allowed_tools = t;
+        }
+        if let Some(t) = update.disallowed_tools {
+            self.settings.disallowed_tools = t;
         }
-        if let Some(t) = thinking_enabled {
-            self.thinking_enabled = t;
+        if let Some(p) = update.system_prompt {
+            self.settings.system_prompt = Some(p).filter(|p| !p.is_empty());
         }
-        if let Some(s) = mcp_servers {
-            self.mcp_servers = s;
+        if let Some(p) = update.append_system_prompt {
+            self.settings.append_system_prompt = Some(p).filter(|p| !p.is_empty());
+        }
+        if let Some(p) = update.persona_id {
+            self.settings.persona_id = Some(p).filter(|p| !p.is_empty());
+        }
+        if let Some(e) = update.env {
+            self.settings.env = e;
+        }
+        if let Some(e) = update.secret_env {
+            self.settings.secret_env = e;
         }
     }
 
-    pub fn get_settings(&self) -> (String, bool, Vec<String>) {
-        (self.model.clone(), self.thinking_enabled, self.mcp_servers.clone())
+    pub fn get_settings(&self) -> AgentSettings {
+        self.settings.clone()
     }
 }
 
@@ -340,42 +593,97 @@ impl Drop for AgentProcess {
 pub struct AgentManager {
     agents: HashMap<String, AgentProcess>,
     broadcast_tx: broadcast::Sender<BroadcastMessage>,
+    conflict_tracker: ConflictTracker,
+    persona_store: PersonaStore,
+    secret_store: SecretStore,
+    claude_path: Option<PathBuf>,
 }
 
 impl AgentManager {
-    pub fn new(broadcast_tx: broadcast::Sender<BroadcastMessage>) -> Self {
+    pub fn new(
+        broadcast_tx: broadcast::Sender<BroadcastMessage>,
+        persona_store: PersonaStore,
+        secret_store: SecretStore,
+        claude_path: Option<PathBuf>,
+    ) -> Self {
         Self {
             agents: HashMap::new(),
+            conflict_tracker: ConflictTracker::new(broadcast_tx.clone()),
             broadcast_tx,
+            persona_store,
+            secret_store,
+            claude_path,
         }
     }
 
+    fn check_secret_env(&self, env: &BTreeMap<String, String>, secret_env: &BTreeMap<String, String>) -> Result<(), String> {
+        secrets::validate_env(env, secret_env)?;
+        match secret_env.values().find(|secret| !self.secret_store.contains(secret)) {
+            Some(missing) => Err(format!("Secret not found: {}", missing)),
+            None => Ok(()),
+        }
+    }
+
+    /// Find existing agents whose working directory is the same as, inside, or contains `working_dir`
+    pub fn overlapping_agents(&self, working_dir: &str, exclude_id: Option<&str>) -> Vec<OverlappingAgent> {
+        self.agents
+            .values()
+            .filter(|agent| Some(agent.id.as_str()) != exclude_id)
+            .filter(|agent| conflicts::dirs_overlap(&agent.working_dir, working_dir))
+            .map(|agent| OverlappingAgent {
+                agent_id: agent.id.clone(),
+                agent_name: agent.name.clone(),
+                working_dir: agent.working_dir.clone(),
+            })
+            .collect()
+    }
+
     pub fn create_agent(
         &mut self,
         id: Option<&str>,
         name: &str,
         working_dir: &str,
-        model: &str,
-        thinking_enabled: bool,
-        mcp_servers: Vec<String>,
+        settings: AgentSettings,
         session_id: Option<String>,
+        fork_session: bool,
     ) -> Result<String, String> {
         // Use provided ID or generate a new one
         let id = id.map(|s| s.to_string()).unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
 
         if session_id.is_some() {
-            tracing::info!("[AgentManager] Creating agent {} with existing session ID for conversation resumption", id);
+            if fork_session {
+                tracing::info!("[AgentManager] Creating agent {} forking an existing session", id);
+            } else {
+                tracing::info!("[AgentManager] Creating agent {} with existing session ID for conversation resumption", id);
+            }
+        }
+
+        for other in self.overlapping_agents(working_dir, Some(&id)) {
+            tracing::warn!(
+                "[AgentManager] Agent {} working dir {} overlaps agent {} ({}) in {}",
+                id, working_dir, other.agent_name, other.agent_id, other.working_dir
+            );
         }
 
+        if let Some(ref persona_id) = settings.persona_id {
+            if self.persona_store.get(persona_id).is_none() {
+                return Err(format!("Persona not found: {}", persona_id));
+            }
+        }
+        self.check_secret_env(&settings.env, &settings.secret_env)?;
+
         let agent = AgentProcess::new(
             id.clone(),
             name.to_string(),
             working_dir.to_string(),
-            model.to_string(),
-            thinking_enabled,
-            mcp_servers,
+            settings,
             self.broadcast_tx.clone(),
             session_id,
+            fork_session,
+            self.conflict_tracker.clone(),
+            self.persona_store.clone(),
+            self.secret_store.clone(),
+            self.claude_path.clone(),
         )?;
         self.agents.insert(id.clone(), agent);
         Ok(id)
@@ -383,17 +691,18 @@ impl AgentManager {
 
     pub fn kill_agent(&mut self, id: &str) -> Result<(), String> {
         if let Some(mut agent) = self.agents.remove(id) {
+            self.conflict_tracker.forget_agent(id);
             agent.kill()
         } else {
             Err(format!("Agent not found: {}", id))
         }
     }
 
-    pub fn send_message(&self, id: &str, message: &str, images: &[String]) -> Result<(), String> {
+    pub fn send_message(&self, id: &str, message: &str, files: &[String]) -> Result<oneshot::Receiver<RunOutcome>, SendError> {
         if let Some(agent) = self.agents.get(id) {
-            agent.send_message(message, images)
+            agent.send_message(message, files)
         } else {
-            Err(format!("Agent not found: {}", id))
+            Err(SendError::NotFound(id.to_string()))
         }
     }
 
@@ -405,25 +714,36 @@ impl AgentManager {
         }
     }
 
-    pub fn list_agents(&self) -> Vec<(String, String, String, String, bool, Vec<String>)> {
+    pub fn list_agents(&self) -> Vec<AgentSnapshot> {
         self.agents
             .iter()
-            .map(|(id, agent)| {
-                let (model, thinking_enabled, mcp_servers) = agent.get_settings();
-                (id.clone(), agent.name.clone(), agent.working_dir.clone(), model, thinking_enabled, mcp_servers)
+            .map(|(id, agent)| AgentSnapshot {
+                id: id.clone(),
+                name: agent.name.clone(),
+                working_dir: agent.working_dir.clone(),
+                settings: agent.get_settings(),
+                session_id: agent.session_id.lock().ok().and_then(|sid| sid.clone()),
+                running: agent.current_child.lock().is_ok_and(|child| child.is_some()),
+                total_cost_usd: agent.total_cost_usd.lock().map(|total| *total).unwrap_or_default(),
             })
             .collect()
     }
 
-    pub fn update_agent_settings(
-        &mut self,
-        id: &str,
-        model: Option<String>,
-        thinking_enabled: Option<bool>,
-        mcp_servers: Option<Vec<String>>,
-    ) -> Result<(), String> {
+    pub fn update_agent_settings(&mut self, id: &str, update: AgentSettingsUpdate) -> Result<(), String> {
+        if let Some(persona_id) = update.persona_id.as_deref().filter(|p| !p.is_empty()) {
+            if self.persona_store.get(persona_id).is_none() {
+                return Err(format!("Persona not found: {}", persona_id));
+            }
+        }
+        if update.env.is_some() || update.secret_env.is_some() {
+            self.check_secret_env(
+                update.env.as_ref().unwrap_or(&BTreeMap::new()),
+                update.secret_env.as_ref().unwrap_or(&BTreeMap::new()),
+            )?;
+        }
+
         if let Some(agent) = self.agents.get_mut(id) {
-            agent.update_settings(model, thinking_enabled, mcp_servers);
+            agent.update_settings(update);
             Ok(())
         } else {
             Err(format!("Agent not found: {}", id))
@@ -0,0 +1,405 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Largest single attachment accepted
+pub const MAX_ATTACHMENT_BYTES: usize = 25 * 1024 * 1024;
+
+/// Text attachments up to this size are inlined into the prompt instead of referenced by path
+pub const MAX_INLINE_TEXT_BYTES: u64 = 32 * 1024;
+
+/// How much of an upload is buffered for MIME sniffing
+const SNIFF_BYTES: usize = 8 * 1024;
+
+/// Unreferenced attachments younger than this survive garbage collection, so uploads
+/// aren't collected before the message referencing them is sent
+pub const GC_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// A stored attachment, identified by the SHA-256 of its content
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: String,
+    /// Sniffed from the content, not taken from the client
+    pub mime_type: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_name: Option<String>,
+    pub created_at: u64,
+    /// Number of messages referencing this attachment, per agent
+    #[serde(default)]
+    pub refs: BTreeMap<String, u32>,
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+impl Attachment {
+    pub fn ref_count(&self) -> u32 {
+        self.refs.values().sum()
+    }
+}
+
+/// Detect a MIME type from the content's magic bytes, falling back to text or octet-stream
+pub fn sniff_mime(data: &[u8]) -> String {
+    if let Some(kind) = infer::get(data) {
+        return kind.mime_type().to_string();
+    }
+    // A sniffing buffer may end mid-character, which still counts as text
+    let is_utf8 = match std::str::from_utf8(data) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    if !data.contains(&0) && is_utf8 {
+        return "text/plain".to_string();
+    }
+    "application/octet-stream".to_string()
+}
+
+/// Marks temporary files in the attachment dir; GC removes stale ones left by a crash
+const TMP_MARKER: &str = ".tmp-";
+
+/// A temporary file that is removed when dropped unless it was kept, so uploads
+/// abandoned mid-stream, e.g. by a client disconnecting, don't leave files behind
+struct TempFile {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TempFile {
+    fn new(path: PathBuf) -> Self {
+        Self { path, keep: false }
+    }
+
+    /// Keep the file, typically after renaming it to its final path
+    fn keep(mut self) {
+        self.keep = true;
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// An upload being streamed to a temporary file in the attachment dir. Dropping it
+/// removes the file.
+pub struct PendingUpload {
+    tmp: TempFile,
+    file: tokio::fs::File,
+    hasher: Sha256,
+    head: Vec<u8>,
+    size: usize,
+}
+
+impl PendingUpload {
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<(), String> {
+        use tokio::io::AsyncWriteExt;
+
+        if self.size + chunk.len() > MAX_ATTACHMENT_BYTES {
+            return Err(format!("Attachment exceeds the {} byte limit", MAX_ATTACHMENT_BYTES));
+        }
+
+        self.file
+            .write_all(chunk)
+            .await
+            .map_err(|e| format!("Failed to write attachment: {}", e))?;
+        self.hasher.update(chunk);
+        if self.head.len() < SNIFF_BYTES {
+            let take = (SNIFF_BYTES - self.head.len()).min(chunk.len());
+            self.head.extend_from_slice(&chunk[..take]);
+        }
+        self.size += chunk.len();
+        Ok(())
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Throw the upload away, e.g. after a client error
+    pub async fn discard(self) {
+        let PendingUpload { tmp, file, .. } = self;
+        drop(file);
+        drop(tmp);
+    }
+}
+
+fn extension_for(mime_type: &str) -> &'static str {
+    match mime_type {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/bmp" => "bmp",
+        "image/tiff" => "tiff",
+        "image/heif" => "heif",
+        "application/pdf" => "pdf",
+        "text/plain" => "txt",
+        _ => "bin",
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Content-addressed attachment files under `<data dir>/attachments`, with a JSON index
+/// tracking which agents' transcripts reference each one
+#[derive(Clone)]
+pub struct AttachmentStore {
+    dir: PathBuf,
+    index: Arc<Mutex<HashMap<String, Attachment>>>,
+}
+
+impl AttachmentStore {
+    /// An unreadable index is an error rather than an empty one, under which GC would
+    /// delete every attachment
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let dir = data_dir.join("attachments");
+        let index_path = dir.join("index.json");
+
+        let mut index: HashMap<String, Attachment> = if index_path.exists() {
+            fs::read_to_string(&index_path)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string()))
+                .map_err(|e| format!("Failed to load {}: {}; fix or move the file", index_path.display(), e))?
+        } else {
+            HashMap::new()
+        };
+
+        // Paths aren't persisted; drop entries whose file has gone missing
+        index.retain(|id, attachment| {
+            attachment.path = dir.join(format!("{}.{}", id, extension_for(&attachment.mime_type)));
+            attachment.path.is_file()
+        });
+
+        Ok(Self {
+            dir,
+            index: Arc::new(Mutex::new(index)),
+        })
+    }
+
+    /// Store content, deduplicating by hash. `mime_type` should come from `sniff_mime`.
+    pub fn store(&self, data: &[u8], mime_type: &str, original_name: Option<&str>) -> Result<Attachment, String> {
+        if data.len() > MAX_ATTACHMENT_BYTES {
+            return Err(format!(
+                "Attachment is {} bytes, the limit is {} bytes",
+                data.len(),
+                MAX_ATTACHMENT_BYTES
+            ));
+        }
+
+        let id = format!("{:x}", Sha256::digest(data));
+        let path = self.dir.join(format!("{}.{}", id, extension_for(mime_type)));
+
+        let mut index = self.index.lock().map_err(|e| e.to_string())?;
+        if let Some(existing) = self.reuse(&mut index, &id)? {
+            return Ok(existing);
+        }
+
+        fs::create_dir_all(&self.dir).map_err(|e| format!("Failed to create attachment dir: {}", e))?;
+        // Write under a temporary name so a crash never leaves a truncated file at the final path
+        let tmp = TempFile::new(self.dir.join(format!("{}{}{}", id, TMP_MARKER, uuid::Uuid::new_v4())));
+        fs::write(&tmp.path, data).map_err(|e| format!("Failed to write attachment: {}", e))?;
+        fs::rename(&tmp.path, &path).map_err(|e| format!("Failed to write attachment: {}", e))?;
+        tmp.keep();
+
+        let attachment = Attachment {
+            id: id.clone(),
+            mime_type: mime_type.to_string(),
+            size: data.len() as u64,
+            original_name: original_name.map(|n| n.to_string()),
+            created_at: unix_secs(),
+            refs: BTreeMap::new(),
+            path,
+        };
+        index.insert(id, attachment.clone());
+        self.save(&index)?;
+
+        Ok(attachment)
+    }
+
+    /// Start streaming an upload; finish it with `finish_upload`
+    pub async fn begin_upload(&self) -> Result<PendingUpload, String> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| format!("Failed to create attachment dir: {}", e))?;
+        let tmp = TempFile::new(self.dir.join(format!("upload{}{}", TMP_MARKER, uuid::Uuid::new_v4())));
+        let file = tokio::fs::File::create(&tmp.path)
+            .await
+            .map_err(|e| format!("Failed to create attachment: {}", e))?;
+
+        Ok(PendingUpload {
+            tmp,
+            file,
+            hasher: Sha256::new(),
+            head: Vec::new(),
+            size: 0,
+        })
+    }
+
+    /// Move a completed upload to its content-addressed path, deduplicating by hash
+    pub async fn finish_upload(&self, upload: PendingUpload, original_name: Option<&str>) -> Result<Attachment, String> {
+        use tokio::io::AsyncWriteExt;
+
+        let PendingUpload { tmp, mut file, hasher, head, size } = upload;
+        file.flush().await.map_err(|e| format!("Failed to write attachment: {}", e))?;
+        drop(file);
+
+        let id = format!("{:x}", hasher.finalize());
+        let mime_type = sniff_mime(&head);
+        let path = self.dir.join(format!("{}.{}", id, extension_for(&mime_type)));
+
+        let mut index = self.index.lock().map_err(|e| e.to_string())?;
+        if let Some(existing) = self.reuse(&mut index, &id)? {
+            return Ok(existing);
+        }
+
+        fs::rename(&tmp.path, &path).map_err(|e| format!("Failed to write attachment: {}", e))?;
+        tmp.keep();
+
+        let attachment = Attachment {
+            id: id.clone(),
+            mime_type,
+            size: size as u64,
+            original_name: original_name.map(|n| n.to_string()),
+            created_at: unix_secs(),
+            refs: BTreeMap::new(),
+            path,
+        };
+        index.insert(id, attachment.clone());
+        self.save(&index)?;
+
+        Ok(attachment)
+    }
+
+    /// An existing attachment with this content. Storing it again restarts its grace
+    /// period, so an old unreferenced copy isn't collected before the new message is sent.
+    fn reuse(&self, index: &mut HashMap<String, Attachment>, id: &str) -> Result<Option<Attachment>, String> {
+        let Some(existing) = index.get_mut(id) else {
+            return Ok(None);
+        };
+        existing.created_at = unix_secs();
+        let existing = existing.clone();
+        self.save(index)?;
+        Ok(Some(existing))
+    }
+
+    pub fn get(&self, id: &str) -> Option<Attachment> {
+        self.index.lock().ok()?.get(id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<Attachment> {
+        let mut attachments: Vec<Attachment> = self
+            .index
+            .lock()
+            .map(|index| index.values().cloned().collect())
+            .unwrap_or_default();
+        attachments.sort_by_key(|a| std::cmp::Reverse(a.created_at));
+        attachments
+    }
+
+    /// Record that a message sent to `agent_id` references these attachments
+    pub fn add_references(&self, ids: &[String], agent_id: &str) -> Result<(), String> {
+        let mut index = self.index.lock().map_err(|e| e.to_string())?;
+        for id in ids {
+            if let Some(attachment) = index.get_mut(id) {
+                *attachment.refs.entry(agent_id.to_string()).or_default() += 1;
+            }
+        }
+        self.save(&index)
+    }
+
+    /// Drop every reference held by an agent's transcript, e.g. when the agent is killed
+    pub fn release_agent(&self, agent_id: &str) -> Result<(), String> {
+        let mut index = self.index.lock().map_err(|e| e.to_string())?;
+        let mut changed = false;
+        for attachment in index.values_mut() {
+            changed |= attachment.refs.remove(agent_id).is_some();
+        }
+        if changed {
+            self.save(&index)?;
+        }
+        Ok(())
+    }
+
+    /// Delete attachments no agent references, once they are older than the grace period.
+    /// References held by agents not in `live_agents`, e.g. ones lost to a restart, are
+    /// dropped first. Returns the ids removed.
+    pub fn gc(&self, live_agents: &HashSet<String>, grace_period: Duration) -> Result<Vec<String>, String> {
+        let mut index = self.index.lock().map_err(|e| e.to_string())?;
+        let cutoff = unix_secs().saturating_sub(grace_period.as_secs());
+
+        let mut released = false;
+        for attachment in index.values_mut() {
+            let before = attachment.refs.len();
+            attachment.refs.retain(|agent_id, _| live_agents.contains(agent_id));
+            released |= attachment.refs.len() != before;
+        }
+
+        let removable: Vec<String> = index
+            .values()
+            .filter(|a| a.ref_count() == 0 && a.created_at <= cutoff)
+            .map(|a| a.id.clone())
+            .collect();
+
+        for id in &removable {
+            if let Some(attachment) = index.remove(id) {
+                if let Err(e) = fs::remove_file(&attachment.path) {
+                    tracing::warn!("[AttachmentStore] Failed to remove {}: {}", attachment.path.display(), e);
+                }
+            }
+        }
+
+        if !removable.is_empty() {
+            tracing::info!("[AttachmentStore] Garbage collected {} attachment(s)", removable.len());
+        }
+        if released || !removable.is_empty() {
+            self.save(&index)?;
+        }
+        drop(index);
+
+        self.remove_stale_temp_files(grace_period);
+        Ok(removable)
+    }
+
+    /// Temporary files normally go when their upload finishes or is dropped, but a crash
+    /// can leave them behind
+    fn remove_stale_temp_files(&self, max_age: Duration) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if !entry.file_name().to_string_lossy().contains(TMP_MARKER) {
+                continue;
+            }
+            let stale = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age >= max_age);
+            if stale {
+                tracing::info!("[AttachmentStore] Removing stale temporary file {}", entry.path().display());
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    fn save(&self, index: &HashMap<String, Attachment>) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| format!("Failed to create attachment dir: {}", e))?;
+        let json = serde_json::to_string_pretty(index)
+            .map_err(|e| format!("Failed to serialize attachment index: {}", e))?;
+        crate::library::write_atomic(&self.dir.join("index.json"), json.as_bytes())
+            .map_err(|e| format!("Failed to write attachment index: {}", e))
+    }
+}
@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::auth::{Identity, Role};
+
+/// The live log is rotated once it would grow past this
+const MAX_AUDIT_FILE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Rotated files kept as `audit.1.jsonl` (newest) to `audit.N.jsonl` (oldest)
+const MAX_ROTATED_FILES: usize = 5;
+
+/// Entries returned by a query when it doesn't set `limit`
+const DEFAULT_QUERY_LIMIT: usize = 500;
+
+/// Most entries a single query can return
+const MAX_QUERY_LIMIT: usize = 5000;
+
+/// What was done
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    AgentCreate,
+    AgentKill,
+    AgentSettings,
+    Prompt,
+    FileWrite,
+    TerminalCreate,
+    /// Keystrokes sent over `/ws`, summed per terminal and connection
+    TerminalInput,
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Unix seconds
+    pub ts: u64,
+    pub role: Role,
+    /// None for the server's own token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    pub action: AuditAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_id: Option<String>,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub details: serde_json::Value,
+}
+
+/// Filters for `GET /api/audit`
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub agent: Option<String>,
+    /// Unix seconds, inclusive
+    pub since: Option<u64>,
+    pub action: Option<AuditAction>,
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.agent.as_deref().is_none_or(|agent| entry.agent_id.as_deref() == Some(agent))
+            && self.since.is_none_or(|since| entry.ts >= since)
+            && self.action.is_none_or(|action| entry.action == action)
+    }
+}
+
+struct Writer {
+    file: File,
+    size: u64,
+}
+
+/// Replace the values of an `env` map in entry details with just the variable names.
+/// Plain env values often hold credentials, which don't belong in a long-lived log;
+/// secret references are names already and are kept.
+pub fn redact_env(mut details: serde_json::Value) -> serde_json::Value {
+    if let Some(env) = details.get_mut("env") {
+        if let Some(vars) = env.as_object() {
+            *env = vars.keys().cloned().collect::<Vec<_>>().into();
+        }
+    }
+    details
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+/// Append-only JSONL log of actions taken through the server, in `<data dir>/audit`
+#[derive(Clone)]
+pub struct AuditLog {
+    dir: PathBuf,
+    writer: Arc<Mutex<Writer>>,
+}
+
+impl AuditLog {
+    pub fn open(data_dir: &Path) -> Result<Self, String> {
+        let dir = data_dir.join("audit");
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create audit dir: {}", e))?;
+
+        let path = dir.join("audit.jsonl");
+        let file = open_append(&path).map_err(|e| format!("Failed to open audit log: {}", e))?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+
+        Ok(Self {
+            dir,
+            writer: Arc::new(Mutex::new(Writer { file, size })),
+        })
+    }
+
+    /// `audit.jsonl` for generation 0, `audit.N.jsonl` for rotated ones
+    fn file_path(&self, generation: usize) -> PathBuf {
+        if generation == 0 {
+            self.dir.join("audit.jsonl")
+        } else {
+            self.dir.join(format!("audit.{}.jsonl", generation))
+        }
+    }
+
+    fn rotate(&self, writer: &mut Writer) -> std::io::Result<()> {
+        let _ = fs::remove_file(self.file_path(MAX_ROTATED_FILES));
+        for generation in (0..MAX_ROTATED_FILES).rev() {
+            let from = self.file_path(generation);
+            if from.exists() {
+                fs::rename(&from, self.file_path(generation + 1))?;
+            }
+        }
+        writer.file = open_append(&self.file_path(0))?;
+        writer.size = 0;
+        Ok(())
+    }
+
+    /// Append an entry. Failures are logged rather than returned so that auditing
+    /// never blocks the action itself.
+    pub fn record(&self, identity: &Identity, action: AuditAction, agent_id: Option<&str>, details: serde_json::Value) {
+        let entry = AuditEntry {
+            ts: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            role: identity.role,
+            device_id: identity.device_id.clone(),
+            action,
+            agent_id: agent_id.map(str::to_string),
+            details,
+        };
+        if let Err(e) = self.append(&entry) {
+            tracing::error!("[AuditLog] Failed to record {:?}: {}", action, e);
+        }
+    }
+
+    fn append(&self, entry: &AuditEntry) -> Result<(), String> {
+        let mut line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+        line.push('\n');
+
+        let mut writer = self.writer.lock().map_err(|e| e.to_string())?;
+        if writer.size > 0 && writer.size + line.len() as u64 > MAX_AUDIT_FILE_BYTES {
+            self.rotate(&mut writer).map_err(|e| format!("Failed to rotate audit log: {}", e))?;
+        }
+        writer.file.write_all(line.as_bytes()).map_err(|e| e.to_string())?;
+        writer.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Open every generation, oldest first, each with the length to read. Holding the
+    /// writer means no rotation moves files in between; the handles keep reading the
+    /// same files after one does.
+    fn snapshot(&self) -> Result<Vec<(File, u64)>, String> {
+        let writer = self.writer.lock().map_err(|e| e.to_string())?;
+        let mut files = Vec::new();
+        for generation in (0..=MAX_ROTATED_FILES).rev() {
+            let file = match File::open(self.file_path(generation)) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(format!("Failed to read audit log: {}", e)),
+            };
+            // Entries appended after the snapshot are left out
+            let len = if generation == 0 { writer.size } else { u64::MAX };
+            files.push((file, len));
+        }
+        Ok(files)
+    }
+
+    /// The newest matching entries, oldest first. Reads files synchronously, so call
+    /// it from a blocking task.
+    pub fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, String> {
+        let limit = query.limit.unwrap_or(DEFAULT_QUERY_LIMIT).clamp(1, MAX_QUERY_LIMIT);
+        let mut entries = VecDeque::with_capacity(limit);
+
+        for (file, len) in self.snapshot()? {
+            for line in BufReader::new(file.take(len)).lines() {
+                let line = line.map_err(|e| format!("Failed to read audit log: {}", e))?;
+                let Ok(entry) = serde_json::from_str::<AuditEntry>(&line) else {
+                    continue;
+                };
+                if query.matches(&entry) {
+                    if entries.len() == limit {
+                        entries.pop_front();
+                    }
+                    entries.push_back(entry);
+                }
+            }
+        }
+
+        Ok(entries.into())
+    }
+}
@@ -0,0 +1,233 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use axum::{
+    body::Body,
+    extract::{MatchedPath, State},
+    http::{header, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::devices::DeviceStore;
+
+/// WebSocket subprotocol the server selects when a client authenticates through
+/// `Sec-WebSocket-Protocol`, since browsers can't set headers on WebSocket requests
+pub const WS_PROTOCOL: &str = "virtual-agency";
+
+/// Prefix of the subprotocol entry carrying the token, e.g. `bearer.<token>`
+const WS_TOKEN_PROTOCOL_PREFIX: &str = "bearer.";
+
+/// What a token may do. Each role includes everything the roles below it can do.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Receives `/ws` events and lists agents and their session history
+    #[default]
+    Viewer,
+    /// Also reads files, attachments and the library, messages and stops agents and uses terminals
+    Operator,
+    /// Also creates and kills agents and changes settings, secrets and devices
+    Admin,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        })
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "operator" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role: {} (expected viewer, operator or admin)", s)),
+        }
+    }
+}
+
+/// Who made a request; `require_token` adds it to the request extensions
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub role: Role,
+    /// None for the server's own token
+    pub device_id: Option<String>,
+}
+
+/// Role needed for a route, keyed by its matched path. Viewers get only the routes
+/// listed for them, other reads need operator and unlisted writes need admin.
+pub fn required_role(method: &Method, route: &str) -> Role {
+    match (method.as_str(), route) {
+        // Secret names, paired devices, the audit log and the host filesystem are admin-only even to read
+        (_, "/api/secrets") | (_, "/api/secrets/:name") => Role::Admin,
+        (_, route) if route.starts_with("/api/devices") => Role::Admin,
+        (_, route) if route.starts_with("/api/shares") => Role::Admin,
+        (_, "/api/audit") => Role::Admin,
+        ("GET", "/api/browse") => Role::Admin,
+        ("GET", "/ws")
+        | ("GET", "/api/events")
+        | ("GET", "/api/agents")
+        | ("GET", "/api/agents/:id/events")
+        | ("GET", "/api/sessions")
+        | ("GET", "/api/sessions/:session_id")
+        | ("GET", "/v1/models") => Role::Viewer,
+        // Each MCP tool then needs the role of the route it mirrors
+        ("POST", "/mcp") => Role::Viewer,
+        // Files, attachments, terminals and the persona and template library
+        ("GET", _) | ("HEAD", _) => Role::Operator,
+        ("POST", "/api/files/read/:agent_id")
+        | ("POST", "/api/agents/:id/messages")
+        | ("POST", "/api/agents/:id/run")
+        | ("POST", "/v1/chat/completions")
+        | ("POST", "/api/agents/:id/stop")
+        | ("POST", "/api/attachments")
+        | ("POST", "/api/terminals")
+        | ("DELETE", "/api/terminals/:id") => Role::Operator,
+        _ => Role::Admin,
+    }
+}
+
+/// 256 random bits, hex encoded
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Shared secret every API and WebSocket client must present
+#[derive(Clone)]
+pub struct AuthToken(Arc<String>);
+
+impl AuthToken {
+    /// Read `auth.token` from the data dir, generating it on first start
+    pub fn load_or_create(data_dir: &Path) -> Result<Self, String> {
+        let path = data_dir.join("auth.token");
+
+        if path.exists() {
+            let token = fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read auth token: {}", e))?
+                .trim()
+                .to_string();
+            if token.is_empty() {
+                return Err(format!("Auth token file {} is empty", path.display()));
+            }
+            return Ok(Self(Arc::new(token)));
+        }
+
+        let token = generate_token();
+        crate::secrets::write_private(&path, token.as_bytes())
+            .map_err(|e| format!("Failed to write auth token: {}", e))?;
+        tracing::info!("[auth] Generated new auth token at {}", path.display());
+
+        Ok(Self(Arc::new(token)))
+    }
+
+    /// Compare without short-circuiting, so response timing doesn't leak the token
+    pub fn matches(&self, candidate: &str) -> bool {
+        let expected = self.0.as_bytes();
+        let candidate = candidate.as_bytes();
+        if expected.len() != candidate.len() {
+            return false;
+        }
+        expected
+            .iter()
+            .zip(candidate)
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+    }
+}
+
+/// Pull a token from the `Authorization` header, the `token` query parameter,
+/// or a `bearer.<token>` WebSocket subprotocol
+fn request_token(request: &Request<Body>) -> Option<String> {
+    let headers = request.headers();
+
+    if let Some(value) = headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()) {
+        if let Some(token) = value.strip_prefix("Bearer ") {
+            return Some(token.trim().to_string());
+        }
+    }
+
+    if let Some(query) = request.uri().query() {
+        let token = query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == "token")
+            .map(|(_, value)| value.to_string());
+        if token.is_some() {
+            return token;
+        }
+    }
+
+    headers
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|protocols| {
+            protocols
+                .split(',')
+                .map(str::trim)
+                .find_map(|p| p.strip_prefix(WS_TOKEN_PROTOCOL_PREFIX))
+                .map(|t| t.to_string())
+        })
+}
+
+/// Accepts the server's own token or the token of a paired device
+#[derive(Clone)]
+pub struct Authenticator {
+    pub token: AuthToken,
+    pub devices: DeviceStore,
+}
+
+impl Authenticator {
+    fn identify(&self, token: &str) -> Option<Identity> {
+        if self.token.matches(token) {
+            return Some(Identity {
+                role: Role::Admin,
+                device_id: None,
+            });
+        }
+        self.devices.authenticate(token).map(|device| Identity {
+            role: device.role,
+            device_id: Some(device.id),
+        })
+    }
+}
+
+/// Reject requests without a valid token, or whose token's role is too low for the route
+pub async fn require_token(State(auth): State<Authenticator>, mut request: Request<Body>, next: Next) -> Response {
+    let identity = match request_token(&request) {
+        Some(candidate) => match auth.identify(&candidate) {
+            Some(identity) => identity,
+            None => return (StatusCode::UNAUTHORIZED, "Invalid auth token").into_response(),
+        },
+        None => return (StatusCode::UNAUTHORIZED, "Missing auth token").into_response(),
+    };
+
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let required = required_role(request.method(), &route);
+    if identity.role < required {
+        tracing::warn!(
+            "[auth] {} {} needs the {} role, token has {}",
+            request.method(), route, required, identity.role
+        );
+        return (StatusCode::FORBIDDEN, format!("Requires the {} role", required)).into_response();
+    }
+
+    request.extensions_mut().insert(identity);
+    next.run(request).await
+}
@@ -0,0 +1,338 @@
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::auth::Role;
+
+/// Template defaults read this, and the template types are shared with the desktop app
+pub use virtual_agency_templates::{default_model, set_default_model};
+
+const DEFAULT_LOG_LEVEL: &str = "virtual_agency_server=debug,tower_http=debug";
+
+/// Command-line flags. Every setting can also come from its environment variable;
+/// values given here win over the config file, which wins over the defaults.
+#[derive(Debug, Parser)]
+#[command(name = "virtual-agency-server", version, about = "Web server for Virtual Agency")]
+pub struct Cli {
+    /// Config file (default: ./virtual-agency.toml, then <config dir>/virtual-agency/server.toml)
+    #[arg(long, env = "VIRTUAL_AGENCY_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address to listen on
+    #[arg(long, env = "VIRTUAL_AGENCY_HOST")]
+    pub host: Option<String>,
+
+    #[arg(long, env = "VIRTUAL_AGENCY_PORT")]
+    pub port: Option<u16>,
+
+    /// Accept paired devices from the local network; binds all interfaces unless --host names one,
+    /// which must not be a loopback address
+    #[arg(long, env = "VIRTUAL_AGENCY_LAN", num_args = 0..=1, default_missing_value = "true")]
+    pub lan: Option<bool>,
+
+    /// Web UI address put in pairing links (default: port 1420 on this machine's LAN address)
+    #[arg(long, env = "VIRTUAL_AGENCY_UI_URL")]
+    pub ui_url: Option<String>,
+
+    /// Serve HTTPS/WSS, with a self-signed certificate unless --tls-cert and --tls-key are given
+    #[arg(long, env = "VIRTUAL_AGENCY_TLS", num_args = 0..=1, default_missing_value = "true")]
+    pub tls: Option<bool>,
+
+    /// PEM certificate chain; implies --tls
+    #[arg(long, env = "VIRTUAL_AGENCY_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, env = "VIRTUAL_AGENCY_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Role of devices paired with the code printed at startup
+    #[arg(long, env = "VIRTUAL_AGENCY_PAIRING_ROLE")]
+    pub pairing_role: Option<Role>,
+
+    /// Where personas, templates, secrets and attachments are stored
+    #[arg(long, env = "VIRTUAL_AGENCY_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Default allowed root when --allowed-root isn't given
+    #[arg(long, env = "WORKSPACE_DIR")]
+    pub workspace_dir: Option<PathBuf>,
+
+    /// Directory agents, terminals, file access and browsing are confined to; repeatable
+    #[arg(long = "allowed-root", env = "VIRTUAL_AGENCY_ALLOWED_ROOTS", value_delimiter = ',')]
+    pub allowed_roots: Vec<PathBuf>,
+
+    /// Largest accepted request body, in bytes
+    #[arg(long, env = "VIRTUAL_AGENCY_MAX_BODY_BYTES")]
+    pub max_body_bytes: Option<usize>,
+
+    /// Messages buffered per WebSocket broadcast channel before slow clients lag
+    #[arg(long, env = "VIRTUAL_AGENCY_BROADCAST_CAPACITY")]
+    pub broadcast_capacity: Option<usize>,
+
+    /// Longest edge of images sent to agents
+    #[arg(long, env = "VIRTUAL_AGENCY_MAX_IMAGE_DIMENSION")]
+    pub max_image_dimension: Option<u32>,
+
+    /// Model for agents created without one
+    #[arg(long, env = "VIRTUAL_AGENCY_DEFAULT_MODEL")]
+    pub default_model: Option<String>,
+
+    /// Claude CLI executable; searched for on PATH when unset
+    #[arg(long, env = "VIRTUAL_AGENCY_CLAUDE_PATH")]
+    pub claude_path: Option<PathBuf>,
+
+    /// Origins allowed to call the API from a browser; `*` allows any
+    #[arg(long = "cors-origin", env = "VIRTUAL_AGENCY_CORS_ORIGINS", value_delimiter = ',')]
+    pub cors_origins: Vec<String>,
+
+    /// tracing filter, e.g. `info` or `virtual_agency_server=debug`
+    #[arg(long, env = "RUST_LOG")]
+    pub log_level: Option<String>,
+
+    /// Print the effective configuration as TOML and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum Command {
+    /// Bridge MCP over stdin and stdout to a running server's `/mcp` endpoint, for MCP
+    /// clients that launch their servers themselves. Takes the same host, port, TLS and
+    /// data dir settings as that server.
+    Mcp {
+        /// Token to send instead of the server's own, e.g. a paired device's
+        #[arg(long, env = "VIRTUAL_AGENCY_TOKEN")]
+        token: Option<String>,
+    },
+}
+
+/// Effective server settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub lan: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ui_url: Option<String>,
+    pub pairing_role: Role,
+    pub tls: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_key: Option<PathBuf>,
+    pub data_dir: PathBuf,
+    pub workspace_dir: PathBuf,
+    /// Empty means just `workspace_dir`
+    pub allowed_roots: Vec<PathBuf>,
+    pub max_body_bytes: usize,
+    pub broadcast_capacity: usize,
+    pub max_image_dimension: u32,
+    pub default_model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub claude_path: Option<PathBuf>,
+    pub cors_origins: Vec<String>,
+    pub log_level: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 3001,
+            lan: false,
+            ui_url: None,
+            pairing_role: Role::Viewer,
+            tls: false,
+            tls_cert: None,
+            tls_key: None,
+            data_dir: dirs::data_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("virtual-agency"),
+            workspace_dir: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+            allowed_roots: Vec::new(),
+            max_body_bytes: 50 * 1024 * 1024,
+            broadcast_capacity: 1000,
+            max_image_dimension: crate::images::DEFAULT_MAX_IMAGE_DIMENSION,
+            default_model: "sonnet".to_string(),
+            claude_path: None,
+            // The Vite dev server and the Tauri webview
+            cors_origins: vec![
+                "http://localhost:1420".to_string(),
+                "http://127.0.0.1:1420".to_string(),
+                "tauri://localhost".to_string(),
+                "http://tauri.localhost".to_string(),
+            ],
+            log_level: DEFAULT_LOG_LEVEL.to_string(),
+        }
+    }
+}
+
+/// Config file locations tried in order when `--config` isn't given
+fn config_search_path() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from("virtual-agency.toml")];
+    if let Some(config_dir) = dirs::config_dir() {
+        paths.push(config_dir.join("virtual-agency").join("server.toml"));
+    }
+    paths
+}
+
+/// The parsed file, and whether it sets `host` itself rather than leaving the default
+fn read_config_file(path: &Path) -> Result<(ServerConfig, bool), String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+    let config = toml::from_str(&contents)
+        .map_err(|e| format!("Failed to parse config file {}: {}", path.display(), e))?;
+    let sets_host = toml::from_str::<toml::Table>(&contents).is_ok_and(|table| table.contains_key("host"));
+    Ok((config, sets_host))
+}
+
+impl ServerConfig {
+    /// Resolve settings from the command line, environment, config file and defaults.
+    /// Returns the config file used, if any.
+    pub fn load(cli: Cli) -> Result<(Self, Option<PathBuf>), String> {
+        let file = match cli.config {
+            // An explicit path must exist
+            Some(path) => Some(path),
+            None => config_search_path().into_iter().find(|p| p.is_file()),
+        };
+        let (mut config, file_sets_host) = match &file {
+            Some(path) => read_config_file(path)?,
+            None => (Self::default(), false),
+        };
+        let host_given = file_sets_host || cli.host.is_some();
+
+        if let Some(host) = cli.host {
+            config.host = host;
+        }
+        if let Some(port) = cli.port {
+            config.port = port;
+        }
+        if let Some(lan) = cli.lan {
+            config.lan = lan;
+        }
+        if let Some(ui_url) = cli.ui_url {
+            config.ui_url = Some(ui_url);
+        }
+        if let Some(pairing_role) = cli.pairing_role {
+            config.pairing_role = pairing_role;
+        }
+        if let Some(tls) = cli.tls {
+            config.tls = tls;
+        }
+        if let Some(tls_cert) = cli.tls_cert {
+            config.tls_cert = Some(tls_cert);
+        }
+        if let Some(tls_key) = cli.tls_key {
+            config.tls_key = Some(tls_key);
+        }
+        if let Some(data_dir) = cli.data_dir {
+            config.data_dir = data_dir;
+        }
+        if let Some(workspace_dir) = cli.workspace_dir {
+            config.workspace_dir = workspace_dir;
+        }
+        if !cli.allowed_roots.is_empty() {
+            config.allowed_roots = cli.allowed_roots;
+        }
+        if let Some(max_body_bytes) = cli.max_body_bytes {
+            config.max_body_bytes = max_body_bytes;
+        }
+        if let Some(broadcast_capacity) = cli.broadcast_capacity {
+            config.broadcast_capacity = broadcast_capacity;
+        }
+        if let Some(max_image_dimension) = cli.max_image_dimension {
+            config.max_image_dimension = max_image_dimension;
+        }
+        if let Some(default_model) = cli.default_model {
+            config.default_model = default_model;
+        }
+        if let Some(claude_path) = cli.claude_path {
+            config.claude_path = Some(claude_path);
+        }
+        if !cli.cors_origins.is_empty() {
+            config.cors_origins = cli.cors_origins;
+        }
+        if let Some(log_level) = cli.log_level {
+            config.log_level = log_level;
+        }
+
+        if config.tls_cert.is_some() {
+            config.tls = true;
+        }
+
+        // LAN mode is pointless on loopback, so widen the default bind address. A loopback
+        // host that was asked for explicitly is a contradiction rather than something to override.
+        if config.lan && is_loopback(&config.host) {
+            if host_given {
+                return Err(format!(
+                    "LAN mode can't accept devices on loopback host {}; give a LAN address or leave host unset",
+                    config.host
+                ));
+            }
+            config.host = "0.0.0.0".to_string();
+        }
+
+        config.validate()?;
+        Ok((config, file))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.broadcast_capacity == 0 {
+            return Err("broadcast_capacity must be greater than 0".to_string());
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err("tls_cert and tls_key must be set together".to_string());
+        }
+        if self.max_image_dimension == 0 {
+            return Err("max_image_dimension must be greater than 0".to_string());
+        }
+        for origin in &self.cors_origins {
+            if origin != "*" && axum::http::HeaderValue::from_str(origin).is_err() {
+                return Err(format!("Invalid CORS origin: {}", origin));
+            }
+        }
+        if self.default_model.is_empty() {
+            return Err("default_model must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn effective_allowed_roots(&self) -> Vec<PathBuf> {
+        if self.allowed_roots.is_empty() {
+            vec![self.workspace_dir.clone()]
+        } else {
+            self.allowed_roots.clone()
+        }
+    }
+
+    /// Base URL of the web UI used in pairing links
+    pub fn effective_ui_url(&self) -> String {
+        if let Some(url) = &self.ui_url {
+            return url.trim_end_matches('/').to_string();
+        }
+        let host = if self.lan { lan_address().unwrap_or_else(|| self.host.clone()) } else { "localhost".to_string() };
+        format!("http://{}:1420", host)
+    }
+
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string_pretty(self).map_err(|e| format!("Failed to serialize config: {}", e))
+    }
+}
+
+fn is_loopback(host: &str) -> bool {
+    host == "localhost" || host.parse::<std::net::IpAddr>().map(|ip| ip.is_loopback()).unwrap_or(false)
+}
+
+/// This machine's address on the network the default route goes through.
+/// Connecting a UDP socket sends no packets; it only picks the outgoing interface.
+pub fn lan_address() -> Option<String> {
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:80").ok()?;
+    Some(socket.local_addr().ok()?.ip().to_string())
+}
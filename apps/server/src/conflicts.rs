@@ -0,0 +1,217 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+use crate::BroadcastMessage;
+
+/// How long a file modification counts towards a conflict with another agent
+const CONFLICT_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Claude CLI tools that modify files, with the input field holding the path
+const FILE_MODIFYING_TOOLS: &[(&str, &str)] = &[
+    ("Write", "file_path"),
+    ("Edit", "file_path"),
+    ("MultiEdit", "file_path"),
+    ("NotebookEdit", "notebook_path"),
+];
+
+/// Emitted when an agent modifies a file that another agent's run in progress modified
+/// within the conflict window
+#[derive(Debug, Clone, Serialize)]
+pub struct ConflictDetected {
+    pub file_path: String,
+    pub agent_id: String,
+    pub agent_name: String,
+    pub other_agent_id: String,
+    pub other_agent_name: String,
+    /// Unix timestamp (ms) of the other agent's modification
+    pub other_modified_at: u64,
+    /// Unix timestamp (ms) of this agent's modification
+    pub detected_at: u64,
+}
+
+/// Another agent whose working directory overlaps a new agent's one
+#[derive(Debug, Clone, Serialize)]
+pub struct OverlappingAgent {
+    pub agent_id: String,
+    pub agent_name: String,
+    pub working_dir: String,
+}
+
+struct Modification {
+    agent_id: String,
+    agent_name: String,
+    at: Instant,
+    at_ms: u64,
+}
+
+#[derive(Default)]
+struct TrackerState {
+    modifications: HashMap<PathBuf, Vec<Modification>>,
+    /// Agents with a run in progress; only their modifications can conflict
+    running: HashSet<String>,
+}
+
+/// Tracks file modifications of runs in progress across agents to detect concurrent edits
+#[derive(Clone)]
+pub struct ConflictTracker {
+    state: Arc<Mutex<TrackerState>>,
+    broadcast_tx: broadcast::Sender<BroadcastMessage>,
+}
+
+impl ConflictTracker {
+    pub fn new(broadcast_tx: broadcast::Sender<BroadcastMessage>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(TrackerState::default())),
+            broadcast_tx,
+        }
+    }
+
+    pub fn run_started(&self, agent_id: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            state.running.insert(agent_id.to_string());
+        }
+    }
+
+    /// The run's modifications stop counting once it ends
+    pub fn run_finished(&self, agent_id: &str) {
+        self.forget_agent(agent_id);
+    }
+
+    /// Inspect a stream-json line from an agent run and record any file it modifies
+    pub fn observe_output(&self, agent_id: &str, agent_name: &str, working_dir: &str, json: &serde_json::Value) {
+        for path in modified_files(json) {
+            self.record_modification(agent_id, agent_name, &Path::new(working_dir).join(path));
+        }
+    }
+
+    /// Record that an agent modified a file, broadcasting a conflict for every other
+    /// agent with a run in progress that modified it within the window
+    pub fn record_modification(&self, agent_id: &str, agent_name: &str, file_path: &Path) {
+        let file_path = normalize_path(file_path);
+        let now = Instant::now();
+        let now_ms = unix_millis();
+
+        let conflicts: Vec<ConflictDetected> = {
+            let Ok(mut state) = self.state.lock() else {
+                return;
+            };
+            let TrackerState { modifications, running } = &mut *state;
+
+            // Drop stale entries so the map doesn't grow for the lifetime of the server
+            modifications.retain(|_, entries| {
+                entries.retain(|m| now.duration_since(m.at) < CONFLICT_WINDOW && running.contains(&m.agent_id));
+                !entries.is_empty()
+            });
+
+            let entries = modifications.entry(file_path.clone()).or_default();
+            let conflicts = entries
+                .iter()
+                .filter(|m| m.agent_id != agent_id)
+                .map(|m| ConflictDetected {
+                    file_path: file_path.to_string_lossy().to_string(),
+                    agent_id: agent_id.to_string(),
+                    agent_name: agent_name.to_string(),
+                    other_agent_id: m.agent_id.clone(),
+                    other_agent_name: m.agent_name.clone(),
+                    other_modified_at: m.at_ms,
+                    detected_at: now_ms,
+                })
+                .collect();
+
+            entries.retain(|m| m.agent_id != agent_id);
+            entries.push(Modification {
+                agent_id: agent_id.to_string(),
+                agent_name: agent_name.to_string(),
+                at: now,
+                at_ms: now_ms,
+            });
+
+            conflicts
+        };
+
+        for conflict in conflicts {
+            tracing::warn!(
+                "[ConflictTracker] {} ({}) and {} ({}) both modified {}",
+                conflict.agent_name,
+                conflict.agent_id,
+                conflict.other_agent_name,
+                conflict.other_agent_id,
+                conflict.file_path
+            );
+            let _ = self.broadcast_tx.send(BroadcastMessage::ConflictDetected(conflict));
+        }
+    }
+
+    /// Forget all modifications made by an agent, e.g. when it is killed
+    pub fn forget_agent(&self, agent_id: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            state.running.remove(agent_id);
+            state.modifications.retain(|_, entries| {
+                entries.retain(|m| m.agent_id != agent_id);
+                !entries.is_empty()
+            });
+        }
+    }
+}
+
+/// Paths written by file-modifying tool calls in a stream-json line, as the tools gave them
+pub fn modified_files(json: &serde_json::Value) -> Vec<&str> {
+    if json.get("type").and_then(|v| v.as_str()) != Some("assistant") {
+        return Vec::new();
+    }
+
+    let Some(content) = json
+        .get("message")
+        .and_then(|m| m.get("content"))
+        .and_then(|c| c.as_array())
+    else {
+        return Vec::new();
+    };
+
+    content
+        .iter()
+        .filter(|block| block.get("type").and_then(|v| v.as_str()) == Some("tool_use"))
+        .filter_map(|block| {
+            let tool = block.get("name").and_then(|v| v.as_str()).unwrap_or_default();
+            let (_, field) = FILE_MODIFYING_TOOLS.iter().find(|(name, _)| *name == tool)?;
+            block.get("input")?.get(*field)?.as_str()
+        })
+        .collect()
+}
+
+/// Whether two working directories are the same or one is nested inside the other
+pub fn dirs_overlap(a: &str, b: &str) -> bool {
+    let a = normalize_path(Path::new(a));
+    let b = normalize_path(Path::new(b));
+    a.starts_with(&b) || b.starts_with(&a)
+}
+
+/// Resolve `.` and `..` components lexically, using the canonical path when it exists
+fn normalize_path(path: &Path) -> PathBuf {
+    if let Ok(canonical) = path.canonicalize() {
+        return canonical;
+    }
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
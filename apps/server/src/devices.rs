@@ -0,0 +1,298 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::auth::Role;
+
+/// How long a pairing code stays valid
+pub const PAIRING_CODE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Wrong guesses allowed before a pairing code is thrown away
+const MAX_PAIRING_ATTEMPTS: u32 = 5;
+
+/// Pairing code characters, without look-alikes such as 0/O and 1/I
+const PAIRING_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// `last_seen_at` is only persisted when it moves by at least this much
+const LAST_SEEN_RESOLUTION_SECS: u64 = 60;
+
+/// A paired device; only a hash of its token is kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Device {
+    pub id: String,
+    pub name: String,
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen_at: Option<u64>,
+    /// Devices paired before roles existed are viewers
+    #[serde(default)]
+    pub role: Role,
+    token_hash: String,
+}
+
+/// Device metadata returned by list endpoints
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceInfo {
+    pub id: String,
+    pub name: String,
+    pub created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen_at: Option<u64>,
+    pub role: Role,
+}
+
+impl From<&Device> for DeviceInfo {
+    fn from(device: &Device) -> Self {
+        Self {
+            id: device.id.clone(),
+            name: device.name.clone(),
+            created_at: device.created_at,
+            last_seen_at: device.last_seen_at,
+            role: device.role,
+        }
+    }
+}
+
+/// Returned once, when a device pairs
+#[derive(Debug, Serialize)]
+pub struct PairedDevice {
+    pub device: DeviceInfo,
+    pub token: String,
+}
+
+/// The code currently accepted by the pairing endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct PairingCode {
+    pub code: String,
+    /// Unix seconds
+    pub expires_at: u64,
+    /// Role the paired device gets
+    pub role: Role,
+    #[serde(skip)]
+    expires: Instant,
+    #[serde(skip)]
+    failed_attempts: u32,
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn generate_pairing_code() -> String {
+    use aes_gcm::aead::rand_core::RngCore;
+
+    let mut bytes = [0u8; 8];
+    aes_gcm::aead::OsRng.fill_bytes(&mut bytes);
+    let chars: String = bytes
+        .iter()
+        .map(|b| PAIRING_ALPHABET[*b as usize % PAIRING_ALPHABET.len()] as char)
+        .collect();
+    format!("{}-{}", &chars[..4], &chars[4..])
+}
+
+/// Devices paired over the LAN, persisted as `devices.json` in the server data dir
+#[derive(Clone)]
+pub struct DeviceStore {
+    path: PathBuf,
+    devices: Arc<RwLock<HashMap<String, Device>>>,
+    pairing_code: Arc<Mutex<Option<PairingCode>>>,
+    /// Notified when a device's role changes or it is revoked
+    changes: Arc<tokio::sync::watch::Sender<()>>,
+}
+
+impl DeviceStore {
+    /// An unreadable file is an error rather than an empty store, which would unpair
+    /// every device on the next save
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let path = data_dir.join("devices.json");
+
+        let devices = if path.exists() {
+            fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| serde_json::from_str::<Vec<Device>>(&contents).map_err(|e| e.to_string()))
+                .map_err(|e| format!("Failed to load {}: {}; fix or move the file", path.display(), e))?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            path,
+            devices: Arc::new(RwLock::new(devices.into_iter().map(|d| (d.id.clone(), d)).collect())),
+            pairing_code: Arc::new(Mutex::new(None)),
+            changes: Arc::new(tokio::sync::watch::Sender::new(())),
+        })
+    }
+
+    /// Current role of a device, or None once it is revoked
+    pub fn role(&self, id: &str) -> Option<Role> {
+        self.devices.read().ok()?.get(id).map(|d| d.role)
+    }
+
+    /// Wakes up whenever a device's role changes or it is revoked, so long-lived
+    /// connections can check their device again
+    pub fn watch(&self) -> tokio::sync::watch::Receiver<()> {
+        self.changes.subscribe()
+    }
+
+    pub fn list(&self) -> Vec<DeviceInfo> {
+        let mut devices: Vec<DeviceInfo> = self
+            .devices
+            .read()
+            .map(|d| d.values().map(DeviceInfo::from).collect())
+            .unwrap_or_default();
+        devices.sort_by_key(|d| d.created_at);
+        devices
+    }
+
+    /// Replace any outstanding pairing code with a fresh one
+    pub fn new_pairing_code(&self, role: Role) -> Result<PairingCode, String> {
+        let code = PairingCode {
+            code: generate_pairing_code(),
+            expires_at: unix_secs() + PAIRING_CODE_TTL.as_secs(),
+            role,
+            expires: Instant::now() + PAIRING_CODE_TTL,
+            failed_attempts: 0,
+        };
+        *self.pairing_code.lock().map_err(|e| e.to_string())? = Some(code.clone());
+        Ok(code)
+    }
+
+    /// Exchange the current pairing code for a device token. The code works once.
+    pub fn pair(&self, code: &str, name: &str) -> Result<PairedDevice, String> {
+        let role = {
+            let mut current = self.pairing_code.lock().map_err(|e| e.to_string())?;
+            let Some(pending) = current.as_mut() else {
+                return Err("No pairing code is active".to_string());
+            };
+            if Instant::now() > pending.expires {
+                *current = None;
+                return Err("Pairing code has expired".to_string());
+            }
+            if !pending.code.eq_ignore_ascii_case(code.trim()) {
+                pending.failed_attempts += 1;
+                if pending.failed_attempts >= MAX_PAIRING_ATTEMPTS {
+                    tracing::warn!("[DeviceStore] Too many wrong pairing attempts, discarding the code");
+                    *current = None;
+                }
+                return Err("Invalid pairing code".to_string());
+            }
+            let role = pending.role;
+            *current = None;
+            role
+        };
+
+        let token = crate::auth::generate_token();
+        let device = Device {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: if name.trim().is_empty() { "Unnamed device".to_string() } else { name.trim().to_string() },
+            created_at: unix_secs(),
+            last_seen_at: None,
+            role,
+            token_hash: hash_token(&token),
+        };
+        let info = DeviceInfo::from(&device);
+
+        {
+            let mut devices = self.devices.write().map_err(|e| e.to_string())?;
+            devices.insert(device.id.clone(), device);
+        }
+        self.save()?;
+        tracing::info!("[DeviceStore] Paired device {} ({}) as {}", info.name, info.id, info.role);
+
+        Ok(PairedDevice { device: info, token })
+    }
+
+    /// Find the device a token belongs to, recording that it was seen
+    pub fn authenticate(&self, token: &str) -> Option<DeviceInfo> {
+        let hash = hash_token(token);
+        let now = unix_secs();
+
+        let (info, stale) = {
+            let devices = self.devices.read().ok()?;
+            let device = devices.values().find(|d| d.token_hash == hash)?;
+            let stale = device
+                .last_seen_at
+                .is_none_or(|seen| now.saturating_sub(seen) >= LAST_SEEN_RESOLUTION_SECS);
+            (DeviceInfo::from(device), stale)
+        };
+
+        if stale {
+            if let Ok(mut devices) = self.devices.write() {
+                if let Some(device) = devices.get_mut(&info.id) {
+                    device.last_seen_at = Some(now);
+                }
+            }
+            if let Err(e) = self.save() {
+                tracing::warn!("[DeviceStore] Failed to record last seen time: {}", e);
+            }
+        }
+
+        Some(info)
+    }
+
+    pub fn set_role(&self, id: &str, role: Role) -> Result<DeviceInfo, String> {
+        let info = {
+            let mut devices = self.devices.write().map_err(|e| e.to_string())?;
+            let device = devices.get_mut(id).ok_or_else(|| format!("Device not found: {}", id))?;
+            device.role = role;
+            DeviceInfo::from(&*device)
+        };
+        self.changes.send_replace(());
+        self.save()?;
+        Ok(info)
+    }
+
+    pub fn revoke(&self, id: &str) -> Result<(), String> {
+        {
+            let mut devices = self.devices.write().map_err(|e| e.to_string())?;
+            if devices.remove(id).is_none() {
+                return Err(format!("Device not found: {}", id));
+            }
+        }
+        self.changes.send_replace(());
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let json = {
+            let devices = self.devices.read().map_err(|e| e.to_string())?;
+            let mut devices: Vec<&Device> = devices.values().collect();
+            devices.sort_by_key(|d| d.created_at);
+            serde_json::to_string_pretty(&devices).map_err(|e| format!("Failed to serialize devices: {}", e))?
+        };
+        crate::secrets::write_private(&self.path, json.as_bytes())
+            .map_err(|e| format!("Failed to write devices file: {}", e))
+    }
+}
+
+/// Print a pairing code and a QR code of the pairing link to the terminal
+pub fn print_pairing_code(code: &PairingCode, pair_url: &str) {
+    use qrcode::render::unicode::Dense1x2;
+
+    println!();
+    println!("  Pair a device as {}: open {} or enter code {}", code.role, pair_url, code.code);
+    match qrcode::QrCode::new(pair_url.as_bytes()) {
+        Ok(qr) => {
+            let image = qr
+                .render::<Dense1x2>()
+                .dark_color(Dense1x2::Light)
+                .light_color(Dense1x2::Dark)
+                .build();
+            println!("{}", image);
+        }
+        Err(e) => tracing::warn!("[DeviceStore] Failed to render pairing QR code: {}", e),
+    }
+    println!("  The code expires in {} minutes and works once.", PAIRING_CODE_TTL.as_secs() / 60);
+    println!();
+}
@@ -0,0 +1,91 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// Broadcasts numbered for Server-Sent Events, with a backlog of the most recent ones
+/// so clients can resume with `Last-Event-ID`
+#[derive(Clone)]
+pub struct EventJournal<T> {
+    inner: Arc<Mutex<Journal<T>>>,
+    tx: broadcast::Sender<(u64, T)>,
+}
+
+struct Journal<T> {
+    next_id: u64,
+    capacity: usize,
+    backlog: VecDeque<(u64, T)>,
+}
+
+/// What a subscriber missed since its last event, followed by live events
+pub struct Replay<T> {
+    pub backlog: Vec<(u64, T)>,
+    /// Some of the missed events had already left the backlog
+    pub truncated: bool,
+    pub live: broadcast::Receiver<(u64, T)>,
+}
+
+impl<T: Clone> EventJournal<T> {
+    pub fn new(capacity: usize) -> Self {
+        // Ids start from the startup time so ids from a previous run are always older
+        let next_id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64 * 1000)
+            .unwrap_or(1);
+        let (tx, _) = broadcast::channel(capacity);
+
+        Self {
+            inner: Arc::new(Mutex::new(Journal {
+                next_id,
+                capacity,
+                backlog: VecDeque::with_capacity(capacity),
+            })),
+            tx,
+        }
+    }
+
+    pub fn push(&self, event: T) {
+        let Ok(mut journal) = self.inner.lock() else {
+            return;
+        };
+        let id = journal.next_id;
+        journal.next_id += 1;
+        if journal.backlog.len() == journal.capacity {
+            journal.backlog.pop_front();
+        }
+        journal.backlog.push_back((id, event.clone()));
+        // Sent under the lock so subscribe_since never sees an event twice or not at all
+        let _ = self.tx.send((id, event));
+    }
+
+    /// Events after `last_id` still in the backlog, then everything newer. Without a
+    /// `last_id` only new events are delivered.
+    pub fn subscribe_since(&self, last_id: Option<u64>) -> Replay<T> {
+        let journal = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let live = self.tx.subscribe();
+
+        let Some(last_id) = last_id else {
+            return Replay {
+                backlog: Vec::new(),
+                truncated: false,
+                live,
+            };
+        };
+        let truncated = journal
+            .backlog
+            .front()
+            .is_some_and(|(oldest, _)| *oldest > last_id + 1);
+        let backlog = journal
+            .backlog
+            .iter()
+            .filter(|(id, _)| *id > last_id)
+            .cloned()
+            .collect();
+
+        Replay {
+            backlog,
+            truncated,
+            live,
+        }
+    }
+}
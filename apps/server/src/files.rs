@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FileNode {
@@ -33,6 +33,8 @@ fn should_ignore(name: &str) -> bool {
     )
 }
 
+/// Walk `path` without following symlinks out of `base`. Symlinks that resolve inside
+/// `base` are listed but not descended into, so link cycles can't recurse forever.
 fn build_file_tree(path: &Path, base_path: &Path) -> Result<FileNode, std::io::Error> {
     let name = path
         .file_name()
@@ -46,7 +48,22 @@ fn build_file_tree(path: &Path, base_path: &Path) -> Result<FileNode, std::io::E
         .to_string_lossy()
         .to_string();
 
-    if path.is_dir() {
+    let file_type = fs::symlink_metadata(path)?.file_type();
+    if file_type.is_symlink() {
+        let target = path.canonicalize()?;
+        if !target.starts_with(base_path) {
+            return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "symlink leaves workspace"));
+        }
+        let is_directory = target.is_dir();
+        return Ok(FileNode {
+            name,
+            path: relative_path,
+            is_directory,
+            children: is_directory.then(Vec::new),
+        });
+    }
+
+    if file_type.is_dir() {
         let mut children = Vec::new();
         if let Ok(entries) = fs::read_dir(path) {
             for entry in entries.flatten() {
@@ -84,62 +101,29 @@ fn build_file_tree(path: &Path, base_path: &Path) -> Result<FileNode, std::io::E
     }
 }
 
-pub async fn get_file_tree(workspace_dir: &PathBuf) -> Result<FileNode, String> {
+/// `workspace_dir` must be canonical, as returned by the sandbox
+pub async fn get_file_tree(workspace_dir: &Path) -> Result<FileNode, String> {
     build_file_tree(workspace_dir, workspace_dir)
         .map_err(|e| e.to_string())
 }
 
-pub async fn read_file(
-    workspace_dir: &PathBuf,
-    req: ReadFileRequest,
-) -> Result<FileContent, String> {
-    let file_path = workspace_dir.join(&req.path);
-
-    // Canonicalize to resolve symlinks and prevent path traversal
-    let canonical_workspace = workspace_dir
-        .canonicalize()
-        .map_err(|e| format!("Invalid workspace directory: {}", e))?;
-    let canonical_file = file_path
-        .canonicalize()
-        .map_err(|e| format!("File not found: {}", e))?;
-
-    if !canonical_file.starts_with(&canonical_workspace) {
-        return Err("Access denied: path outside workspace".to_string());
-    }
-
-    let content = fs::read_to_string(&canonical_file)
+/// `path` must already be resolved with `sandbox::resolve_existing`
+pub async fn read_file(path: &Path) -> Result<FileContent, String> {
+    let content = fs::read_to_string(path)
         .map_err(|e| e.to_string())?;
 
     Ok(FileContent { content })
 }
 
-pub async fn write_file(
-    workspace_dir: &PathBuf,
-    req: WriteFileRequest,
-) -> Result<serde_json::Value, String> {
-    let file_path = workspace_dir.join(&req.path);
-
-    // Canonicalize workspace directory
-    let canonical_workspace = workspace_dir
-        .canonicalize()
-        .map_err(|e| format!("Invalid workspace directory: {}", e))?;
-
-    // For write operations, we need to handle the case where the file doesn't exist yet
-    // So we check the parent directory instead
-    if let Some(parent) = file_path.parent() {
+/// `path` must already be resolved with `sandbox::resolve_for_write`, which guarantees
+/// that its missing parents are created inside the workspace
+pub async fn write_file(path: &Path, content: &str) -> Result<serde_json::Value, String> {
+    if let Some(parent) = path.parent() {
         fs::create_dir_all(parent)
             .map_err(|e| format!("Failed to create directory: {}", e))?;
-
-        let canonical_parent = parent
-            .canonicalize()
-            .map_err(|e| format!("Invalid parent directory: {}", e))?;
-
-        if !canonical_parent.starts_with(&canonical_workspace) {
-            return Err("Access denied: path outside workspace".to_string());
-        }
     }
 
-    fs::write(&file_path, &req.content)
+    fs::write(path, content)
         .map_err(|e| e.to_string())?;
 
     Ok(serde_json::json!({"success": true}))
@@ -0,0 +1,150 @@
+use image::{AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+use serde::Serialize;
+use std::io::Cursor;
+
+/// Longest edge images are downscaled to unless `VIRTUAL_AGENCY_MAX_IMAGE_DIMENSION` says otherwise
+pub const DEFAULT_MAX_IMAGE_DIMENSION: u32 = 2000;
+
+/// Quality used when re-encoding photos as JPEG
+const JPEG_QUALITY: u8 = 85;
+
+/// Decoder memory cap, so a tiny file can't expand into gigabytes of pixels
+const MAX_DECODE_ALLOC: u64 = 512 * 1024 * 1024;
+
+/// Formats the CLI accepts as-is when we can't decode them ourselves
+const PASSTHROUGH_TYPES: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// An image after normalization, ready to be stored as an attachment
+pub struct ProcessedImage {
+    pub data: Vec<u8>,
+    pub mime_type: String,
+    pub info: ImageProcessingInfo,
+}
+
+/// Reported back to the client for each image in a message
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageProcessingInfo {
+    /// Filled in once the processed image is stored
+    pub attachment_id: String,
+    pub original_mime_type: String,
+    pub original_bytes: usize,
+    pub processed_bytes: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_dimensions: Option<(u32, u32)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<(u32, u32)>,
+    pub mime_type: String,
+}
+
+/// Decode an image, downscale it so neither edge exceeds `max_dimension`, and re-encode it.
+/// Re-encoding drops EXIF and other metadata, so EXIF orientation is applied to the pixels
+/// first. JPEGs stay JPEG; everything else becomes PNG. Animated GIFs, which would lose all
+/// but their first frame, and images the decoder doesn't support are passed through if the
+/// CLI understands them.
+pub fn normalize(data: &[u8], sniffed_mime: &str, max_dimension: u32) -> Result<ProcessedImage, String> {
+    if sniffed_mime == "image/gif" && is_animated_gif(data) {
+        return Ok(passthrough(data, sniffed_mime));
+    }
+
+    let image = match decode(data) {
+        Ok(image) => image,
+        Err(e) if PASSTHROUGH_TYPES.contains(&sniffed_mime) => {
+            tracing::warn!("[images] Could not decode {} image, sending it unchanged: {}", sniffed_mime, e);
+            return Ok(passthrough(data, sniffed_mime));
+        }
+        Err(e) => return Err(format!("Unsupported image ({}): {}", sniffed_mime, e)),
+    };
+
+    let original_dimensions = (image.width(), image.height());
+    let image = if image.width() > max_dimension || image.height() > max_dimension {
+        image.resize(max_dimension, max_dimension, image::imageops::FilterType::Lanczos3)
+    } else {
+        image
+    };
+
+    let (processed, mime_type) = if sniffed_mime == "image/jpeg" {
+        (encode_jpeg(&image)?, "image/jpeg")
+    } else {
+        (encode_png(&image)?, "image/png")
+    };
+
+    Ok(ProcessedImage {
+        info: ImageProcessingInfo {
+            attachment_id: String::new(),
+            original_mime_type: sniffed_mime.to_string(),
+            original_bytes: data.len(),
+            processed_bytes: processed.len(),
+            original_dimensions: Some(original_dimensions),
+            dimensions: Some((image.width(), image.height())),
+            mime_type: mime_type.to_string(),
+        },
+        data: processed,
+        mime_type: mime_type.to_string(),
+    })
+}
+
+fn passthrough(data: &[u8], mime_type: &str) -> ProcessedImage {
+    ProcessedImage {
+        data: data.to_vec(),
+        mime_type: mime_type.to_string(),
+        info: ImageProcessingInfo {
+            attachment_id: String::new(),
+            original_mime_type: mime_type.to_string(),
+            original_bytes: data.len(),
+            processed_bytes: data.len(),
+            original_dimensions: None,
+            dimensions: None,
+            mime_type: mime_type.to_string(),
+        },
+    }
+}
+
+fn decode_limits() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    limits
+}
+
+fn decode(data: &[u8]) -> Result<DynamicImage, String> {
+    let mut reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?;
+    let mut limits = decode_limits();
+    reader.limits(limits.clone());
+    let mut decoder = reader.into_decoder().map_err(|e| e.to_string())?;
+    limits.reserve(decoder.total_bytes()).map_err(|e| e.to_string())?;
+
+    // Cameras store photos unrotated and record the rotation in EXIF
+    let orientation = decoder.orientation().map_err(|e| e.to_string())?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|e| e.to_string())?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// Whether a GIF has more than one frame; only the first two frames are decoded
+fn is_animated_gif(data: &[u8]) -> bool {
+    let Ok(mut decoder) = image::codecs::gif::GifDecoder::new(Cursor::new(data)) else {
+        return false;
+    };
+    if decoder.set_limits(decode_limits()).is_err() {
+        return false;
+    }
+    decoder.into_frames().take(2).count() > 1
+}
+
+fn encode_png(image: &DynamicImage) -> Result<Vec<u8>, String> {
+    let mut out = Cursor::new(Vec::new());
+    image
+        .write_to(&mut out, ImageFormat::Png)
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    Ok(out.into_inner())
+}
+
+fn encode_jpeg(image: &DynamicImage) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    // JPEG has no alpha channel
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY)
+        .encode_image(&image.to_rgb8())
+        .map_err(|e| format!("Failed to encode JPEG: {}", e))?;
+    Ok(out)
+}
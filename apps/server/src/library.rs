@@ -0,0 +1,157 @@
+use axum::http::StatusCode;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// A record kept in a `Library`
+pub trait LibraryItem: Clone + Serialize + DeserializeOwned {
+    /// File in the data dir the library is persisted to
+    const FILE: &'static str;
+    /// What the records are, for messages, e.g. `Persona`
+    const KIND: &'static str;
+
+    fn id(&self) -> &str;
+    fn set_id(&mut self, id: String);
+    /// Libraries are listed by name
+    fn name(&self) -> &str;
+
+    /// Contents of a library that has never been saved
+    fn defaults() -> Vec<Self> {
+        Vec::new()
+    }
+}
+
+#[derive(Debug)]
+pub enum LibraryError {
+    /// A record with the id being created already exists
+    Exists(String),
+    NotFound(String),
+    Failed(String),
+}
+
+impl From<LibraryError> for (StatusCode, String) {
+    fn from(error: LibraryError) -> Self {
+        match error {
+            LibraryError::Exists(e) => (StatusCode::CONFLICT, e),
+            LibraryError::NotFound(e) => (StatusCode::NOT_FOUND, e),
+            LibraryError::Failed(e) => (StatusCode::INTERNAL_SERVER_ERROR, e),
+        }
+    }
+}
+
+/// How a write treats a record that already has the item's id
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Existing {
+    Reject,
+    Require,
+    Replace,
+}
+
+/// Replace a file through a temporary file next to it, so a crash mid-write leaves
+/// the old contents rather than a truncated file
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+/// Named records persisted as a JSON array in the server data dir
+#[derive(Clone)]
+pub struct Library<T> {
+    path: PathBuf,
+    items: Arc<RwLock<HashMap<String, T>>>,
+}
+
+impl<T: LibraryItem> Library<T> {
+    /// An unreadable file is an error rather than an empty library, which the next
+    /// save would write over
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let path = data_dir.join(T::FILE);
+
+        let items = if path.exists() {
+            fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| serde_json::from_str::<Vec<T>>(&contents).map_err(|e| e.to_string()))
+                .map_err(|e| format!("Failed to load {}: {}; fix or move the file", path.display(), e))?
+        } else {
+            T::defaults()
+        };
+
+        Ok(Self {
+            path,
+            items: Arc::new(RwLock::new(items.into_iter().map(|i| (i.id().to_string(), i)).collect())),
+        })
+    }
+
+    pub fn get(&self, id: &str) -> Option<T> {
+        self.items.read().ok()?.get(id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<T> {
+        self.items.read().map(|items| sorted(&items)).unwrap_or_default()
+    }
+
+    /// Add a record, failing if its id is taken. Records without an id get one.
+    pub fn create(&self, item: T) -> Result<T, LibraryError> {
+        self.write(item, Existing::Reject)
+    }
+
+    /// Replace an existing record
+    pub fn update(&self, item: T) -> Result<T, LibraryError> {
+        self.write(item, Existing::Require)
+    }
+
+    /// Create a record, or replace it if the id already exists
+    pub fn upsert(&self, item: T) -> Result<T, LibraryError> {
+        self.write(item, Existing::Replace)
+    }
+
+    pub fn delete(&self, id: &str) -> Result<(), LibraryError> {
+        let mut items = self.items.write().map_err(|e| LibraryError::Failed(e.to_string()))?;
+        if items.remove(id).is_none() {
+            return Err(LibraryError::NotFound(format!("{} not found: {}", T::KIND, id)));
+        }
+        self.save(&items)
+    }
+
+    /// Checked and saved under the write lock, so concurrent writes can't interleave
+    fn write(&self, mut item: T, existing: Existing) -> Result<T, LibraryError> {
+        if item.id().is_empty() {
+            item.set_id(uuid::Uuid::new_v4().to_string());
+        }
+
+        let mut items = self.items.write().map_err(|e| LibraryError::Failed(e.to_string()))?;
+        match (existing, items.contains_key(item.id())) {
+            (Existing::Reject, true) => {
+                return Err(LibraryError::Exists(format!("{} already exists: {}", T::KIND, item.id())))
+            }
+            (Existing::Require, false) => {
+                return Err(LibraryError::NotFound(format!("{} not found: {}", T::KIND, item.id())))
+            }
+            _ => {}
+        }
+        items.insert(item.id().to_string(), item.clone());
+        self.save(&items)?;
+        Ok(item)
+    }
+
+    fn save(&self, items: &HashMap<String, T>) -> Result<(), LibraryError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| LibraryError::Failed(format!("Failed to create data dir: {}", e)))?;
+        }
+        let json = serde_json::to_string_pretty(&sorted(items))
+            .map_err(|e| LibraryError::Failed(format!("Failed to serialize {}: {}", T::FILE, e)))?;
+        write_atomic(&self.path, json.as_bytes())
+            .map_err(|e| LibraryError::Failed(format!("Failed to write {}: {}", T::FILE, e)))
+    }
+}
+
+fn sorted<T: LibraryItem>(items: &HashMap<String, T>) -> Vec<T> {
+    let mut items: Vec<T> = items.values().cloned().collect();
+    items.sort_by_key(|i| i.name().to_lowercase());
+    items
+}
@@ -1,29 +1,100 @@
 mod agents;
+mod attachments;
+mod audit;
+mod auth;
+mod config;
+mod conflicts;
+mod devices;
+mod events;
 mod files;
+mod images;
+mod library;
+mod mcp;
+mod openai;
+mod personas;
 mod pty;
+mod sandbox;
+mod secrets;
+mod sessions;
+mod shares;
+mod subscriptions;
+mod templates;
+mod tls;
 
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        DefaultBodyLimit, Path, Query, State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        DefaultBodyLimit, Extension, Multipart, Path, Query, State,
+    },
+    http::{header, HeaderName, HeaderValue, Method, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
     },
-    http::{header, HeaderValue, Method, StatusCode},
-    response::IntoResponse,
     routing::{delete, get, post},
     Json, Router,
 };
+use clap::Parser;
 use futures::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::{path::PathBuf, sync::Arc};
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
 use tokio::sync::{broadcast, RwLock};
-use tower_http::cors::CorsLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use agents::{AgentManager, AgentOutput, AgentStatusChange};
+use attachments::AttachmentStore;
+use audit::{AuditAction, AuditLog};
+use agents::{AgentManager, AgentOutput, AgentSettings, AgentSettingsUpdate, AgentStatusChange};
+use config::ServerConfig;
+use conflicts::{ConflictDetected, OverlappingAgent};
+use devices::DeviceStore;
+use events::EventJournal;
+use personas::PersonaStore;
+use sandbox::{AllowedRoots, SandboxError};
+use secrets::SecretStore;
+use shares::ShareStore;
+use subscriptions::{EventKind, SubscriptionChange, Subscriptions};
+use templates::TemplateStore;
 use pty::{TerminalManager, TerminalOutput};
 
 type SharedState = Arc<AppState>;
 
+/// Handler error: a status with a plain-text message, or a structured sandbox refusal
+enum ApiError {
+    Status(StatusCode, String),
+    Sandbox(SandboxError),
+}
+
+impl From<(StatusCode, String)> for ApiError {
+    fn from((status, message): (StatusCode, String)) -> Self {
+        ApiError::Status(status, message)
+    }
+}
+
+impl From<SandboxError> for ApiError {
+    fn from(e: SandboxError) -> Self {
+        ApiError::Sandbox(e)
+    }
+}
+
+impl From<ApiError> for openai::OpenAiError {
+    fn from(e: ApiError) -> Self {
+        match e {
+            ApiError::Status(status, message) => Self::new(status, message),
+            ApiError::Sandbox(e) => Self::new(e.status(), e.message),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            ApiError::Status(status, message) => (status, message).into_response(),
+            ApiError::Sandbox(e) => e.into_response(),
+        }
+    }
+}
+
 // Middleware to add Private Network Access headers for browser security
 async fn private_network_access_middleware(
     request: axum::http::Request<axum::body::Body>,
@@ -40,7 +111,7 @@ async fn private_network_access_middleware(
     // Ensure PATCH is included in allowed methods for preflight
     response.headers_mut().insert(
         "Access-Control-Allow-Methods",
-        HeaderValue::from_static("GET, POST, DELETE, PATCH, OPTIONS"),
+        HeaderValue::from_static("GET, POST, PUT, DELETE, PATCH, OPTIONS"),
     );
 
     response
@@ -51,7 +122,21 @@ struct AppState {
     terminal_manager: RwLock<TerminalManager>,
     broadcast_tx: broadcast::Sender<BroadcastMessage>,
     terminal_broadcast_tx: broadcast::Sender<TerminalOutput>,
-    workspace_dir: PathBuf,
+    /// Both channels above, numbered for Server-Sent Events
+    event_journal: EventJournal<BroadcastMessage>,
+    /// Directories agents, terminals, file operations and browsing are confined to
+    allowed_roots: AllowedRoots,
+    persona_store: PersonaStore,
+    template_store: TemplateStore,
+    secret_store: SecretStore,
+    attachment_store: AttachmentStore,
+    device_store: DeviceStore,
+    share_store: ShareStore,
+    audit_log: AuditLog,
+    /// Web UI address used in pairing links
+    ui_url: String,
+    /// Longest edge of images sent to agents
+    max_image_dimension: u32,
 }
 
 #[derive(Clone, Serialize)]
@@ -63,10 +148,44 @@ enum BroadcastMessage {
     AgentStatus(AgentStatusChange),
     #[serde(rename = "terminal-output")]
     TerminalOutput(TerminalOutput),
+    #[serde(rename = "conflict-detected")]
+    ConflictDetected(ConflictDetected),
+}
+
+impl BroadcastMessage {
+    fn kind(&self) -> EventKind {
+        match self {
+            BroadcastMessage::AgentOutput(_) => EventKind::AgentOutput,
+            BroadcastMessage::AgentStatus(_) => EventKind::AgentStatus,
+            BroadcastMessage::TerminalOutput(_) => EventKind::TerminalOutput,
+            BroadcastMessage::ConflictDetected(_) => EventKind::ConflictDetected,
+        }
+    }
+
+    /// Whether a `/ws` connection with these subscriptions should receive this message
+    fn subscribed_by(&self, subscriptions: &Subscriptions) -> bool {
+        match self {
+            BroadcastMessage::AgentOutput(output) => {
+                subscriptions.wants(EventKind::AgentOutput, &[&output.agent_id], None)
+            }
+            BroadcastMessage::AgentStatus(status) => {
+                subscriptions.wants(EventKind::AgentStatus, &[&status.agent_id], None)
+            }
+            BroadcastMessage::TerminalOutput(output) => {
+                subscriptions.wants(EventKind::TerminalOutput, &[], Some(&output.terminal_id))
+            }
+            BroadcastMessage::ConflictDetected(conflict) => subscriptions.wants(
+                EventKind::ConflictDetected,
+                &[&conflict.agent_id, &conflict.other_agent_id],
+                None,
+            ),
+        }
+    }
 }
 
-/// Incoming WebSocket messages from clients
-#[derive(Deserialize, Debug)]
+/// Incoming WebSocket messages from clients. Agent and terminal commands take the
+/// same fields as their REST counterparts.
+#[derive(Deserialize)]
 #[serde(tag = "type")]
 enum WsClientMessage {
     #[serde(rename = "terminal-input")]
@@ -77,39 +196,321 @@ enum WsClientMessage {
         cols: u16,
         rows: u16,
     },
+    #[serde(rename = "terminal-create")]
+    TerminalCreate(CreateTerminalRequest),
+    #[serde(rename = "terminal-kill")]
+    TerminalKill { terminal_id: String },
+    /// Also receive the named event kinds for the named agents and terminals
+    #[serde(rename = "subscribe")]
+    Subscribe(SubscriptionChange),
+    #[serde(rename = "unsubscribe")]
+    Unsubscribe(SubscriptionChange),
+    #[serde(rename = "agent-create")]
+    AgentCreate(CreateAgentRequest),
+    #[serde(rename = "agent-message")]
+    AgentMessage(WsAgentMessage),
+    #[serde(rename = "agent-stop")]
+    AgentStop { agent_id: String },
+    #[serde(rename = "agent-kill")]
+    AgentKill { agent_id: String },
+    #[serde(rename = "agent-settings")]
+    AgentSettings(WsAgentSettings),
+}
+
+#[derive(Deserialize)]
+struct WsAgentMessage {
+    agent_id: String,
+    #[serde(flatten)]
+    request: SendMessageRequest,
+}
+
+#[derive(Deserialize)]
+struct WsAgentSettings {
+    agent_id: String,
+    #[serde(flatten)]
+    update: AgentSettingsUpdate,
+}
+
+impl WsClientMessage {
+    /// The role the equivalent REST route needs
+    fn required_role(&self) -> auth::Role {
+        let (method, route) = match self {
+            WsClientMessage::Subscribe(_) | WsClientMessage::Unsubscribe(_) => return auth::Role::Viewer,
+            // Terminal input runs arbitrary commands, so viewers only watch
+            WsClientMessage::TerminalInput { .. } | WsClientMessage::TerminalResize { .. } => {
+                return auth::Role::Operator
+            }
+            WsClientMessage::TerminalCreate(_) => (Method::POST, "/api/terminals"),
+            WsClientMessage::TerminalKill { .. } => (Method::DELETE, "/api/terminals/:id"),
+            WsClientMessage::AgentCreate(_) => (Method::POST, "/api/agents"),
+            WsClientMessage::AgentMessage(_) => (Method::POST, "/api/agents/:id/messages"),
+            WsClientMessage::AgentStop { .. } => (Method::POST, "/api/agents/:id/stop"),
+            WsClientMessage::AgentKill { .. } => (Method::DELETE, "/api/agents/:id"),
+            WsClientMessage::AgentSettings(_) => (Method::PATCH, "/api/agents/:id"),
+        };
+        auth::required_role(&method, route)
+    }
+}
+
+/// A client message with an optional id that is echoed in its reply. Messages with an
+/// id get an `ack` or an `error` frame; messages without one only hear about errors.
+#[derive(Deserialize)]
+struct WsRequest {
+    #[serde(default)]
+    request_id: Option<String>,
+    #[serde(flatten)]
+    message: WsClientMessage,
+}
+
+/// Replies to client messages, sent alongside broadcasts on the same socket
+#[derive(Serialize)]
+#[serde(tag = "type")]
+enum WsReply {
+    #[serde(rename = "ack")]
+    Ack {
+        request_id: Option<String>,
+        status: u16,
+        #[serde(skip_serializing_if = "serde_json::Value::is_null")]
+        result: serde_json::Value,
+    },
+    #[serde(rename = "error")]
+    Error {
+        request_id: Option<String>,
+        status: u16,
+        message: String,
+        /// Structured error body, e.g. a sandbox refusal
+        #[serde(skip_serializing_if = "serde_json::Value::is_null")]
+        details: serde_json::Value,
+    },
 }
 
 #[tokio::main]
 async fn main() {
-    // Initialize tracing
+    // Settings come from flags, then environment, then the config file, then defaults
+    let cli = config::Cli::parse();
+    let print_config = cli.print_config;
+    // Some(token) when bridging MCP over stdio instead of serving
+    let mcp_bridge = cli.command.as_ref().map(|config::Command::Mcp { token }| token.clone());
+    let (config, config_file) = match ServerConfig::load(cli) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    if print_config {
+        match config.to_toml() {
+            Ok(toml) => print!("{}", toml),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Initialize tracing; stdout carries the protocol when bridging MCP over stdio
+    let log_writer = if mcp_bridge.is_some() {
+        tracing_subscriber::fmt::writer::BoxMakeWriter::new(std::io::stderr)
+    } else {
+        tracing_subscriber::fmt::writer::BoxMakeWriter::new(std::io::stdout)
+    };
     tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::try_from_default_env()
-            .unwrap_or_else(|_| "virtual_agency_server=debug,tower_http=debug".into()))
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::EnvFilter::try_new(&config.log_level)
+            .unwrap_or_else(|e| {
+                eprintln!("Invalid log level {:?}: {}", config.log_level, e);
+                std::process::exit(2);
+            }))
+        .with(tracing_subscriber::fmt::layer().with_writer(log_writer))
         .init();
 
+    if let Some(path) = &config_file {
+        tracing::info!("Loaded config file {}", path.display());
+    }
+    config::set_default_model(config.default_model.clone());
+
+    // The bridge only talks to the running server; it must not open the data dir's stores
+    if let Some(token) = mcp_bridge {
+        if let Err(e) = mcp::bridge_stdio(&config, token).await {
+            tracing::error!("[mcp] {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // Create broadcast channel for WebSocket clients
-    let (broadcast_tx, _) = broadcast::channel::<BroadcastMessage>(1000);
-    let (terminal_broadcast_tx, _) = broadcast::channel::<TerminalOutput>(1000);
+    let (broadcast_tx, _) = broadcast::channel::<BroadcastMessage>(config.broadcast_capacity);
+    let (terminal_broadcast_tx, _) = broadcast::channel::<TerminalOutput>(config.broadcast_capacity);
+
+    // Number every broadcast for event stream clients
+    let event_journal = EventJournal::new(config.broadcast_capacity);
+    {
+        let journal = event_journal.clone();
+        let mut agent_rx = broadcast_tx.subscribe();
+        let mut terminal_rx = terminal_broadcast_tx.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let msg = tokio::select! {
+                    msg = agent_rx.recv() => match msg {
+                        Ok(msg) => msg,
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            tracing::warn!("Event journal skipped {} agent events", n);
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    output = terminal_rx.recv() => match output {
+                        Ok(output) => BroadcastMessage::TerminalOutput(output),
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            tracing::warn!("Event journal skipped {} terminal events", n);
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                };
+                journal.push(msg);
+            }
+        });
+    }
+
+    let allowed_roots = match AllowedRoots::new(&config.effective_allowed_roots()) {
+        Ok(roots) => roots,
+        Err(e) => {
+            tracing::error!("Invalid allowed roots: {}", e);
+            std::process::exit(1);
+        }
+    };
+    for root in allowed_roots.roots() {
+        tracing::info!("Allowing agents, terminals and file access under {}", root.display());
+    }
+
+    // Server-side libraries (personas, ...) live in the data directory
+    let data_dir = config.data_dir.clone();
+    tracing::info!("Using data directory {}", data_dir.display());
+
+    let persona_store = match PersonaStore::load(&data_dir) {
+        Ok(store) => store,
+        Err(e) => {
+            tracing::error!("Failed to load persona store: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let template_store = match TemplateStore::load(&data_dir) {
+        Ok(store) => store,
+        Err(e) => {
+            tracing::error!("Failed to load template store: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let secret_store = match SecretStore::load(&data_dir) {
+        Ok(store) => store,
+        Err(e) => {
+            tracing::error!("Failed to load secret store: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // Get workspace directory from environment or use current directory
-    let workspace_dir = std::env::var("WORKSPACE_DIR")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")));
+    let attachment_store = match AttachmentStore::load(&data_dir) {
+        Ok(store) => store,
+        Err(e) => {
+            tracing::error!("Failed to load attachment store: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let device_store = match DeviceStore::load(&data_dir) {
+        Ok(store) => store,
+        Err(e) => {
+            tracing::error!("Failed to load device store: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let share_store = match ShareStore::load(&data_dir) {
+        Ok(store) => store,
+        Err(e) => {
+            tracing::error!("Failed to load share store: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let audit_log = match AuditLog::open(&data_dir) {
+        Ok(log) => log,
+        Err(e) => {
+            tracing::error!("Failed to open audit log: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let ui_url = config.effective_ui_url();
 
     let state = Arc::new(AppState {
-        agent_manager: RwLock::new(AgentManager::new(broadcast_tx.clone())),
+        agent_manager: RwLock::new(AgentManager::new(
+            broadcast_tx.clone(),
+            persona_store.clone(),
+            secret_store.clone(),
+            config.claude_path.clone(),
+        )),
         terminal_manager: RwLock::new(TerminalManager::new(terminal_broadcast_tx.clone())),
         broadcast_tx,
         terminal_broadcast_tx,
-        workspace_dir,
+        event_journal,
+        allowed_roots,
+        persona_store,
+        template_store,
+        secret_store,
+        attachment_store,
+        device_store: device_store.clone(),
+        share_store,
+        audit_log,
+        ui_url: ui_url.clone(),
+        max_image_dimension: config.max_image_dimension,
     });
 
+    // Periodically remove attachments that no live agent references any more. Agents
+    // don't survive a restart, so this also releases references left from a previous run.
+    let gc_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(attachments::GC_GRACE_PERIOD);
+        loop {
+            interval.tick().await;
+            if let Err(e) = collect_attachments(&gc_state).await {
+                tracing::error!("Attachment garbage collection failed: {}", e);
+            }
+        }
+    });
+
+    // Every API route and the WebSocket require the token in the data dir
+    let auth_token = match auth::AuthToken::load_or_create(&data_dir) {
+        Ok(token) => token,
+        Err(e) => {
+            tracing::error!("Failed to load auth token: {}", e);
+            std::process::exit(1);
+        }
+    };
+    tracing::info!("API clients must send the token in {}", data_dir.join("auth.token").display());
+    let authenticator = auth::Authenticator {
+        token: auth_token,
+        devices: device_store.clone(),
+    };
+
     // Build router with CORS and Private Network Access support
+    let mut cors_origins = config.cors_origins.clone();
+    if config.lan {
+        // Paired devices load the UI from the address in the pairing link
+        cors_origins.push(ui_url.clone());
+    }
+    let allow_origin = if cors_origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(cors_origins.iter().filter_map(|o| HeaderValue::from_str(o).ok()))
+    };
     let cors = CorsLayer::new()
-        .allow_origin(tower_http::cors::Any)
-        .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::PATCH, Method::OPTIONS])
-        .allow_headers([header::CONTENT_TYPE, header::ACCEPT, header::AUTHORIZATION])
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::PATCH, Method::OPTIONS])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::ACCEPT,
+            header::AUTHORIZATION,
+            LAST_EVENT_ID,
+        ])
         .expose_headers([header::CONTENT_TYPE]);
 
     let app = Router::new()
@@ -117,23 +518,114 @@ async fn main() {
         .route("/api/agents/:id", delete(kill_agent).patch(update_agent_settings))
         .route("/api/agents/:id/messages", post(send_message))
         .route("/api/agents/:id/stop", post(stop_agent))
+        .route("/api/agents/:id/run", post(run_agent))
+        .route("/api/agents/:id/share", post(create_share))
+        .route("/api/agents/:id/events", get(agent_events))
+        .route("/api/events", get(events))
+        .route("/api/shares", get(list_shares))
+        .route("/api/shares/:id", delete(revoke_share))
+        .route("/api/personas", get(list_personas).post(create_persona))
+        .route("/api/personas/:id", get(get_persona).put(update_persona).delete(delete_persona))
+        .route("/api/templates", get(list_templates).post(create_template))
+        .route("/api/templates/import", post(import_template))
+        .route("/api/templates/:id", get(get_template).put(update_template).delete(delete_template))
+        .route("/api/templates/:id/export", get(export_template))
+        .route(
+            "/api/attachments",
+            // Uploads are streamed to disk with a per-file limit instead of the global body limit
+            get(list_attachments).post(upload_attachments).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/api/attachments/gc", post(gc_attachments))
+        .route("/api/attachments/:id", get(get_attachment))
+        .route("/api/secrets", get(list_secrets))
+        .route("/api/secrets/:name", axum::routing::put(set_secret).delete(delete_secret))
         .route("/api/terminals", get(list_terminals).post(create_terminal))
         .route("/api/terminals/:id", delete(kill_terminal))
         .route("/api/files/tree/:agent_id", get(get_file_tree))
         .route("/api/files/read/:agent_id", post(read_file))
         .route("/api/files/write/:agent_id", post(write_file))
-        .route("/api/health", get(health_check))
         .route("/api/browse", get(browse_directory))
+        .route("/api/sessions", get(list_sessions))
+        .route("/api/sessions/:session_id", get(get_session))
+        .route("/api/audit", get(query_audit))
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/models", get(list_models))
+        .route("/mcp", post(mcp::http))
+        .route("/api/devices", get(list_devices))
+        .route("/api/devices/pairing-code", post(create_pairing_code))
+        .route("/api/devices/:id", delete(revoke_device).patch(update_device))
         .route("/ws", get(ws_handler))
-        .layer(DefaultBodyLimit::max(50 * 1024 * 1024)) // 50MB limit for large images
+        .route_layer(axum::middleware::from_fn_with_state(authenticator, auth::require_token))
+        // Left open so clients can detect the server before they have a token
+        .route("/api/health", get(health_check))
+        // Authenticated by the one-time pairing code instead
+        .route("/api/devices/pair", post(pair_device))
+        // Authenticated by the signed share token in the path
+        .route("/api/shared/:token", get(get_shared_agent))
+        .route("/ws/shared/:token", get(shared_ws_handler))
+        .layer(DefaultBodyLimit::max(config.max_body_bytes))
         .layer(cors)
         .layer(axum::middleware::from_fn(private_network_access_middleware))
         .with_state(state);
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3001").await.unwrap();
-    tracing::info!("Virtual Agency server listening on http://127.0.0.1:3001");
+    let addr = format!("{}:{}", config.host, config.port);
+    let listener = match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("Failed to bind {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+
+    let tls = if config.tls {
+        let mut hostnames = vec![config.host.clone()];
+        hostnames.extend(config::lan_address());
+        hostnames.retain(|h| h != "0.0.0.0" && h != "::");
+        match tls::load_or_generate(config.tls_cert.as_deref(), config.tls_key.as_deref(), &data_dir, &hostnames) {
+            Ok(material) => Some(material),
+            Err(e) => {
+                tracing::error!("Failed to set up TLS: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    tracing::info!("Virtual Agency server listening on {}://{}", scheme, addr);
+    if let Some(material) = &tls {
+        tracing::info!("Using TLS certificate {}", material.cert_path.display());
+        // Printed outside the log so it's visible whatever the log level
+        println!("  TLS certificate SHA-256 fingerprint: {}", material.fingerprint);
+    }
+
+    if config.lan {
+        match device_store.new_pairing_code(config.pairing_role) {
+            Ok(code) => devices::print_pairing_code(&code, &pairing_url(&ui_url, &code.code)),
+            Err(e) => tracing::error!("Failed to create pairing code: {}", e),
+        }
+    }
 
-    axum::serve(listener, app).await.unwrap();
+    match tls {
+        Some(material) => {
+            // Only the ring provider is compiled in; install it before building the config
+            let _ = rustls::crypto::ring::default_provider().install_default();
+            let rustls_config = match axum_server::tls_rustls::RustlsConfig::from_pem(material.cert_pem, material.key_pem).await {
+                Ok(rustls_config) => rustls_config,
+                Err(e) => {
+                    tracing::error!("Invalid TLS certificate or key: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let listener = listener.into_std().unwrap();
+            axum_server::from_tcp_rustls(listener, rustls_config)
+                .serve(app.into_make_service())
+                .await
+                .unwrap();
+        }
+        None => axum::serve(listener, app).await.unwrap(),
+    }
 }
 
 async fn health_check() -> Json<serde_json::Value> {
@@ -157,21 +649,21 @@ struct BrowseResponse {
     current_path: String,
     parent_path: Option<String>,
     entries: Vec<DirEntry>,
+    /// Allowed roots, so clients can offer them as starting points
+    roots: Vec<String>,
 }
 
 async fn browse_directory(
+    State(state): State<SharedState>,
     Query(query): Query<BrowseQuery>,
-) -> Result<Json<BrowseResponse>, (StatusCode, String)> {
-    let path = query.path
-        .map(PathBuf::from)
-        .unwrap_or_else(|| dirs::home_dir().unwrap_or_else(|| PathBuf::from("/")));
-
-    if !path.exists() {
-        return Err((StatusCode::NOT_FOUND, "Path does not exist".to_string()));
-    }
+) -> Result<Json<BrowseResponse>, ApiError> {
+    let path = match query.path {
+        Some(path) => state.allowed_roots.check(path)?,
+        None => state.allowed_roots.default_root().to_path_buf(),
+    };
 
     if !path.is_dir() {
-        return Err((StatusCode::BAD_REQUEST, "Path is not a directory".to_string()));
+        return Err((StatusCode::BAD_REQUEST, "Path is not a directory".to_string()).into());
     }
 
     let mut entries = Vec::new();
@@ -197,109 +689,170 @@ async fn browse_directory(
             }
         }
         Err(e) => {
-            return Err((StatusCode::FORBIDDEN, format!("Cannot read directory: {}", e)));
+            return Err((StatusCode::FORBIDDEN, format!("Cannot read directory: {}", e)).into());
         }
     }
 
     // Sort directories alphabetically
     entries.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
 
-    let parent_path = path.parent().map(|p| p.to_string_lossy().to_string());
+    // Don't offer to navigate above the allowed roots
+    let parent_path = path
+        .parent()
+        .filter(|p| state.allowed_roots.contains(p))
+        .map(|p| p.to_string_lossy().to_string());
 
     Ok(Json(BrowseResponse {
         current_path: path.to_string_lossy().to_string(),
         parent_path,
         entries,
+        roots: state.allowed_roots.roots().iter().map(|r| r.to_string_lossy().to_string()).collect(),
     }))
 }
 
+// Session browser endpoints
+async fn list_sessions(
+    State(state): State<SharedState>,
+    Query(query): Query<sessions::SessionQuery>,
+) -> Result<Json<Vec<sessions::SessionSummary>>, ApiError> {
+    // Transcripts are only readable for projects inside the allowed roots
+    let working_dir = state.allowed_roots.check(&query.working_dir)?;
+    sessions::list_sessions(&working_dir.to_string_lossy())
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e).into())
+}
+
+async fn get_session(
+    State(state): State<SharedState>,
+    Path(session_id): Path<String>,
+    Query(query): Query<sessions::SessionQuery>,
+) -> Result<Json<sessions::SessionDetail>, ApiError> {
+    let working_dir = state.allowed_roots.check(&query.working_dir)?;
+    match sessions::get_session(&working_dir.to_string_lossy(), &session_id).await {
+        Ok(Some(detail)) => Ok(Json(detail)),
+        Ok(None) => Err((StatusCode::NOT_FOUND, "Session not found".to_string()).into()),
+        Err(e) => Err((StatusCode::BAD_REQUEST, e).into()),
+    }
+}
+
 // File system endpoints
 async fn get_file_tree(
     State(state): State<SharedState>,
     Path(agent_id): Path<String>,
-) -> Result<Json<files::FileNode>, (StatusCode, String)> {
+) -> Result<Json<files::FileNode>, ApiError> {
     // Get agent's working directory
     let manager = state.agent_manager.read().await;
     let agents = manager.list_agents();
 
     let working_dir = agents
         .iter()
-        .find(|(id, _, _, _, _, _)| id == &agent_id)
-        .map(|(_, _, working_dir, _, _, _)| PathBuf::from(working_dir))
+        .find(|agent| agent.id == agent_id)
+        .map(|agent| PathBuf::from(&agent.working_dir))
         .ok_or_else(|| (StatusCode::NOT_FOUND, "Agent not found".to_string()))?;
 
     drop(manager);
 
+    let working_dir = state.allowed_roots.check(&working_dir)?;
+
     files::get_file_tree(&working_dir)
         .await
         .map(Json)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into())
 }
 
 async fn read_file(
     State(state): State<SharedState>,
     Path(agent_id): Path<String>,
     Json(req): Json<files::ReadFileRequest>,
-) -> Result<Json<files::FileContent>, (StatusCode, String)> {
+) -> Result<Json<files::FileContent>, ApiError> {
     // Get agent's working directory
     let manager = state.agent_manager.read().await;
     let agents = manager.list_agents();
 
     let working_dir = agents
         .iter()
-        .find(|(id, _, _, _, _, _)| id == &agent_id)
-        .map(|(_, _, working_dir, _, _, _)| PathBuf::from(working_dir))
+        .find(|agent| agent.id == agent_id)
+        .map(|agent| PathBuf::from(&agent.working_dir))
         .ok_or_else(|| (StatusCode::NOT_FOUND, "Agent not found".to_string()))?;
 
     drop(manager);
 
-    files::read_file(&working_dir, req)
+    let working_dir = state.allowed_roots.check(&working_dir)?;
+    let path = sandbox::resolve_existing(&working_dir, &req.path)?;
+    files::read_file(&path)
         .await
         .map(Json)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into())
 }
 
 async fn write_file(
     State(state): State<SharedState>,
+    Extension(identity): Extension<auth::Identity>,
     Path(agent_id): Path<String>,
     Json(req): Json<files::WriteFileRequest>,
-) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+) -> Result<Json<serde_json::Value>, ApiError> {
     // Get agent's working directory
     let manager = state.agent_manager.read().await;
     let agents = manager.list_agents();
 
     let working_dir = agents
         .iter()
-        .find(|(id, _, _, _, _, _)| id == &agent_id)
-        .map(|(_, _, working_dir, _, _, _)| PathBuf::from(working_dir))
+        .find(|agent| agent.id == agent_id)
+        .map(|agent| PathBuf::from(&agent.working_dir))
         .ok_or_else(|| (StatusCode::NOT_FOUND, "Agent not found".to_string()))?;
 
     drop(manager);
 
-    files::write_file(&working_dir, req)
+    let working_dir = state.allowed_roots.check(&working_dir)?;
+    let path = sandbox::resolve_for_write(&working_dir, &req.path)?;
+    let result = files::write_file(&path, &req.content)
         .await
-        .map(Json)
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    state.audit_log.record(
+        &identity,
+        AuditAction::FileWrite,
+        Some(&agent_id),
+        serde_json::json!({ "path": path, "bytes": req.content.len() }),
+    );
+    Ok(Json(result))
 }
 
-#[derive(Deserialize)]
+#[derive(Default, Deserialize)]
 struct CreateAgentRequest {
     #[serde(default)]
     id: Option<String>,
     name: String,
     working_dir: String,
-    #[serde(default = "default_model")]
-    model: String,
+    /// Template providing defaults; the fields below override it
+    #[serde(default)]
+    template_id: Option<String>,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    thinking_enabled: Option<bool>,
+    #[serde(default)]
+    mcp_servers: Option<Vec<String>>,
+    #[serde(default)]
+    allowed_tools: Option<Vec<String>>,
+    #[serde(default)]
+    disallowed_tools: Option<Vec<String>>,
+    #[serde(default)]
+    system_prompt: Option<String>,
     #[serde(default)]
-    thinking_enabled: bool,
+    append_system_prompt: Option<String>,
     #[serde(default)]
-    mcp_servers: Vec<String>,
+    persona_id: Option<String>,
+    #[serde(default)]
+    env: Option<BTreeMap<String, String>>,
+    /// Variable name -> secret name; values are resolved at spawn time
+    #[serde(default)]
+    secret_env: Option<BTreeMap<String, String>>,
     #[serde(default)]
     session_id: Option<String>, // Session ID to resume conversation
-}
-
-fn default_model() -> String {
-    "sonnet".to_string()
+    #[serde(default)]
+    fork_session: bool, // Branch off session_id instead of continuing it
 }
 
 #[derive(Serialize)]
@@ -307,45 +860,111 @@ struct AgentInfo {
     id: String,
     name: String,
     working_dir: String,
-    model: String,
-    thinking_enabled: bool,
-    mcp_servers: Vec<String>,
+    #[serde(flatten)]
+    settings: AgentSettings,
+    /// Other agents sharing or nesting this agent's working directory (only set on create)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    overlapping_agents: Vec<OverlappingAgent>,
+    running: bool,
+    /// Summed cost of the agent's runs, in USD
+    total_cost_usd: f64,
 }
 
 async fn create_agent(
     State(state): State<SharedState>,
+    Extension(identity): Extension<auth::Identity>,
     Json(req): Json<CreateAgentRequest>,
-) -> Result<Json<AgentInfo>, (StatusCode, String)> {
+) -> Result<Json<AgentInfo>, ApiError> {
     tracing::info!(
-        "[create_agent] Received request - id: {:?}, name: {}, working_dir: {}, model: {}, thinking: {}, mcp_servers: {:?}, session_id: {:?}",
-        req.id, req.name, req.working_dir, req.model, req.thinking_enabled, req.mcp_servers, req.session_id
+        "[create_agent] Received request - id: {:?}, name: {}, working_dir: {}, template: {:?}, model: {:?}, thinking: {:?}, mcp_servers: {:?}, persona: {:?}, session_id: {:?}, fork_session: {}",
+        req.id, req.name, req.working_dir, req.template_id, req.model, req.thinking_enabled, req.mcp_servers, req.persona_id, req.session_id, req.fork_session
     );
 
+    let template = match req.template_id.as_deref() {
+        Some(template_id) => Some(
+            state
+                .template_store
+                .get(template_id)
+                .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("Template not found: {}", template_id)))?,
+        ),
+        None => None,
+    };
+
+    if let Some(ref sid) = req.session_id {
+        if !sessions::is_valid_session_id(sid) {
+            return Err((StatusCode::BAD_REQUEST, "Invalid session id".to_string()).into());
+        }
+    }
+
+    state.allowed_roots.check(&req.working_dir)?;
+
     let mut manager = state.agent_manager.write().await;
 
+    let overlapping_agents = manager.overlapping_agents(&req.working_dir, req.id.as_deref());
+
+    // Explicit request fields override the template, which overrides the defaults
+    let settings = AgentSettings {
+        model: req.model
+            .or_else(|| template.as_ref().map(|t| t.model.clone()))
+            .unwrap_or_else(config::default_model),
+        thinking_enabled: req.thinking_enabled
+            .or_else(|| template.as_ref().map(|t| t.thinking_enabled))
+            .unwrap_or(false),
+        mcp_servers: req.mcp_servers
+            .or_else(|| template.as_ref().map(|t| t.mcp_servers.clone()))
+            .unwrap_or_default(),
+        allowed_tools: req.allowed_tools
+            .or_else(|| template.as_ref().map(|t| t.allowed_tools.clone()))
+            .unwrap_or_default(),
+        disallowed_tools: req.disallowed_tools
+            .or_else(|| template.as_ref().map(|t| t.disallowed_tools.clone()))
+            .unwrap_or_default(),
+        system_prompt: req.system_prompt
+            .or_else(|| template.as_ref().and_then(|t| t.system_prompt.clone()))
+            .filter(|p| !p.is_empty()),
+        append_system_prompt: req.append_system_prompt
+            .or_else(|| template.as_ref().and_then(|t| t.append_system_prompt.clone()))
+            .filter(|p| !p.is_empty()),
+        persona_id: req.persona_id
+            .or_else(|| template.as_ref().and_then(|t| t.persona_id.clone()))
+            .filter(|p| !p.is_empty()),
+        env: req.env.unwrap_or_default(),
+        secret_env: req.secret_env.unwrap_or_default(),
+    };
+
     match manager.create_agent(
         req.id.as_deref(),
         &req.name,
         &req.working_dir,
-        &req.model,
-        req.thinking_enabled,
-        req.mcp_servers.clone(),
-        req.session_id.clone(),
+        settings.clone(),
+        req.session_id,
+        req.fork_session,
     ) {
         Ok(id) => {
             tracing::info!("[create_agent] Successfully created agent with id: {}", id);
+            state.audit_log.record(
+                &identity,
+                AuditAction::AgentCreate,
+                Some(&id),
+                serde_json::json!({
+                    "name": req.name,
+                    "working_dir": req.working_dir,
+                    "settings": audit::redact_env(serde_json::to_value(&settings).unwrap_or_default()),
+                }),
+            );
             Ok(Json(AgentInfo {
                 id,
                 name: req.name,
                 working_dir: req.working_dir,
-                model: req.model,
-                thinking_enabled: req.thinking_enabled,
-                mcp_servers: req.mcp_servers,
+                settings,
+                overlapping_agents,
+                running: false,
+                total_cost_usd: 0.0,
             }))
         },
         Err(e) => {
             tracing::error!("[create_agent] Failed to create agent: {}", e);
-            Err((StatusCode::INTERNAL_SERVER_ERROR, e))
+            Err((StatusCode::INTERNAL_SERVER_ERROR, e).into())
         },
     }
 }
@@ -353,50 +972,58 @@ async fn create_agent(
 async fn list_agents(State(state): State<SharedState>) -> Json<Vec<AgentInfo>> {
     let manager = state.agent_manager.read().await;
     let agents = manager.list_agents();
-    Json(agents.into_iter().map(|(id, name, working_dir, model, thinking_enabled, mcp_servers)| AgentInfo {
-        id,
-        name,
-        working_dir,
-        model,
-        thinking_enabled,
-        mcp_servers,
+    Json(agents.into_iter().map(|agent| AgentInfo {
+        id: agent.id,
+        name: agent.name,
+        working_dir: agent.working_dir,
+        settings: agent.settings,
+        overlapping_agents: Vec::new(),
+        running: agent.running,
+        total_cost_usd: agent.total_cost_usd,
     }).collect())
 }
 
 async fn kill_agent(
     State(state): State<SharedState>,
+    Extension(identity): Extension<auth::Identity>,
     Path(id): Path<String>,
 ) -> Result<StatusCode, (StatusCode, String)> {
     let mut manager = state.agent_manager.write().await;
 
     match manager.kill_agent(&id) {
-        Ok(_) => Ok(StatusCode::NO_CONTENT),
+        Ok(_) => {
+            state.audit_log.record(&identity, AuditAction::AgentKill, Some(&id), serde_json::Value::Null);
+            // The agent's transcript no longer holds its attachments; the periodic GC removes them
+            if let Err(e) = state.attachment_store.release_agent(&id) {
+                tracing::error!("[kill_agent] Failed to release attachments for {}: {}", id, e);
+            }
+            if let Err(e) = state.share_store.revoke_agent(&id) {
+                tracing::error!("[kill_agent] Failed to revoke share links for {}: {}", id, e);
+            }
+            Ok(StatusCode::NO_CONTENT)
+        }
         Err(e) => Err((StatusCode::NOT_FOUND, e)),
     }
 }
 
-#[derive(Deserialize)]
-struct UpdateAgentRequest {
-    model: Option<String>,
-    thinking_enabled: Option<bool>,
-    mcp_servers: Option<Vec<String>>,
-}
-
 async fn update_agent_settings(
     State(state): State<SharedState>,
+    Extension(identity): Extension<auth::Identity>,
     Path(id): Path<String>,
-    Json(req): Json<UpdateAgentRequest>,
+    Json(req): Json<AgentSettingsUpdate>,
 ) -> Result<StatusCode, (StatusCode, String)> {
     tracing::info!(
-        "[update_agent_settings] Updating agent {} - model: {:?}, thinking: {:?}, mcp_servers: {:?}",
-        id, req.model, req.thinking_enabled, req.mcp_servers
+        "[update_agent_settings] Updating agent {} - model: {:?}, thinking: {:?}, mcp_servers: {:?}, persona: {:?}",
+        id, req.model, req.thinking_enabled, req.mcp_servers, req.persona_id
     );
 
+    let details = audit::redact_env(serde_json::to_value(&req).unwrap_or_default());
     let mut manager = state.agent_manager.write().await;
 
-    match manager.update_agent_settings(&id, req.model, req.thinking_enabled, req.mcp_servers) {
+    match manager.update_agent_settings(&id, req) {
         Ok(_) => {
             tracing::info!("[update_agent_settings] Successfully updated agent: {}", id);
+            state.audit_log.record(&identity, AuditAction::AgentSettings, Some(&id), details);
             Ok(StatusCode::OK)
         },
         Err(e) => {
@@ -406,118 +1033,1291 @@ async fn update_agent_settings(
     }
 }
 
-#[derive(Deserialize)]
-struct ImageData {
-    data: String,      // base64 encoded
-    mime_type: String, // e.g., "image/png"
-}
-
-#[derive(Deserialize)]
-struct SendMessageRequest {
-    message: String,
-    #[serde(default)]
-    images: Vec<ImageData>,
+// Persona library endpoints
+async fn list_personas(State(state): State<SharedState>) -> Json<Vec<personas::Persona>> {
+    Json(state.persona_store.list())
 }
 
-async fn send_message(
+async fn get_persona(
     State(state): State<SharedState>,
     Path(id): Path<String>,
-    Json(req): Json<SendMessageRequest>,
-) -> Result<StatusCode, (StatusCode, String)> {
-    tracing::info!("[send_message] Attempting to send message to agent: {}", id);
-
-    let manager = state.agent_manager.read().await;
-    let existing_agents = manager.list_agents();
-    tracing::info!("[send_message] Existing agents: {:?}", existing_agents.iter().map(|(id, _, _, _, _, _)| id).collect::<Vec<_>>());
+) -> Result<Json<personas::Persona>, (StatusCode, String)> {
+    state
+        .persona_store
+        .get(&id)
+        .map(Json)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Persona not found: {}", id)))
+}
 
-    // Convert base64 images to temp files
-    let mut image_paths: Vec<String> = Vec::new();
-    for (i, img) in req.images.iter().enumerate() {
-        match save_base64_image(&img.data, &img.mime_type, i) {
-            Ok(path) => {
-                tracing::info!("[send_message] Saved image {} to: {}", i, path);
-                image_paths.push(path);
-            }
-            Err(e) => {
-                tracing::error!("[send_message] Failed to save image {}: {}", i, e);
-            }
-        }
-    }
+async fn create_persona(
+    State(state): State<SharedState>,
+    Json(req): Json<personas::PersonaRequest>,
+) -> Result<Json<personas::Persona>, (StatusCode, String)> {
+    state
+        .persona_store
+        .create(req.into_persona(None))
+        .map(Json)
+        .map_err(Into::into)
+}
 
-    match manager.send_message(&id, &req.message, &image_paths) {
-        Ok(_) => {
-            tracing::info!("[send_message] Successfully sent message to agent: {}", id);
-            Ok(StatusCode::ACCEPTED)
-        },
-        Err(e) => {
-            tracing::error!("[send_message] Failed: {}", e);
-            Err((StatusCode::NOT_FOUND, e))
-        },
-    }
+async fn update_persona(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Json(req): Json<personas::PersonaRequest>,
+) -> Result<Json<personas::Persona>, (StatusCode, String)> {
+    state
+        .persona_store
+        .update(req.into_persona(Some(id)))
+        .map(Json)
+        .map_err(Into::into)
 }
 
-fn save_base64_image(base64_data: &str, mime_type: &str, index: usize) -> Result<String, String> {
-    use base64::{Engine as _, engine::general_purpose::STANDARD};
-    use std::io::Write;
+async fn delete_persona(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    state
+        .persona_store
+        .delete(&id)
+        .map(|_| StatusCode::NO_CONTENT)
+        .map_err(Into::into)
+}
 
-    // Decode base64
-    let decoded = STANDARD.decode(base64_data)
-        .map_err(|e| format!("Failed to decode base64: {}", e))?;
-
-    // Determine extension from mime type
-    let extension = match mime_type {
-        "image/png" => "png",
-        "image/jpeg" | "image/jpg" => "jpg",
-        "image/gif" => "gif",
-        "image/webp" => "webp",
-        "image/bmp" => "bmp",
-        _ => "png",
-    };
+// Agent template endpoints
+async fn list_templates(State(state): State<SharedState>) -> Json<Vec<templates::AgentTemplate>> {
+    Json(state.template_store.list())
+}
 
-    // Create temp file
-    let temp_dir = std::env::temp_dir();
-    let filename = format!("virtual-agency-image-{}-{}.{}", std::process::id(), index, extension);
-    let file_path = temp_dir.join(&filename);
+async fn get_template(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<templates::AgentTemplate>, (StatusCode, String)> {
+    state
+        .template_store
+        .get(&id)
+        .map(Json)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Template not found: {}", id)))
+}
 
-    // Write to file
-    let mut file = std::fs::File::create(&file_path)
-        .map_err(|e| format!("Failed to create temp file: {}", e))?;
-    file.write_all(&decoded)
-        .map_err(|e| format!("Failed to write image data: {}", e))?;
+async fn create_template(
+    State(state): State<SharedState>,
+    Json(template): Json<templates::AgentTemplate>,
+) -> Result<Json<templates::AgentTemplate>, (StatusCode, String)> {
+    state
+        .template_store
+        .create(template)
+        .map(Json)
+        .map_err(Into::into)
+}
 
-    Ok(file_path.to_string_lossy().to_string())
+async fn update_template(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Json(mut template): Json<templates::AgentTemplate>,
+) -> Result<Json<templates::AgentTemplate>, (StatusCode, String)> {
+    template.id = id;
+    state
+        .template_store
+        .update(template)
+        .map(Json)
+        .map_err(Into::into)
 }
 
-async fn stop_agent(
+async fn delete_template(
     State(state): State<SharedState>,
     Path(id): Path<String>,
 ) -> Result<StatusCode, (StatusCode, String)> {
-    tracing::info!("[stop_agent] Stopping agent: {}", id);
-
-    let manager = state.agent_manager.read().await;
+    state
+        .template_store
+        .delete(&id)
+        .map(|_| StatusCode::NO_CONTENT)
+        .map_err(Into::into)
+}
 
-    match manager.stop_agent(&id) {
-        Ok(_) => {
-            tracing::info!("[stop_agent] Successfully stopped agent: {}", id);
-            Ok(StatusCode::OK)
-        },
-        Err(e) => {
-            tracing::error!("[stop_agent] Failed: {}", e);
-            Err((StatusCode::NOT_FOUND, e))
-        },
-    }
+async fn export_template(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let template = state
+        .template_store
+        .get(&id)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Template not found: {}", id)))?;
+
+    let json = serde_json::to_string_pretty(&templates::TemplateFile::new(template))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let filename: String = id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.template.json\"", filename)),
+        ],
+        json,
+    ))
 }
 
-// Terminal endpoints
 #[derive(Deserialize)]
-struct CreateTerminalRequest {
+struct ImportTemplateQuery {
     #[serde(default)]
-    id: Option<String>,
+    overwrite: bool,
+}
+
+async fn import_template(
+    State(state): State<SharedState>,
+    Query(query): Query<ImportTemplateQuery>,
+    Json(file): Json<templates::TemplateFile>,
+) -> Result<Json<templates::AgentTemplate>, (StatusCode, String)> {
+    let template = file.into_template().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let imported = if query.overwrite {
+        state.template_store.upsert(template)
+    } else {
+        state.template_store.create(template)
+    };
+    imported.map(Json).map_err(Into::into)
+}
+
+// Secret store endpoints; values can be written but are never returned
+async fn list_secrets(State(state): State<SharedState>) -> Json<Vec<secrets::SecretInfo>> {
+    Json(state.secret_store.list())
+}
+
+#[derive(Deserialize)]
+struct SetSecretRequest {
+    value: String,
+}
+
+async fn set_secret(
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+    Json(req): Json<SetSecretRequest>,
+) -> Result<Json<secrets::SecretInfo>, (StatusCode, String)> {
+    if !secrets::is_valid_secret_name(&name) {
+        return Err((StatusCode::BAD_REQUEST, format!("Invalid secret name: {}", name)));
+    }
+
+    tracing::info!("[set_secret] Storing secret {}", name);
+
+    state
+        .secret_store
+        .set(&name, &req.value)
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+async fn delete_secret(
+    State(state): State<SharedState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    tracing::info!("[delete_secret] Deleting secret {}", name);
+
+    state
+        .secret_store
+        .delete(&name)
+        .map(|_| StatusCode::NO_CONTENT)
+        .map_err(|e| (StatusCode::NOT_FOUND, e))
+}
+
+/// The client also sends `mime_type`, but the stored type is sniffed from the data
+#[derive(Deserialize)]
+struct ImageData {
+    data: String, // base64 encoded
+}
+
+#[derive(Deserialize)]
+struct SendMessageRequest {
+    message: String,
+    #[serde(default)]
+    images: Vec<ImageData>,
+    /// Ids returned by the multipart upload endpoint
+    #[serde(default)]
+    attachment_ids: Vec<String>,
+}
+
+async fn send_message(
+    State(state): State<SharedState>,
+    Extension(identity): Extension<auth::Identity>,
+    Path(id): Path<String>,
+    Json(req): Json<SendMessageRequest>,
+) -> Result<(StatusCode, Json<SendMessageResponse>), (StatusCode, String)> {
+    let (processed_images, _) = start_run(&state, &identity, &id, req).await?;
+    Ok((StatusCode::ACCEPTED, Json(SendMessageResponse { images: processed_images })))
+}
+
+/// Store a message's images and attachments and start the agent's run. Returns what
+/// happened to each image and a receiver for how the run ends.
+async fn start_run(
+    state: &SharedState,
+    identity: &auth::Identity,
+    id: &str,
+    req: SendMessageRequest,
+) -> Result<(Vec<images::ImageProcessingInfo>, tokio::sync::oneshot::Receiver<agents::RunOutcome>), (StatusCode, String)> {
+    tracing::info!("[send_message] Attempting to send message to agent: {}", id);
+
+    // Normalize and store images before taking the agent lock; decoding is CPU-bound
+    let mut attachments = Vec::new();
+    let mut processed_images = Vec::new();
+    for (i, img) in req.images.into_iter().enumerate() {
+        let store = state.attachment_store.clone();
+        let max_dimension = state.max_image_dimension;
+        let (attachment, info) =
+            tokio::task::spawn_blocking(move || store_base64_image(&store, &img.data, max_dimension))
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+                .map_err(|(status, e)| {
+                    tracing::error!("[send_message] Rejected image {}: {}", i, e);
+                    (status, format!("Image {}: {}", i, e))
+                })?;
+        tracing::info!(
+            "[send_message] Stored image {} as attachment {} ({} -> {} bytes)",
+            i, attachment.id, info.original_bytes, info.processed_bytes
+        );
+        attachments.push(attachment);
+        processed_images.push(info);
+    }
+    for attachment_id in &req.attachment_ids {
+        let attachment = state
+            .attachment_store
+            .get(attachment_id)
+            .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("Attachment not found: {}", attachment_id)))?;
+        if !attachment.mime_type.starts_with("image/") {
+            attachments.push(attachment);
+            continue;
+        }
+
+        // Uploaded images are normalized like inline ones; the message references the result
+        let store = state.attachment_store.clone();
+        let max_dimension = state.max_image_dimension;
+        let (processed, info) = tokio::task::spawn_blocking(move || {
+            let data = std::fs::read(&attachment.path)
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read attachment: {}", e)))?;
+            store_image(&store, &data, max_dimension)
+        })
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|(status, e)| {
+            tracing::error!("[send_message] Rejected attachment {}: {}", attachment_id, e);
+            (status, format!("Attachment {}: {}", attachment_id, e))
+        })?;
+        tracing::info!(
+            "[send_message] Normalized attachment {} as {} ({} -> {} bytes)",
+            attachment_id, processed.id, info.original_bytes, info.processed_bytes
+        );
+        attachments.push(processed);
+        processed_images.push(info);
+    }
+
+    // Small text files go straight into the prompt; everything else is passed by path
+    let prompt = req.message.clone();
+    let mut message = req.message;
+    let mut file_paths: Vec<String> = Vec::new();
+    for attachment in &attachments {
+        if attachment.mime_type.starts_with("text/") && attachment.size <= attachments::MAX_INLINE_TEXT_BYTES {
+            let content = std::fs::read_to_string(&attachment.path)
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read attachment: {}", e)))?;
+            let name = attachment.original_name.as_deref().unwrap_or(&attachment.id);
+            message.push_str(&format!("\n\n<attached_file name=\"{}\">\n{}\n</attached_file>", name, content));
+        } else {
+            file_paths.push(attachment.path.to_string_lossy().to_string());
+        }
+    }
+
+    let manager = state.agent_manager.read().await;
+    let existing_agents = manager.list_agents();
+    tracing::info!("[send_message] Existing agents: {:?}", existing_agents.iter().map(|agent| &agent.id).collect::<Vec<_>>());
+
+    match manager.send_message(id, &message, &file_paths) {
+        Ok(done) => {
+            tracing::info!("[send_message] Successfully sent message to agent: {}", id);
+            let ids: Vec<String> = attachments.into_iter().map(|a| a.id).collect();
+            state.audit_log.record(
+                identity,
+                AuditAction::Prompt,
+                Some(id),
+                serde_json::json!({ "message": prompt, "attachment_ids": &ids }),
+            );
+            let store = state.attachment_store.clone();
+            let agent_id = id.to_string();
+            let recorded = tokio::task::spawn_blocking(move || store.add_references(&ids, &agent_id))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|result| result);
+            if let Err(e) = recorded {
+                tracing::error!("[send_message] Failed to record attachment references: {}", e);
+            }
+            Ok((processed_images, done))
+        },
+        Err(agents::SendError::Busy) => {
+            tracing::warn!("[send_message] Agent {} is already running", id);
+            Err((StatusCode::CONFLICT, agents::SendError::Busy.to_string()))
+        }
+        Err(e) => {
+            tracing::error!("[send_message] Failed: {}", e);
+            let status = match e {
+                agents::SendError::NotFound(_) => StatusCode::NOT_FOUND,
+                agents::SendError::MissingSecret(_) => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Err((status, e.to_string()))
+        },
+    }
+}
+
+#[derive(Serialize)]
+struct SendMessageResponse {
+    /// Original and processed size of each image, in request order: inline images,
+    /// then image attachments
+    images: Vec<images::ImageProcessingInfo>,
+}
+
+/// How long `/run` waits for a run when the request doesn't say
+const DEFAULT_RUN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// Longest wait a `/run` request can ask for
+const MAX_RUN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+#[derive(Deserialize)]
+struct RunRequest {
+    #[serde(flatten)]
+    message: SendMessageRequest,
+    /// Seconds to wait before stopping the run
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+    /// Stream the agent's events as NDJSON ahead of the result, as does
+    /// `Accept: application/x-ndjson`
+    #[serde(default)]
+    stream: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum RunStatus {
+    Completed,
+    /// The CLI exited non-zero or reported an error result
+    Failed,
+    /// Stopped or killed before it finished
+    Stopped,
+    /// Stopped because the timeout elapsed
+    TimedOut,
+}
+
+/// Everything a script needs from a finished run; the last line of a streamed run
+#[derive(Serialize)]
+struct RunResponse {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    agent_id: String,
+    status: RunStatus,
+    exit_code: Option<i32>,
+    /// Final assistant text
+    text: Option<String>,
+    /// The CLI's final `result` message, as emitted
+    result: Option<serde_json::Value>,
+    usage: Option<serde_json::Value>,
+    total_cost_usd: Option<f64>,
+    session_id: Option<String>,
+    changed_files: Vec<String>,
+    duration_ms: u64,
+    images: Vec<images::ImageProcessingInfo>,
+}
+
+impl RunResponse {
+    /// `outcome` is None when the run timed out
+    fn new(
+        agent_id: &str,
+        outcome: Option<agents::RunOutcome>,
+        started: std::time::Instant,
+        images: Vec<images::ImageProcessingInfo>,
+    ) -> Self {
+        let timed_out = outcome.is_none();
+        let outcome = outcome.unwrap_or_default();
+        let result = outcome.result;
+        let field = |name: &str| result.as_ref().and_then(|r| r.get(name)).cloned();
+        let is_error = field("is_error").and_then(|v| v.as_bool()).unwrap_or(false);
+
+        let status = match outcome.exit_code {
+            _ if timed_out => RunStatus::TimedOut,
+            None => RunStatus::Stopped,
+            Some(0) if !is_error => RunStatus::Completed,
+            Some(_) => RunStatus::Failed,
+        };
+
+        Self {
+            kind: "run-result",
+            agent_id: agent_id.to_string(),
+            status,
+            exit_code: outcome.exit_code,
+            text: field("result").and_then(|v| v.as_str().map(str::to_string)),
+            usage: field("usage"),
+            total_cost_usd: field("total_cost_usd").and_then(|v| v.as_f64()),
+            session_id: field("session_id").and_then(|v| v.as_str().map(str::to_string)),
+            result,
+            changed_files: outcome.changed_files,
+            duration_ms: started.elapsed().as_millis() as u64,
+            images,
+        }
+    }
+}
+
+/// Send a message and wait for the run to finish, for scripts. Timed-out runs are
+/// stopped and answered with 504.
+async fn run_agent(
+    State(state): State<SharedState>,
+    Extension(identity): Extension<auth::Identity>,
+    Path(id): Path<String>,
+    headers: axum::http::HeaderMap,
+    Json(req): Json<RunRequest>,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    let timeout = req
+        .timeout_secs
+        .map(std::time::Duration::from_secs)
+        .unwrap_or(DEFAULT_RUN_TIMEOUT)
+        .min(MAX_RUN_TIMEOUT);
+    let stream = req.stream
+        || headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|accept| accept.contains("application/x-ndjson"));
+
+    // Subscribe before starting so none of the run's events are missed
+    let agent_rx = state.broadcast_tx.subscribe();
+    let started = std::time::Instant::now();
+    let (images, done) = start_run(&state, &identity, &id, req.message).await?;
+
+    if !stream {
+        let outcome = follow_run(&state, &id, done, timeout, None).await;
+        let response = RunResponse::new(&id, outcome, started, images);
+        let status = if response.status == RunStatus::TimedOut {
+            StatusCode::GATEWAY_TIMEOUT
+        } else {
+            StatusCode::OK
+        };
+        return Ok((status, Json(response)).into_response());
+    }
+
+    let (line_tx, line_rx) = tokio::sync::mpsc::channel::<String>(256);
+    tokio::spawn(async move {
+        let outcome = follow_run(&state, &id, done, timeout, Some((agent_rx, line_tx.clone()))).await;
+        if let Ok(line) = serde_json::to_string(&RunResponse::new(&id, outcome, started, images)) {
+            let _ = line_tx.send(line + "\n").await;
+        }
+    });
+    let body = futures::stream::unfold(line_rx, |mut rx| async move {
+        rx.recv().await.map(|line| (Ok::<_, std::convert::Infallible>(line), rx))
+    });
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        axum::body::Body::from_stream(body),
+    )
+        .into_response())
+}
+
+/// Wait for a run to end, stopping it if the timeout elapses first (returns None then).
+/// With `forward`, the agent's broadcasts are sent on as NDJSON lines meanwhile.
+async fn follow_run(
+    state: &SharedState,
+    agent_id: &str,
+    mut done: tokio::sync::oneshot::Receiver<agents::RunOutcome>,
+    timeout: std::time::Duration,
+    forward: Option<(broadcast::Receiver<BroadcastMessage>, tokio::sync::mpsc::Sender<String>)>,
+) -> Option<agents::RunOutcome> {
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+
+    // A dropped sender means the run never got to report, e.g. it was killed
+    let finished = match forward {
+        None => tokio::select! {
+            outcome = &mut done => Some(outcome.unwrap_or_default()),
+            _ = &mut deadline => None,
+        },
+        Some((mut agent_rx, lines)) => {
+            let mut subscriptions = Subscriptions::default();
+            subscriptions.subscribe(SubscriptionChange {
+                agents: vec![agent_id.to_string()],
+                ..Default::default()
+            });
+            let forward_line = |msg: BroadcastMessage| {
+                let lines = lines.clone();
+                let line = msg
+                    .subscribed_by(&subscriptions)
+                    .then(|| serde_json::to_string(&msg).ok())
+                    .flatten();
+                async move {
+                    if let Some(line) = line {
+                        let _ = lines.send(line + "\n").await;
+                    }
+                }
+            };
+
+            loop {
+                tokio::select! {
+                    outcome = &mut done => {
+                        // Everything the run printed was broadcast before it reported
+                        loop {
+                            match agent_rx.try_recv() {
+                                Ok(msg) => forward_line(msg).await,
+                                Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                                Err(_) => break,
+                            }
+                        }
+                        break Some(outcome.unwrap_or_default());
+                    }
+                    msg = agent_rx.recv() => {
+                        if let Ok(msg) = msg {
+                            forward_line(msg).await;
+                        }
+                    }
+                    _ = &mut deadline => break None,
+                }
+            }
+        }
+    };
+
+    if finished.is_none() {
+        tracing::warn!("[run_agent] Run of {} timed out after {:?}, stopping it", agent_id, timeout);
+        if let Err(e) = state.agent_manager.read().await.stop_agent(agent_id) {
+            tracing::error!("[run_agent] Failed to stop {}: {}", agent_id, e);
+        }
+    }
+    finished
+}
+
+/// What a chat completion's `model` selects
+enum ChatTarget {
+    Agent(String),
+    /// Run on a temporary agent made from the template
+    Template(Box<templates::AgentTemplate>),
+}
+
+/// Resolve `agent:<id or name>`, `template:<id or name>`, or a bare id or name with
+/// agents taking precedence
+async fn resolve_chat_model(state: &SharedState, model: &str) -> Result<ChatTarget, openai::OpenAiError> {
+    let (kind, name) = match model.split_once(':') {
+        Some((kind @ ("agent" | "template"), name)) => (Some(kind), name),
+        _ => (None, model),
+    };
+
+    if kind != Some("template") {
+        let agents = state.agent_manager.read().await.list_agents();
+        if let Some(agent) = agents.iter().find(|agent| agent.id == name) {
+            return Ok(ChatTarget::Agent(agent.id.clone()));
+        }
+        let named: Vec<_> = agents.iter().filter(|agent| agent.name == name).collect();
+        match named.as_slice() {
+            [agent] => return Ok(ChatTarget::Agent(agent.id.clone())),
+            [] => {}
+            _ => {
+                return Err(openai::OpenAiError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("Several agents are named {}; use agent:<id>", name),
+                ))
+            }
+        }
+    }
+
+    if kind != Some("agent") {
+        let template = state
+            .template_store
+            .get(name)
+            .or_else(|| state.template_store.list().into_iter().find(|t| t.name == name));
+        if let Some(template) = template {
+            return Ok(ChatTarget::Template(Box::new(template)));
+        }
+    }
+
+    Err(openai::OpenAiError::new(
+        StatusCode::NOT_FOUND,
+        format!("The model {} does not match an agent or template", model),
+    ))
+}
+
+/// The error a chat completion reports for a run, if it didn't finish normally
+fn chat_run_error(run: &RunResponse, timeout: std::time::Duration) -> Option<openai::OpenAiError> {
+    match run.status {
+        RunStatus::Completed => None,
+        RunStatus::Stopped => Some(openai::OpenAiError::new(
+            StatusCode::CONFLICT,
+            "The agent run was stopped before it finished",
+        )),
+        RunStatus::TimedOut => Some(openai::OpenAiError::new(
+            StatusCode::GATEWAY_TIMEOUT,
+            format!("The agent didn't finish within {} seconds and was stopped", timeout.as_secs()),
+        )),
+        RunStatus::Failed => Some(openai::OpenAiError::new(
+            StatusCode::BAD_GATEWAY,
+            match (&run.exit_code, &run.text) {
+                (_, Some(text)) => format!("The agent run failed: {}", text),
+                (Some(code), None) => format!("The agent run failed with exit code {}", code),
+                (None, None) => "The agent run failed".to_string(),
+            },
+        )),
+    }
+}
+
+async fn remove_chat_agent(state: &SharedState, identity: &auth::Identity, agent_id: &str) {
+    if let Err((_, e)) = kill_agent(State(state.clone()), Extension(identity.clone()), Path(agent_id.to_string())).await {
+        tracing::error!("[chat_completions] Failed to remove temporary agent {}: {}", agent_id, e);
+    }
+}
+
+fn chat_event(data: &impl Serialize) -> Event {
+    Event::default()
+        .json_data(data)
+        .unwrap_or_else(|e| Event::default().comment(format!("failed to encode chunk: {}", e)))
+}
+
+/// OpenAI-compatible chat completions, so tools that speak that API can use agents
+/// unchanged. Existing agents only receive the last user message since they keep their
+/// own conversation and prompts. A template runs on a temporary agent, given the whole
+/// transcript and the system messages, which needs the admin role like creating one and
+/// a `working_dir` for it to run in.
+async fn chat_completions(
+    State(state): State<SharedState>,
+    Extension(identity): Extension<auth::Identity>,
+    Json(req): Json<openai::ChatCompletionRequest>,
+) -> Result<axum::response::Response, openai::OpenAiError> {
+    let target = resolve_chat_model(&state, &req.model).await?;
+    let prompt = openai::prompt(&req.messages, matches!(target, ChatTarget::Template(_)))
+        .map_err(|e| openai::OpenAiError::new(StatusCode::BAD_REQUEST, e))?;
+    // An existing agent's system prompt is part of its settings, not of one request
+    if matches!(target, ChatTarget::Agent(_)) && prompt.system.is_some() {
+        return Err(openai::OpenAiError::new(
+            StatusCode::BAD_REQUEST,
+            "System and developer messages can't be applied to an existing agent; set its append_system_prompt instead",
+        ));
+    }
+
+    let (agent_id, temporary) = match target {
+        ChatTarget::Agent(id) => (id, false),
+        ChatTarget::Template(template) => {
+            if identity.role < auth::Role::Admin {
+                return Err(openai::OpenAiError::new(
+                    StatusCode::FORBIDDEN,
+                    "Running a template creates an agent, which requires the admin role",
+                ));
+            }
+            let Some(working_dir) = req.working_dir.clone() else {
+                return Err(openai::OpenAiError::new(
+                    StatusCode::BAD_REQUEST,
+                    "Running a template needs a working_dir for its agent",
+                ));
+            };
+            let append_system_prompt: Vec<String> =
+                [template.append_system_prompt, prompt.system].into_iter().flatten().collect();
+            let create = CreateAgentRequest {
+                name: format!("{} (API)", template.name),
+                working_dir,
+                template_id: Some(template.id),
+                append_system_prompt: Some(append_system_prompt.join("\n\n")),
+                ..Default::default()
+            };
+            let Json(agent) = create_agent(State(state.clone()), Extension(identity.clone()), Json(create)).await?;
+            (agent.id, true)
+        }
+    };
+
+    let message = SendMessageRequest {
+        message: prompt.message,
+        images: prompt.images.into_iter().map(|data| ImageData { data }).collect(),
+        attachment_ids: Vec::new(),
+    };
+    // Subscribe before starting so none of the run's events are missed
+    let agent_rx = state.broadcast_tx.subscribe();
+    let timeout = DEFAULT_RUN_TIMEOUT;
+    let started = std::time::Instant::now();
+    let (images, done) = match start_run(&state, &identity, &agent_id, message).await {
+        Ok(run) => run,
+        Err(e) => {
+            if temporary {
+                remove_chat_agent(&state, &identity, &agent_id).await;
+            }
+            return Err(e.into());
+        }
+    };
+
+    if !req.stream {
+        // Followed in a task so a client hanging up doesn't leave a temporary agent behind
+        let run = tokio::spawn(async move {
+            let outcome = follow_run(&state, &agent_id, done, timeout, None).await;
+            if temporary {
+                remove_chat_agent(&state, &identity, &agent_id).await;
+            }
+            RunResponse::new(&agent_id, outcome, started, images)
+        })
+        .await
+        .map_err(|e| openai::OpenAiError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        if let Some(error) = chat_run_error(&run, timeout) {
+            return Err(error);
+        }
+        let usage = run.usage.as_ref().map(openai::Usage::from_claude);
+        return Ok(Json(openai::ChatCompletion::new(req.model, run.text.unwrap_or_default(), usage)).into_response());
+    }
+
+    let include_usage = req.stream_options.is_some_and(|options| options.include_usage);
+    let chunks = openai::ChunkBuilder::new(req.model);
+    let (event_tx, event_rx) = tokio::sync::mpsc::channel::<Event>(256);
+    tokio::spawn(async move {
+        let role = openai::Delta {
+            role: Some("assistant"),
+            ..Default::default()
+        };
+        let _ = event_tx.send(chat_event(&chunks.delta(role, None))).await;
+
+        // The CLI reports whole text blocks, each sent as one content delta
+        let (line_tx, mut line_rx) = tokio::sync::mpsc::channel::<String>(256);
+        let relay = async {
+            let mut first = true;
+            while let Some(line) = line_rx.recv().await {
+                let text = serde_json::from_str::<serde_json::Value>(&line)
+                    .ok()
+                    .filter(|msg| msg["type"] == "agent-output" && msg["stream"] == "stdout")
+                    .and_then(|msg| serde_json::from_str(msg["data"].as_str()?).ok())
+                    .and_then(|json: serde_json::Value| openai::assistant_text(&json));
+                let Some(text) = text else {
+                    continue;
+                };
+                let content = if first { text } else { format!("\n\n{}", text) };
+                first = false;
+                let delta = openai::Delta {
+                    content: Some(content),
+                    ..Default::default()
+                };
+                let _ = event_tx.send(chat_event(&chunks.delta(delta, None))).await;
+            }
+            !first
+        };
+        let follow = follow_run(&state, &agent_id, done, timeout, Some((agent_rx, line_tx)));
+        let (outcome, streamed_text) = tokio::join!(follow, relay);
+        if temporary {
+            remove_chat_agent(&state, &identity, &agent_id).await;
+        }
+
+        let run = RunResponse::new(&agent_id, outcome, started, images);
+        match chat_run_error(&run, timeout) {
+            None => {
+                // Runs that printed no text blocks still have their result text
+                if let Some(text) = run.text.filter(|_| !streamed_text) {
+                    let delta = openai::Delta {
+                        content: Some(text),
+                        ..Default::default()
+                    };
+                    let _ = event_tx.send(chat_event(&chunks.delta(delta, None))).await;
+                }
+                let _ = event_tx.send(chat_event(&chunks.delta(openai::Delta::default(), Some("stop")))).await;
+                if include_usage {
+                    let usage = openai::Usage::from_claude(run.usage.as_ref().unwrap_or(&serde_json::Value::Null));
+                    let _ = event_tx.send(chat_event(&chunks.usage(usage))).await;
+                }
+            }
+            Some(error) => {
+                let _ = event_tx.send(chat_event(&error.body())).await;
+            }
+        }
+        let _ = event_tx.send(Event::default().data("[DONE]")).await;
+    });
+
+    let stream = futures::stream::unfold(event_rx, |mut rx| async move {
+        rx.recv().await.map(|event| (Ok::<_, std::convert::Infallible>(event), rx))
+    });
+    Ok(Sse::new(stream)
+        .keep_alive(KeepAlive::new().interval(SSE_HEARTBEAT_INTERVAL))
+        .into_response())
+}
+
+/// Agents and templates as OpenAI models, for clients that list them
+async fn list_models(State(state): State<SharedState>) -> Json<openai::ModelList> {
+    let agents = state.agent_manager.read().await.list_agents();
+    let agents = agents.into_iter().map(|agent| openai::Model {
+        id: format!("agent:{}", agent.id),
+        object: "model",
+        created: 0,
+        owned_by: "virtual-agency",
+        name: agent.name,
+    });
+    let templates = state.template_store.list().into_iter().map(|template| openai::Model {
+        id: format!("template:{}", template.id),
+        object: "model",
+        created: 0,
+        owned_by: "virtual-agency",
+        name: template.name,
+    });
+
+    Json(openai::ModelList {
+        object: "list",
+        data: agents.chain(templates).collect(),
+    })
+}
+
+/// Decode, normalize and store one base64 image from a message
+fn store_base64_image(
+    store: &AttachmentStore,
+    base64_data: &str,
+    max_dimension: u32,
+) -> Result<(attachments::Attachment, images::ImageProcessingInfo), (StatusCode, String)> {
+    use base64::{Engine as _, engine::general_purpose::STANDARD};
+
+    let decoded = STANDARD.decode(base64_data)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Failed to decode base64: {}", e)))?;
+
+    if decoded.len() > attachments::MAX_ATTACHMENT_BYTES {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Image exceeds the {} byte limit", attachments::MAX_ATTACHMENT_BYTES),
+        ));
+    }
+
+    store_image(store, &decoded, max_dimension)
+}
+
+/// Normalize an image and store the result
+fn store_image(
+    store: &AttachmentStore,
+    data: &[u8],
+    max_dimension: u32,
+) -> Result<(attachments::Attachment, images::ImageProcessingInfo), (StatusCode, String)> {
+    let mime_type = attachments::sniff_mime(data);
+    if !mime_type.starts_with("image/") {
+        return Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("Not an image (detected {})", mime_type)));
+    }
+
+    let processed = images::normalize(data, &mime_type, max_dimension)
+        .map_err(|e| (StatusCode::UNSUPPORTED_MEDIA_TYPE, e))?;
+    let attachment = store.store(&processed.data, &processed.mime_type, None)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    let mut info = processed.info;
+    info.attachment_id = attachment.id.clone();
+    Ok((attachment, info))
+}
+
+/// Most files accepted in one multipart upload
+const MAX_UPLOAD_FILES: usize = 20;
+
+async fn upload_attachments(
+    State(state): State<SharedState>,
+    mut multipart: Multipart,
+) -> Result<Json<Vec<attachments::Attachment>>, (StatusCode, String)> {
+    let mut uploaded = Vec::new();
+
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?
+    {
+        // Plain form fields carry no file
+        let Some(file_name) = field.file_name().map(|n| n.to_string()) else {
+            continue;
+        };
+
+        if uploaded.len() >= MAX_UPLOAD_FILES {
+            return Err((StatusCode::BAD_REQUEST, format!("At most {} files per upload", MAX_UPLOAD_FILES)));
+        }
+
+        let mut upload = state
+            .attachment_store
+            .begin_upload()
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+        loop {
+            let chunk = match field.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(e) => {
+                    upload.discard().await;
+                    return Err((StatusCode::BAD_REQUEST, e.to_string()));
+                }
+            };
+            if upload.size() + chunk.len() > attachments::MAX_ATTACHMENT_BYTES {
+                upload.discard().await;
+                return Err((
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("{} exceeds the {} byte limit", file_name, attachments::MAX_ATTACHMENT_BYTES),
+                ));
+            }
+            if let Err(e) = upload.write(&chunk).await {
+                upload.discard().await;
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, e));
+            }
+        }
+
+        let attachment = state
+            .attachment_store
+            .finish_upload(upload, Some(&file_name))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        tracing::info!(
+            "[upload_attachments] Stored {} as {} ({}, {} bytes)",
+            file_name, attachment.id, attachment.mime_type, attachment.size
+        );
+        uploaded.push(attachment);
+    }
+
+    Ok(Json(uploaded))
+}
+
+// Audit log endpoint
+async fn query_audit(
+    State(state): State<SharedState>,
+    Query(query): Query<audit::AuditQuery>,
+) -> Result<Json<Vec<audit::AuditEntry>>, (StatusCode, String)> {
+    let audit_log = state.audit_log.clone();
+    tokio::task::spawn_blocking(move || audit_log.query(&query))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+// Device pairing endpoints
+fn pairing_url(ui_url: &str, code: &str) -> String {
+    format!("{}/?pair={}", ui_url, code)
+}
+
+#[derive(Deserialize)]
+struct PairDeviceRequest {
+    code: String,
+    #[serde(default)]
+    name: String,
+}
+
+async fn pair_device(
+    State(state): State<SharedState>,
+    Json(req): Json<PairDeviceRequest>,
+) -> Result<Json<devices::PairedDevice>, (StatusCode, String)> {
+    state
+        .device_store
+        .pair(&req.code, &req.name)
+        .map(Json)
+        .map_err(|e| {
+            tracing::warn!("[pair_device] Pairing failed: {}", e);
+            (StatusCode::UNAUTHORIZED, e)
+        })
+}
+
+#[derive(Serialize)]
+struct PairingCodeResponse {
+    #[serde(flatten)]
+    code: devices::PairingCode,
+    url: String,
+}
+
+#[derive(Deserialize, Default)]
+struct PairingCodeRequest {
+    #[serde(default)]
+    role: auth::Role,
+}
+
+async fn create_pairing_code(
+    State(state): State<SharedState>,
+    req: Option<Json<PairingCodeRequest>>,
+) -> Result<Json<PairingCodeResponse>, (StatusCode, String)> {
+    let Json(req) = req.unwrap_or_default();
+    let code = state
+        .device_store
+        .new_pairing_code(req.role)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    let url = pairing_url(&state.ui_url, &code.code);
+    devices::print_pairing_code(&code, &url);
+    Ok(Json(PairingCodeResponse { code, url }))
+}
+
+async fn list_devices(State(state): State<SharedState>) -> Json<Vec<devices::DeviceInfo>> {
+    Json(state.device_store.list())
+}
+
+#[derive(Deserialize)]
+struct UpdateDeviceRequest {
+    role: auth::Role,
+}
+
+async fn update_device(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Json(req): Json<UpdateDeviceRequest>,
+) -> Result<Json<devices::DeviceInfo>, (StatusCode, String)> {
+    state
+        .device_store
+        .set_role(&id, req.role)
+        .map(Json)
+        .map_err(|e| (StatusCode::NOT_FOUND, e))
+}
+
+async fn revoke_device(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    state
+        .device_store
+        .revoke(&id)
+        .map(|_| StatusCode::NO_CONTENT)
+        .map_err(|e| (StatusCode::NOT_FOUND, e))
+}
+
+// Share link endpoints
+#[derive(Deserialize, Default)]
+struct CreateShareRequest {
+    /// Lifetime in seconds
+    #[serde(default)]
+    ttl_secs: Option<u64>,
+    #[serde(default)]
+    label: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CreateShareResponse {
+    #[serde(flatten)]
+    share: shares::CreatedShare,
+    /// Read-only endpoints the token unlocks
+    history_url: String,
+    ws_url: String,
+}
+
+async fn create_share(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    req: Option<Json<CreateShareRequest>>,
+) -> Result<Json<CreateShareResponse>, (StatusCode, String)> {
+    let Json(req) = req.unwrap_or_default();
+
+    let exists = state.agent_manager.read().await.list_agents().iter().any(|agent| agent.id == id);
+    if !exists {
+        return Err((StatusCode::NOT_FOUND, format!("Agent not found: {}", id)));
+    }
+
+    let ttl = req
+        .ttl_secs
+        .map(std::time::Duration::from_secs)
+        .unwrap_or(shares::DEFAULT_SHARE_TTL);
+    let share = state
+        .share_store
+        .create(&id, ttl, req.label)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    tracing::info!("[create_share] Shared agent {} until {}", id, share.share.expires_at);
+
+    Ok(Json(CreateShareResponse {
+        history_url: format!("/api/shared/{}", share.token),
+        ws_url: format!("/ws/shared/{}", share.token),
+        share,
+    }))
+}
+
+#[derive(Deserialize)]
+struct ListSharesQuery {
+    agent: Option<String>,
+}
+
+async fn list_shares(
+    State(state): State<SharedState>,
+    Query(query): Query<ListSharesQuery>,
+) -> Json<Vec<shares::Share>> {
+    Json(state.share_store.list(query.agent.as_deref()))
+}
+
+async fn revoke_share(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    state
+        .share_store
+        .revoke(&id)
+        .map(|_| StatusCode::NO_CONTENT)
+        .map_err(|e| (StatusCode::NOT_FOUND, e))
+}
+
+/// What a share link shows: the agent and its conversation so far
+#[derive(Serialize)]
+struct SharedAgent {
+    id: String,
+    name: String,
+    model: String,
+    expires_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    history: Option<sessions::SessionDetail>,
+}
+
+async fn get_shared_agent(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+) -> Result<Json<SharedAgent>, (StatusCode, String)> {
+    let share = state
+        .share_store
+        .verify(&token)
+        .ok_or_else(|| (StatusCode::UNAUTHORIZED, "Invalid or expired share link".to_string()))?;
+
+    let agent = state
+        .agent_manager
+        .read()
+        .await
+        .list_agents()
+        .into_iter()
+        .find(|agent| agent.id == share.agent_id)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Shared agent no longer exists".to_string()))?;
+
+    let history = match &agent.session_id {
+        Some(session_id) => sessions::get_session(&agent.working_dir, session_id)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?,
+        None => None,
+    };
+
+    Ok(Json(SharedAgent {
+        id: agent.id,
+        name: agent.name,
+        model: agent.settings.model,
+        expires_at: share.expires_at,
+        history,
+    }))
+}
+
+async fn shared_ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let share = state
+        .share_store
+        .verify(&token)
+        .ok_or_else(|| (StatusCode::UNAUTHORIZED, "Invalid or expired share link".to_string()))?;
+    Ok(ws.on_upgrade(move |socket| handle_shared_socket(socket, state, token, share.agent_id)))
+}
+
+/// How often a shared WebSocket re-checks that its link is still valid
+const SHARE_RECHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Forward only the shared agent's events; anything the viewer sends is ignored
+async fn handle_shared_socket(socket: WebSocket, state: SharedState, token: String, agent_id: String) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut agent_rx = state.broadcast_tx.subscribe();
+    let mut recheck = tokio::time::interval(SHARE_RECHECK_INTERVAL);
+
+    tracing::debug!("Shared WebSocket opened for agent {}", agent_id);
+
+    loop {
+        tokio::select! {
+            result = agent_rx.recv() => {
+                let msg = match result {
+                    Ok(msg) => msg,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let relevant = match &msg {
+                    BroadcastMessage::AgentOutput(output) => output.agent_id == agent_id,
+                    BroadcastMessage::AgentStatus(status) => status.agent_id == agent_id,
+                    // Conflicts name other agents, which the link doesn't cover
+                    _ => false,
+                };
+                if !relevant {
+                    continue;
+                }
+                if let Ok(json) = serde_json::to_string(&msg) {
+                    if sender.send(Message::Text(json)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            // Close the stream once the link expires or is revoked
+            _ = recheck.tick() => {
+                if state.share_store.verify(&token).is_none() {
+                    let _ = sender.send(Message::Close(None)).await;
+                    break;
+                }
+            }
+            incoming = receiver.next() => {
+                match incoming {
+                    Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+
+    tracing::debug!("Shared WebSocket closed for agent {}", agent_id);
+}
+
+// Attachment store endpoints
+async fn list_attachments(State(state): State<SharedState>) -> Json<Vec<attachments::Attachment>> {
+    Json(state.attachment_store.list())
+}
+
+async fn get_attachment(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<Json<attachments::Attachment>, (StatusCode, String)> {
+    state
+        .attachment_store
+        .get(&id)
+        .map(Json)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Attachment not found: {}", id)))
+}
+
+async fn gc_attachments(
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    collect_attachments(&state)
+        .await
+        .map(|removed| Json(serde_json::json!({ "removed": removed })))
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+/// Run attachment GC against the agents alive now, off the async runtime
+async fn collect_attachments(state: &AppState) -> Result<Vec<String>, String> {
+    let live_agents = state
+        .agent_manager
+        .read()
+        .await
+        .list_agents()
+        .into_iter()
+        .map(|agent| agent.id)
+        .collect();
+    let store = state.attachment_store.clone();
+    tokio::task::spawn_blocking(move || store.gc(&live_agents, attachments::GC_GRACE_PERIOD))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+async fn stop_agent(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    tracing::info!("[stop_agent] Stopping agent: {}", id);
+
+    let manager = state.agent_manager.read().await;
+
+    match manager.stop_agent(&id) {
+        Ok(_) => {
+            tracing::info!("[stop_agent] Successfully stopped agent: {}", id);
+            Ok(StatusCode::OK)
+        },
+        Err(e) => {
+            tracing::error!("[stop_agent] Failed: {}", e);
+            Err((StatusCode::NOT_FOUND, e))
+        },
+    }
+}
+
+// Terminal endpoints
+#[derive(Deserialize)]
+struct CreateTerminalRequest {
+    #[serde(default)]
+    id: Option<String>,
     working_dir: String,
     #[serde(default = "default_cols")]
     cols: u16,
     #[serde(default = "default_rows")]
     rows: u16,
+    /// Inherit this agent's environment, including its secrets
+    #[serde(default)]
+    agent_id: Option<String>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    /// Variable name -> secret name
+    #[serde(default)]
+    secret_env: BTreeMap<String, String>,
 }
 
 fn default_cols() -> u16 {
@@ -536,8 +2336,9 @@ struct TerminalInfo {
 
 async fn create_terminal(
     State(state): State<SharedState>,
+    Extension(identity): Extension<auth::Identity>,
     Json(req): Json<CreateTerminalRequest>,
-) -> Result<Json<TerminalInfo>, (StatusCode, String)> {
+) -> Result<Json<TerminalInfo>, ApiError> {
     tracing::info!(
         "[create_terminal] Creating terminal in {} ({}x{})",
         req.working_dir,
@@ -545,11 +2346,40 @@ async fn create_terminal(
         req.rows
     );
 
+    secrets::validate_env(&req.env, &req.secret_env).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    state.allowed_roots.check(&req.working_dir)?;
+
+    // Request variables override the ones inherited from the agent
+    let (mut env, mut secret_env) = (BTreeMap::new(), BTreeMap::new());
+    if let Some(ref agent_id) = req.agent_id {
+        let manager = state.agent_manager.read().await;
+        let agent = manager
+            .list_agents()
+            .into_iter()
+            .find(|agent| &agent.id == agent_id)
+            .ok_or_else(|| (StatusCode::NOT_FOUND, "Agent not found".to_string()))?;
+        env = agent.settings.env;
+        secret_env = agent.settings.secret_env;
+    }
+    env.extend(req.env);
+    secret_env.extend(req.secret_env);
+
+    let env = state
+        .secret_store
+        .resolve_env(&env, &secret_env)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
     let mut manager = state.terminal_manager.write().await;
 
-    match manager.create_terminal(req.id.as_deref(), &req.working_dir, req.cols, req.rows) {
+    match manager.create_terminal(req.id.as_deref(), &req.working_dir, req.cols, req.rows, &env) {
         Ok(id) => {
             tracing::info!("[create_terminal] Successfully created terminal: {}", id);
+            state.audit_log.record(
+                &identity,
+                AuditAction::TerminalCreate,
+                req.agent_id.as_deref(),
+                serde_json::json!({ "terminal_id": id, "working_dir": req.working_dir }),
+            );
             Ok(Json(TerminalInfo {
                 id,
                 working_dir: req.working_dir,
@@ -557,7 +2387,7 @@ async fn create_terminal(
         }
         Err(e) => {
             tracing::error!("[create_terminal] Failed: {}", e);
-            Err((StatusCode::INTERNAL_SERVER_ERROR, e))
+            Err((StatusCode::INTERNAL_SERVER_ERROR, e).into())
         }
     }
 }
@@ -587,14 +2417,305 @@ async fn kill_terminal(
     }
 }
 
+/// Server-Sent Events keep-alive comment interval, short enough for common proxy timeouts
+const SSE_HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// Sent by reconnecting event stream clients
+const LAST_EVENT_ID: HeaderName = HeaderName::from_static("last-event-id");
+
+/// Filters for the event streams, matching the WebSocket `subscribe` message as
+/// comma-separated lists
+#[derive(Deserialize)]
+struct EventsQuery {
+    agents: Option<String>,
+    terminals: Option<String>,
+    events: Option<String>,
+    /// For clients that can't set the `Last-Event-ID` header
+    last_event_id: Option<u64>,
+}
+
+async fn events(
+    State(state): State<SharedState>,
+    Extension(identity): Extension<auth::Identity>,
+    headers: axum::http::HeaderMap,
+    Query(query): Query<EventsQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    event_stream(state, identity, &headers, query, None)
+}
+
+async fn agent_events(
+    State(state): State<SharedState>,
+    Extension(identity): Extension<auth::Identity>,
+    Path(id): Path<String>,
+    headers: axum::http::HeaderMap,
+    Query(query): Query<EventsQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let exists = state.agent_manager.read().await.list_agents().iter().any(|agent| agent.id == id);
+    if !exists {
+        return Err((StatusCode::NOT_FOUND, "Agent not found".to_string()));
+    }
+    event_stream(state, identity, &headers, query, Some(id))
+}
+
+/// Resolves once the device behind a connection is revoked; never for the server token
+async fn device_revoked(state: SharedState, device_id: Option<String>) {
+    let Some(device_id) = device_id else {
+        return std::future::pending().await;
+    };
+    let mut changes = state.device_store.watch();
+    while state.device_store.role(&device_id).is_some() {
+        if changes.changed().await.is_err() {
+            return std::future::pending().await;
+        }
+    }
+    tracing::info!("Closing event stream of revoked device {}", device_id);
+}
+
+/// The `/ws` broadcast stream as Server-Sent Events. Each event's id can be sent back
+/// as `Last-Event-ID` to replay what was missed while disconnected.
+fn event_stream(
+    state: SharedState,
+    identity: auth::Identity,
+    headers: &axum::http::HeaderMap,
+    query: EventsQuery,
+    agent_id: Option<String>,
+) -> Result<Sse<impl futures::Stream<Item = Result<Event, std::convert::Infallible>>>, (StatusCode, String)> {
+    let change = SubscriptionChange::from_lists(
+        query.agents.as_deref(),
+        query.terminals.as_deref(),
+        query.events.as_deref(),
+    )
+    .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let mut subscriptions = match agent_id {
+        Some(id) => Subscriptions::for_agent(id),
+        None => Subscriptions::default(),
+    };
+    if !change.is_empty() {
+        subscriptions.subscribe(change);
+    }
+
+    let last_event_id = match headers.get(LAST_EVENT_ID) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .ok_or_else(|| (StatusCode::BAD_REQUEST, "Invalid Last-Event-ID".to_string()))?,
+        ),
+        None => query.last_event_id,
+    };
+
+    let replay = state.event_journal.subscribe_since(last_event_id);
+    let notice = replay
+        .truncated
+        .then(|| Event::default().comment("some events since Last-Event-ID are no longer available"));
+    let live = futures::stream::unfold(replay.live, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => return Some((event, rx)),
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("Event stream client lagged, skipped {} events", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let events = futures::stream::iter(replay.backlog)
+        .chain(live)
+        .filter(move |(_, msg)| futures::future::ready(msg.subscribed_by(&subscriptions)))
+        .map(|(id, msg)| {
+            Event::default()
+                .id(id.to_string())
+                .event(msg.kind().as_str())
+                .json_data(&msg)
+                .unwrap_or_else(|e| Event::default().comment(format!("failed to encode event {}: {}", id, e)))
+        });
+    let stream = futures::stream::iter(notice)
+        .chain(events)
+        .take_until(device_revoked(state, identity.device_id))
+        .map(Ok);
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(SSE_HEARTBEAT_INTERVAL).text("heartbeat")))
+}
+
 async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<SharedState>,
+    Extension(identity): Extension<auth::Identity>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_socket(socket, state))
+    tracing::debug!(
+        "WebSocket connection as {} from {}",
+        identity.role,
+        identity.device_id.as_deref().unwrap_or("the server token")
+    );
+    ws.protocols([auth::WS_PROTOCOL])
+        .on_upgrade(move |socket| handle_socket(socket, state, identity))
+}
+
+/// Terminal input is audited as byte and message counts, flushed this often
+const TERMINAL_INPUT_AUDIT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Record and reset the terminal input counted on one connection
+fn flush_terminal_input(
+    audit_log: &AuditLog,
+    identity: &auth::Identity,
+    counts: &mut std::collections::HashMap<String, (usize, usize)>,
+) {
+    for (terminal_id, (bytes, messages)) in counts.drain() {
+        audit_log.record(
+            identity,
+            AuditAction::TerminalInput,
+            None,
+            serde_json::json!({ "terminal_id": terminal_id, "bytes": bytes, "messages": messages }),
+        );
+    }
+}
+
+/// Carry out one client message, answering the way the equivalent REST call would
+async fn run_ws_command(
+    state: &SharedState,
+    identity: &auth::Identity,
+    subscriptions: &std::sync::RwLock<Subscriptions>,
+    input_counts: &mut std::collections::HashMap<String, (usize, usize)>,
+    message: WsClientMessage,
+) -> axum::response::Response {
+    let state = state.clone();
+    let identity = identity.clone();
+    match message {
+        WsClientMessage::Subscribe(change) => {
+            if let Ok(mut subscriptions) = subscriptions.write() {
+                subscriptions.subscribe(change);
+            }
+            StatusCode::NO_CONTENT.into_response()
+        }
+        WsClientMessage::Unsubscribe(change) => {
+            if let Ok(mut subscriptions) = subscriptions.write() {
+                subscriptions.unsubscribe(change);
+            }
+            StatusCode::NO_CONTENT.into_response()
+        }
+        WsClientMessage::TerminalInput { terminal_id, data } => {
+            let manager = state.terminal_manager.read().await;
+            let Some(terminal) = manager.get_terminal(&terminal_id) else {
+                return (StatusCode::NOT_FOUND, format!("Terminal {} not found", terminal_id)).into_response();
+            };
+            let counts = input_counts.entry(terminal_id.clone()).or_default();
+            counts.0 += data.len();
+            counts.1 += 1;
+            match terminal.write(data.as_bytes()).await {
+                Ok(_) => StatusCode::NO_CONTENT.into_response(),
+                Err(e) => {
+                    tracing::error!("Failed to write to terminal {}: {}", terminal_id, e);
+                    (StatusCode::INTERNAL_SERVER_ERROR, e).into_response()
+                }
+            }
+        }
+        WsClientMessage::TerminalResize { terminal_id, cols, rows } => {
+            let manager = state.terminal_manager.read().await;
+            let Some(terminal) = manager.get_terminal(&terminal_id) else {
+                return (StatusCode::NOT_FOUND, format!("Terminal {} not found", terminal_id)).into_response();
+            };
+            match terminal.resize(cols, rows).await {
+                Ok(_) => StatusCode::NO_CONTENT.into_response(),
+                Err(e) => {
+                    tracing::error!("Failed to resize terminal {}: {}", terminal_id, e);
+                    (StatusCode::INTERNAL_SERVER_ERROR, e).into_response()
+                }
+            }
+        }
+        WsClientMessage::TerminalCreate(req) => {
+            create_terminal(State(state), Extension(identity), Json(req)).await.into_response()
+        }
+        WsClientMessage::TerminalKill { terminal_id } => {
+            kill_terminal(State(state), Path(terminal_id)).await.into_response()
+        }
+        WsClientMessage::AgentCreate(req) => {
+            create_agent(State(state), Extension(identity), Json(req)).await.into_response()
+        }
+        WsClientMessage::AgentMessage(msg) => {
+            send_message(State(state), Extension(identity), Path(msg.agent_id), Json(msg.request))
+                .await
+                .into_response()
+        }
+        WsClientMessage::AgentStop { agent_id } => stop_agent(State(state), Path(agent_id)).await.into_response(),
+        WsClientMessage::AgentKill { agent_id } => {
+            kill_agent(State(state), Extension(identity), Path(agent_id)).await.into_response()
+        }
+        WsClientMessage::AgentSettings(settings) => {
+            update_agent_settings(State(state), Extension(identity), Path(settings.agent_id), Json(settings.update))
+                .await
+                .into_response()
+        }
+    }
+}
+
+/// Turn a command's response into an `ack` or `error` frame. Successes are only
+/// acknowledged when the client sent a request id.
+async fn ws_reply(request_id: Option<String>, response: axum::response::Response) -> Option<String> {
+    let status = response.status();
+    if status.is_success() && request_id.is_none() {
+        return None;
+    }
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap_or_default();
+    let body = if body.is_empty() {
+        serde_json::Value::Null
+    } else {
+        serde_json::from_slice(&body)
+            .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(&body).to_string()))
+    };
+
+    let reply = if status.is_success() {
+        WsReply::Ack {
+            request_id,
+            status: status.as_u16(),
+            result: body,
+        }
+    } else {
+        let (message, details) = match body {
+            serde_json::Value::String(message) => (message, serde_json::Value::Null),
+            details => (
+                details
+                    .get("message")
+                    .and_then(|m| m.as_str())
+                    .or(status.canonical_reason())
+                    .unwrap_or("Request failed")
+                    .to_string(),
+                details,
+            ),
+        };
+        WsReply::Error {
+            request_id,
+            status: status.as_u16(),
+            message,
+            details,
+        }
+    };
+    serde_json::to_string(&reply).ok()
+}
+
+/// Bring a device connection's role up to date. False once the device is revoked.
+fn refresh_identity(state: &AppState, identity: &mut auth::Identity) -> bool {
+    let Some(device_id) = identity.device_id.as_deref() else {
+        return true;
+    };
+    match state.device_store.role(device_id) {
+        Some(role) => {
+            if role != identity.role {
+                tracing::info!("WebSocket connection of device {} is now {}", device_id, role);
+                identity.role = role;
+            }
+            true
+        }
+        None => false,
+    }
 }
 
-async fn handle_socket(socket: WebSocket, state: SharedState) {
+async fn handle_socket(socket: WebSocket, state: SharedState, mut identity: auth::Identity) {
     let (mut sender, mut receiver) = socket.split();
 
     // Subscribe to broadcast channels
@@ -604,12 +2725,29 @@ async fn handle_socket(socket: WebSocket, state: SharedState) {
     // Clone state for the receive task
     let state_clone = state.clone();
 
+    // Filled in by the client's subscribe/unsubscribe messages
+    let subscriptions = Arc::new(std::sync::RwLock::new(Subscriptions::default()));
+    let send_subscriptions = subscriptions.clone();
+
+    // Replies to client messages go through the send task, which owns the socket
+    let (reply_tx, mut reply_rx) = tokio::sync::mpsc::channel::<Message>(64);
+    let mut device_changes = state.device_store.watch();
+
     // Spawn task to forward broadcast messages to WebSocket
     let send_task = tokio::spawn(async move {
+        let subscribed = |msg: &BroadcastMessage| {
+            send_subscriptions
+                .read()
+                .map(|s| msg.subscribed_by(&s))
+                .unwrap_or(false)
+        };
         loop {
             tokio::select! {
                 // Agent messages
                 Ok(msg) = agent_rx.recv() => {
+                    if !subscribed(&msg) {
+                        continue;
+                    }
                     if let Ok(json) = serde_json::to_string(&msg) {
                         if sender.send(Message::Text(json)).await.is_err() {
                             break;
@@ -619,55 +2757,90 @@ async fn handle_socket(socket: WebSocket, state: SharedState) {
                 // Terminal output messages
                 Ok(output) = terminal_rx.recv() => {
                     let msg = BroadcastMessage::TerminalOutput(output);
+                    if !subscribed(&msg) {
+                        continue;
+                    }
                     if let Ok(json) = serde_json::to_string(&msg) {
                         if sender.send(Message::Text(json)).await.is_err() {
                             break;
                         }
                     }
                 }
+                // Acks and errors for this client's messages
+                Some(reply) = reply_rx.recv() => {
+                    let close = matches!(reply, Message::Close(_));
+                    if sender.send(reply).await.is_err() || close {
+                        break;
+                    }
+                }
             }
         }
     });
 
     // Handle incoming messages - now processes terminal input
     let recv_task = tokio::spawn(async move {
-        while let Some(Ok(msg)) = receiver.next().await {
+        let mut input_counts = std::collections::HashMap::new();
+        let mut audit_interval = tokio::time::interval(TERMINAL_INPUT_AUDIT_INTERVAL);
+        loop {
+            let msg = tokio::select! {
+                msg = receiver.next() => match msg {
+                    Some(Ok(msg)) => Some(msg),
+                    _ => break,
+                },
+                _ = audit_interval.tick() => {
+                    flush_terminal_input(&state_clone.audit_log, &identity, &mut input_counts);
+                    continue;
+                }
+                Ok(()) = device_changes.changed() => None,
+            };
+            // Checked again for every message, so a revoked or downgraded device loses access at once
+            if !refresh_identity(&state_clone, &mut identity) {
+                tracing::info!(
+                    "Closing WebSocket connection of revoked device {}",
+                    identity.device_id.as_deref().unwrap_or_default()
+                );
+                let _ = reply_tx
+                    .send(Message::Close(Some(CloseFrame {
+                        code: axum::extract::ws::close_code::POLICY,
+                        reason: "Device revoked".into(),
+                    })))
+                    .await;
+                break;
+            }
+            let Some(msg) = msg else {
+                continue;
+            };
             match msg {
                 Message::Close(_) => break,
                 Message::Text(text) => {
-                    // Parse incoming message
-                    if let Ok(client_msg) = serde_json::from_str::<WsClientMessage>(&text) {
-                        match client_msg {
-                            WsClientMessage::TerminalInput { terminal_id, data } => {
-                                let manager = state_clone.terminal_manager.read().await;
-                                if let Some(terminal) = manager.get_terminal(&terminal_id) {
-                                    if let Err(e) = terminal.write(data.as_bytes()).await {
-                                        tracing::error!(
-                                            "Failed to write to terminal {}: {}",
-                                            terminal_id,
-                                            e
-                                        );
-                                    }
-                                } else {
-                                    tracing::warn!("Terminal {} not found", terminal_id);
-                                }
-                            }
-                            WsClientMessage::TerminalResize {
-                                terminal_id,
-                                cols,
-                                rows,
-                            } => {
-                                let manager = state_clone.terminal_manager.read().await;
-                                if let Some(terminal) = manager.get_terminal(&terminal_id) {
-                                    if let Err(e) = terminal.resize(cols, rows).await {
-                                        tracing::error!(
-                                            "Failed to resize terminal {}: {}",
-                                            terminal_id,
-                                            e
-                                        );
-                                    }
-                                }
-                            }
+                    let (request_id, response) = match serde_json::from_str::<WsRequest>(&text) {
+                        Ok(request) => {
+                            let required = request.message.required_role();
+                            let response = if identity.role < required {
+                                tracing::warn!(
+                                    "Refusing WebSocket command needing {} from a {} connection",
+                                    required, identity.role
+                                );
+                                (StatusCode::FORBIDDEN, format!("Requires the {} role", required)).into_response()
+                            } else {
+                                run_ws_command(&state_clone, &identity, &subscriptions, &mut input_counts, request.message)
+                                    .await
+                            };
+                            (request.request_id, response)
+                        }
+                        Err(e) => {
+                            // Still correlate the error if the id itself was readable
+                            let request_id = serde_json::from_str::<serde_json::Value>(&text)
+                                .ok()
+                                .and_then(|v| v.get("request_id")?.as_str().map(str::to_string));
+                            let response =
+                                (StatusCode::BAD_REQUEST, format!("Invalid message: {}", e)).into_response();
+                            (request_id, response)
+                        }
+                    };
+                    if let Some(reply) = ws_reply(request_id, response).await {
+                        if reply_tx.send(Message::Text(reply)).await.is_err() {
+                            break;
                         }
                     }
                 }
@@ -677,12 +2850,14 @@ async fn handle_socket(socket: WebSocket, state: SharedState) {
                 _ => {}
             }
         }
+        flush_terminal_input(&state_clone.audit_log, &identity, &mut input_counts);
     });
 
-    // Wait for either task to complete
+    // Wait for either task to complete, then stop the other so the socket is dropped
+    let (mut send_task, mut recv_task) = (send_task, recv_task);
     tokio::select! {
-        _ = send_task => {},
-        _ = recv_task => {},
+        _ = &mut send_task => recv_task.abort(),
+        _ = &mut recv_task => send_task.abort(),
     }
 
     tracing::debug!("WebSocket connection closed");
@@ -0,0 +1,485 @@
+//! Model Context Protocol server, so MCP clients can run the office. Served as
+//! streamable HTTP on `/mcp`; `virtual-agency-server mcp` bridges stdio clients to it.
+//! Each tool calls the REST handler it mirrors and needs the same role.
+
+use axum::{
+    body::Bytes,
+    extract::{Extension, Path, State},
+    http::{HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use crate::config::ServerConfig;
+use crate::{auth, files, sessions, SharedState};
+
+/// Newest revision first; a client asking for another gets the newest
+const PROTOCOL_VERSIONS: [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// Messages returned by `get_history` unless the caller asks for more
+const DEFAULT_HISTORY_LIMIT: usize = 50;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+#[derive(Deserialize)]
+struct AgentArgs {
+    agent_id: String,
+}
+
+#[derive(Deserialize)]
+struct SendMessageArgs {
+    agent_id: String,
+    message: String,
+    /// Wait for the run and return its result instead of returning once it starts
+    #[serde(default = "default_wait")]
+    wait: bool,
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+}
+
+fn default_wait() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+struct HistoryArgs {
+    agent_id: String,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct ReadFileArgs {
+    agent_id: String,
+    path: String,
+}
+
+/// Name, description and input schema of every tool
+fn tools() -> Value {
+    let agent_id = json!({ "type": "string", "description": "Agent id, as returned by list_agents" });
+    json!([
+        {
+            "name": "list_agents",
+            "description": "List the office's agents with their working directory, model, whether they are running and their cost so far.",
+            "inputSchema": { "type": "object", "properties": {} }
+        },
+        {
+            "name": "create_agent",
+            "description": "Create an agent working in a directory, optionally from a template or persona.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "working_dir": { "type": "string", "description": "Defaults to the server's first allowed root" },
+                    "template_id": { "type": "string" },
+                    "persona_id": { "type": "string" },
+                    "model": { "type": "string" },
+                    "system_prompt": { "type": "string" },
+                    "append_system_prompt": { "type": "string" }
+                },
+                "required": ["name"]
+            }
+        },
+        {
+            "name": "send_message",
+            "description": "Send a message to an agent. By default waits for the run to finish and returns its final text, usage and changed files.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "agent_id": agent_id,
+                    "message": { "type": "string" },
+                    "wait": { "type": "boolean", "default": true },
+                    "timeout_secs": { "type": "integer", "minimum": 1, "description": "Stop the run after this long when waiting" }
+                },
+                "required": ["agent_id", "message"]
+            }
+        },
+        {
+            "name": "get_history",
+            "description": "Messages of an agent's current conversation, most recent last.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "agent_id": agent_id,
+                    "limit": { "type": "integer", "minimum": 1, "default": DEFAULT_HISTORY_LIMIT }
+                },
+                "required": ["agent_id"]
+            }
+        },
+        {
+            "name": "stop_agent",
+            "description": "Stop an agent's current run. The agent and its conversation are kept.",
+            "inputSchema": { "type": "object", "properties": { "agent_id": agent_id }, "required": ["agent_id"] }
+        },
+        {
+            "name": "kill_agent",
+            "description": "Remove an agent from the office.",
+            "inputSchema": { "type": "object", "properties": { "agent_id": agent_id }, "required": ["agent_id"] }
+        },
+        {
+            "name": "read_file",
+            "description": "Read a file in an agent's working directory.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "agent_id": agent_id,
+                    "path": { "type": "string", "description": "Relative to the agent's working directory" }
+                },
+                "required": ["agent_id", "path"]
+            }
+        }
+    ])
+}
+
+/// The REST route a tool mirrors, for its role
+fn tool_route(name: &str) -> Option<(Method, &'static str)> {
+    Some(match name {
+        "list_agents" => (Method::GET, "/api/agents"),
+        "create_agent" => (Method::POST, "/api/agents"),
+        "send_message" => (Method::POST, "/api/agents/:id/run"),
+        "get_history" => (Method::GET, "/api/sessions/:session_id"),
+        "stop_agent" => (Method::POST, "/api/agents/:id/stop"),
+        "kill_agent" => (Method::DELETE, "/api/agents/:id"),
+        "read_file" => (Method::POST, "/api/files/read/:agent_id"),
+        _ => return None,
+    })
+}
+
+fn tool_text(text: String, is_error: bool) -> Value {
+    json!({ "content": [{ "type": "text", "text": text }], "isError": is_error })
+}
+
+/// A handler's response as a tool result; error statuses set `isError`
+async fn tool_result(response: Response) -> Value {
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        .unwrap_or_default();
+    let text = match body.trim() {
+        "" if status.is_success() => "Done".to_string(),
+        "" => status.to_string(),
+        _ => body,
+    };
+    tool_text(text, !status.is_success())
+}
+
+fn arguments<T: DeserializeOwned>(arguments: Value) -> Result<T, Value> {
+    serde_json::from_value(arguments).map_err(|e| tool_text(format!("Invalid arguments: {}", e), true))
+}
+
+async fn call_tool(state: &SharedState, identity: &auth::Identity, name: &str, args: Value) -> Result<Value, String> {
+    let (method, route) = tool_route(name).ok_or_else(|| format!("Unknown tool: {}", name))?;
+    let required = auth::required_role(&method, route);
+    if identity.role < required {
+        tracing::warn!("[mcp] {} needs the {} role, token has {}", name, required, identity.role);
+        return Ok(tool_text(format!("Requires the {} role", required), true));
+    }
+
+    tracing::info!("[mcp] Calling tool {}", name);
+    Ok(match run_tool(state.clone(), identity.clone(), name, args).await {
+        Ok(response) => tool_result(response).await,
+        Err(invalid) => invalid,
+    })
+}
+
+/// Run a tool through its REST handler; invalid arguments come back as an error result
+async fn run_tool(state: SharedState, identity: auth::Identity, name: &str, args: Value) -> Result<Response, Value> {
+    Ok(match name {
+        "list_agents" => crate::list_agents(State(state)).await.into_response(),
+        "create_agent" => {
+            let mut args = args;
+            if let Some(args) = args.as_object_mut() {
+                let default_root = state.allowed_roots.default_root().to_string_lossy().to_string();
+                args.entry("working_dir").or_insert(Value::String(default_root));
+            }
+            crate::create_agent(State(state), Extension(identity), Json(arguments(args)?))
+                .await
+                .into_response()
+        }
+        "send_message" => {
+            let args: SendMessageArgs = arguments(args)?;
+            let message = crate::SendMessageRequest {
+                message: args.message,
+                images: Vec::new(),
+                attachment_ids: Vec::new(),
+            };
+            if args.wait {
+                let req = crate::RunRequest {
+                    message,
+                    timeout_secs: args.timeout_secs,
+                    stream: false,
+                };
+                crate::run_agent(State(state), Extension(identity), Path(args.agent_id), HeaderMap::new(), Json(req))
+                    .await
+                    .into_response()
+            } else {
+                crate::send_message(State(state), Extension(identity), Path(args.agent_id), Json(message))
+                    .await
+                    .into_response()
+            }
+        }
+        "get_history" => history(&state, arguments(args)?).await,
+        "stop_agent" => {
+            let args: AgentArgs = arguments(args)?;
+            crate::stop_agent(State(state), Path(args.agent_id)).await.into_response()
+        }
+        "kill_agent" => {
+            let args: AgentArgs = arguments(args)?;
+            crate::kill_agent(State(state), Extension(identity), Path(args.agent_id))
+                .await
+                .into_response()
+        }
+        "read_file" => {
+            let args: ReadFileArgs = arguments(args)?;
+            let req = files::ReadFileRequest { path: args.path };
+            crate::read_file(State(state), Path(args.agent_id), Json(req)).await.into_response()
+        }
+        _ => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+/// The latest messages of the agent's current session
+async fn history(state: &SharedState, args: HistoryArgs) -> Response {
+    let agent = {
+        let manager = state.agent_manager.read().await;
+        manager.list_agents().into_iter().find(|agent| agent.id == args.agent_id)
+    };
+    let Some(agent) = agent else {
+        return (StatusCode::NOT_FOUND, "Agent not found".to_string()).into_response();
+    };
+    let Some(session_id) = agent.session_id else {
+        return "The agent has no conversation yet".into_response();
+    };
+
+    match sessions::get_session(&agent.working_dir, &session_id).await {
+        Ok(Some(mut detail)) => {
+            let limit = args.limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+            let skip = detail.messages.len().saturating_sub(limit);
+            detail.messages.drain(..skip);
+            Json(detail).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "Session not found".to_string()).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+fn initialize(params: &Value) -> Value {
+    let version = params
+        .get("protocolVersion")
+        .and_then(|v| v.as_str())
+        .filter(|v| PROTOCOL_VERSIONS.contains(v))
+        .unwrap_or(PROTOCOL_VERSIONS[0]);
+    json!({
+        "protocolVersion": version,
+        "capabilities": { "tools": { "listChanged": false } },
+        "serverInfo": { "name": "virtual-agency", "version": env!("CARGO_PKG_VERSION") },
+        "instructions": "Tools for running Virtual Agency's Claude agents. Call list_agents for agent ids; send_message waits for the agent's answer."
+    })
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// Handle a JSON-RPC message or batch. None when nothing needs a reply.
+pub async fn handle(state: &SharedState, identity: &auth::Identity, message: Value) -> Option<Value> {
+    match message {
+        Value::Array(batch) if !batch.is_empty() => {
+            let replies: Vec<Value> =
+                futures::future::join_all(batch.into_iter().map(|message| handle_one(state, identity, message)))
+                    .await
+                    .into_iter()
+                    .flatten()
+                    .collect();
+            (!replies.is_empty()).then_some(Value::Array(replies))
+        }
+        message => handle_one(state, identity, message).await,
+    }
+}
+
+async fn handle_one(state: &SharedState, identity: &auth::Identity, message: Value) -> Option<Value> {
+    let id = message.get("id").cloned();
+    let Some(method) = message.get("method").and_then(|m| m.as_str()) else {
+        // Responses are only sent to server requests, which this server never makes
+        if message.get("result").is_some() || message.get("error").is_some() {
+            return None;
+        }
+        return Some(error(id.unwrap_or(Value::Null), INVALID_REQUEST, "Invalid request"));
+    };
+    // Notifications, e.g. notifications/initialized, need no reply
+    let id = id?;
+    let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+    let result = match method {
+        "initialize" => Ok(initialize(&params)),
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({ "tools": tools() })),
+        "tools/call" => {
+            let name = params.get("name").and_then(|n| n.as_str()).unwrap_or_default();
+            let args = params
+                .get("arguments")
+                .filter(|args| !args.is_null())
+                .cloned()
+                .unwrap_or_else(|| json!({}));
+            call_tool(state, identity, name, args).await.map_err(|e| (INVALID_PARAMS, e))
+        }
+        method => Err((METHOD_NOT_FOUND, format!("Method not found: {}", method))),
+    };
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => error(id, code, &message),
+    })
+}
+
+/// `POST /mcp`, the streamable HTTP transport. Replies are always plain JSON: the server
+/// never sends requests or notifications of its own, so it keeps no sessions or streams.
+pub async fn http(
+    State(state): State<SharedState>,
+    Extension(identity): Extension<auth::Identity>,
+    body: Bytes,
+) -> Response {
+    let message = match serde_json::from_slice::<Value>(&body) {
+        Ok(message) => message,
+        Err(e) => {
+            let reply = error(Value::Null, PARSE_ERROR, &format!("Parse error: {}", e));
+            return (StatusCode::BAD_REQUEST, Json(reply)).into_response();
+        }
+    };
+    match handle(&state, &identity, message).await {
+        Some(reply) => Json(reply).into_response(),
+        None => StatusCode::ACCEPTED.into_response(),
+    }
+}
+
+/// The running server's `/mcp` endpoint, as seen from this machine
+fn server_url(config: &ServerConfig) -> String {
+    let host = match config.host.as_str() {
+        "0.0.0.0" | "::" => "127.0.0.1".to_string(),
+        host if host.contains(':') => format!("[{}]", host),
+        host => host.to_string(),
+    };
+    let scheme = if config.tls { "https" } else { "http" };
+    format!("{}://{}:{}/mcp", scheme, host, config.port)
+}
+
+/// An HTTP client trusting the server's own certificate, which is usually self-signed
+fn bridge_client(config: &ServerConfig) -> Result<reqwest::Client, String> {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let mut builder = reqwest::Client::builder().use_rustls_tls();
+    if config.tls {
+        let cert_path = config
+            .tls_cert
+            .clone()
+            .unwrap_or_else(|| config.data_dir.join("tls").join("cert.pem"));
+        let pem = std::fs::read(&cert_path)
+            .map_err(|e| format!("Failed to read the server's certificate {}: {}", cert_path.display(), e))?;
+        let cert = reqwest::Certificate::from_pem(&pem).map_err(|e| format!("Invalid certificate: {}", e))?;
+        builder = builder.add_root_certificate(cert);
+    }
+    builder.build().map_err(|e| format!("Failed to create HTTP client: {}", e))
+}
+
+/// Forward one message to the server; None when there is nothing to answer
+async fn forward(client: &reqwest::Client, url: &str, token: &str, message: Value) -> Option<Value> {
+    let id = message.get("id").cloned();
+    let failed = |e: String| {
+        tracing::error!("[mcp] {}", e);
+        id.clone().map(|id| error(id, INTERNAL_ERROR, &e))
+    };
+
+    let response = match client.post(url).bearer_auth(token).json(&message).send().await {
+        Ok(response) => response,
+        Err(e) => return failed(format!("Failed to reach the server at {}: {}", url, e)),
+    };
+    let status = response.status();
+    if status == StatusCode::ACCEPTED {
+        return None;
+    }
+    let body = match response.text().await {
+        Ok(body) => body,
+        Err(e) => return failed(format!("Failed to read the server's reply: {}", e)),
+    };
+    // JSON-RPC errors such as a parse error come back with an error status too
+    match serde_json::from_str::<Value>(&body) {
+        Ok(reply) if reply.get("jsonrpc").is_some() => Some(reply),
+        _ => failed(format!("Server returned {}: {}", status, body.trim())),
+    }
+}
+
+/// Bridge MCP clients that launch their servers themselves to the running server:
+/// every line from stdin is posted to its `/mcp` endpoint and every reply written to
+/// stdout as a line. Uses the server's token from the data dir unless given another,
+/// e.g. a paired device's with a lower role. Requests are forwarded concurrently so a
+/// send_message waiting on its agent doesn't hold up the others.
+pub async fn bridge_stdio(config: &ServerConfig, token: Option<String>) -> Result<(), String> {
+    let token = match token {
+        Some(token) => token,
+        None => {
+            let path = config.data_dir.join("auth.token");
+            std::fs::read_to_string(&path)
+                .map(|token| token.trim().to_string())
+                .map_err(|e| format!("Failed to read the server's token at {} ({}); is the server set up?", path.display(), e))?
+        }
+    };
+    let client = bridge_client(config)?;
+    let url = server_url(config);
+
+    let (reply_tx, mut reply_rx) = tokio::sync::mpsc::unbounded_channel::<Value>();
+    let writer = tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        while let Some(reply) = reply_rx.recv().await {
+            let line = reply.to_string() + "\n";
+            if stdout.write_all(line.as_bytes()).await.is_err() || stdout.flush().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    tracing::info!("[mcp] Bridging stdio to {}", url);
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                tracing::error!("[mcp] Failed to read stdin: {}", e);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message = match serde_json::from_str::<Value>(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                let _ = reply_tx.send(error(Value::Null, PARSE_ERROR, &format!("Parse error: {}", e)));
+                continue;
+            }
+        };
+
+        let client = client.clone();
+        let url = url.clone();
+        let token = token.clone();
+        let reply_tx = reply_tx.clone();
+        tokio::spawn(async move {
+            if let Some(reply) = forward(&client, &url, &token, message).await {
+                let _ = reply_tx.send(reply);
+            }
+        });
+    }
+
+    // Let requests still running finish and reply
+    drop(reply_tx);
+    let _ = writer.await;
+    tracing::info!("[mcp] stdin closed");
+    Ok(())
+}
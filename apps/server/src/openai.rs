@@ -0,0 +1,360 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Body of `POST /v1/chat/completions`. Sampling parameters like `temperature` are
+/// accepted and ignored; the agent's own settings apply.
+#[derive(Debug, Deserialize)]
+pub struct ChatCompletionRequest {
+    /// `agent:<id or name>`, `template:<id or name>`, or a bare id or name
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub stream_options: Option<StreamOptions>,
+    /// Not part of the OpenAI API: where a `template:` model's temporary agent runs,
+    /// which must be inside an allowed root
+    #[serde(default)]
+    pub working_dir: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct StreamOptions {
+    #[serde(default)]
+    pub include_usage: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    /// Null for assistant messages that only carry tool calls
+    #[serde(default)]
+    pub content: Option<MessageContent>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+}
+
+impl ChatMessage {
+    fn text(&self) -> String {
+        match &self.content {
+            Some(MessageContent::Text(text)) => text.clone(),
+            Some(MessageContent::Parts(parts)) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            None => String::new(),
+        }
+    }
+
+    /// Base64 data of the message's images; only `data:` URLs are accepted since the
+    /// server doesn't fetch remote content
+    fn images(&self) -> Result<Vec<String>, String> {
+        let Some(MessageContent::Parts(parts)) = &self.content else {
+            return Ok(Vec::new());
+        };
+        parts
+            .iter()
+            .filter_map(|part| match part {
+                ContentPart::ImageUrl { image_url } => Some(&image_url.url),
+                _ => None,
+            })
+            .map(|url| {
+                url.strip_prefix("data:")
+                    .and_then(|rest| rest.split_once(";base64,"))
+                    .map(|(_, data)| data.to_string())
+                    .ok_or_else(|| "Only base64 data: URLs are supported for images".to_string())
+            })
+            .collect()
+    }
+}
+
+/// What a chat request asks the agent to do
+pub struct Prompt {
+    pub message: String,
+    /// Base64 image data
+    pub images: Vec<String>,
+    /// System and developer messages, joined
+    pub system: Option<String>,
+}
+
+/// Build the prompt from the last user message. Existing agents keep their own
+/// conversation, so earlier turns are only replayed as a transcript when `with_history`
+/// is set, for agents started fresh from a template.
+pub fn prompt(messages: &[ChatMessage], with_history: bool) -> Result<Prompt, String> {
+    let last_user = messages
+        .iter()
+        .rposition(|m| m.role == "user")
+        .ok_or_else(|| "messages must include a user message".to_string())?;
+
+    let system: Vec<String> = messages
+        .iter()
+        .filter(|m| m.role == "system" || m.role == "developer")
+        .map(ChatMessage::text)
+        .filter(|text| !text.trim().is_empty())
+        .collect();
+
+    let mut message = String::new();
+    if with_history {
+        let history: Vec<String> = messages[..last_user]
+            .iter()
+            .filter(|m| m.role == "user" || m.role == "assistant")
+            .map(|m| format!("{}: {}", if m.role == "user" { "User" } else { "Assistant" }, m.text()))
+            .collect();
+        if !history.is_empty() {
+            message = format!("Conversation so far:\n\n{}\n\n", history.join("\n\n"));
+        }
+    }
+    message.push_str(&messages[last_user].text());
+
+    Ok(Prompt {
+        message,
+        images: messages[last_user].images()?,
+        system: (!system.is_empty()).then(|| system.join("\n\n")),
+    })
+}
+
+/// Text blocks of a Claude CLI `assistant` stream-json message
+pub fn assistant_text(line: &serde_json::Value) -> Option<String> {
+    if line.get("type").and_then(|t| t.as_str()) != Some("assistant") {
+        return None;
+    }
+    let text: Vec<&str> = line
+        .pointer("/message/content")?
+        .as_array()?
+        .iter()
+        .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("text"))
+        .filter_map(|block| block.get("text").and_then(|t| t.as_str()))
+        .collect();
+    (!text.is_empty()).then(|| text.join("\n\n"))
+}
+
+pub fn completion_id() -> String {
+    format!("chatcmpl-{}", uuid::Uuid::new_v4().simple())
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Serialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+impl Usage {
+    /// From the CLI's usage, counting cached input as prompt tokens
+    pub fn from_claude(usage: &serde_json::Value) -> Self {
+        let tokens = |name: &str| usage.get(name).and_then(|v| v.as_u64()).unwrap_or_default();
+        let prompt_tokens =
+            tokens("input_tokens") + tokens("cache_creation_input_tokens") + tokens("cache_read_input_tokens");
+        let completion_tokens = tokens("output_tokens");
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatCompletion {
+    pub id: String,
+    pub object: &'static str,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<Choice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Choice {
+    pub index: u32,
+    pub message: AssistantMessage,
+    pub finish_reason: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AssistantMessage {
+    pub role: &'static str,
+    pub content: String,
+}
+
+impl ChatCompletion {
+    pub fn new(model: String, content: String, usage: Option<Usage>) -> Self {
+        Self {
+            id: completion_id(),
+            object: "chat.completion",
+            created: unix_time(),
+            model,
+            choices: vec![Choice {
+                index: 0,
+                message: AssistantMessage {
+                    role: "assistant",
+                    content,
+                },
+                finish_reason: "stop",
+            }],
+            usage,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    pub object: &'static str,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChunkChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChunkChoice {
+    pub index: u32,
+    pub delta: Delta,
+    pub finish_reason: Option<&'static str>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Delta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+/// Chunks of one streamed completion share its id, creation time and model
+pub struct ChunkBuilder {
+    id: String,
+    created: u64,
+    model: String,
+}
+
+impl ChunkBuilder {
+    pub fn new(model: String) -> Self {
+        Self {
+            id: completion_id(),
+            created: unix_time(),
+            model,
+        }
+    }
+
+    fn chunk(&self, choices: Vec<ChunkChoice>, usage: Option<Usage>) -> ChatCompletionChunk {
+        ChatCompletionChunk {
+            id: self.id.clone(),
+            object: "chat.completion.chunk",
+            created: self.created,
+            model: self.model.clone(),
+            choices,
+            usage,
+        }
+    }
+
+    pub fn delta(&self, delta: Delta, finish_reason: Option<&'static str>) -> ChatCompletionChunk {
+        self.chunk(
+            vec![ChunkChoice {
+                index: 0,
+                delta,
+                finish_reason,
+            }],
+            None,
+        )
+    }
+
+    /// Sent last when the client asked for `stream_options.include_usage`
+    pub fn usage(&self, usage: Usage) -> ChatCompletionChunk {
+        self.chunk(Vec::new(), Some(usage))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Model {
+    pub id: String,
+    pub object: &'static str,
+    pub created: u64,
+    pub owned_by: &'static str,
+    /// Display name of the agent or template
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelList {
+    pub object: &'static str,
+    pub data: Vec<Model>,
+}
+
+/// An error in OpenAI's `{"error": {...}}` shape, which its client libraries parse
+#[derive(Debug)]
+pub struct OpenAiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl OpenAiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn body(&self) -> serde_json::Value {
+        let (kind, code) = match self.status {
+            StatusCode::NOT_FOUND => ("invalid_request_error", Some("model_not_found")),
+            status if status.is_client_error() => ("invalid_request_error", None),
+            StatusCode::GATEWAY_TIMEOUT => ("timeout", None),
+            _ => ("server_error", None),
+        };
+        serde_json::json!({
+            "error": { "message": self.message, "type": kind, "param": null, "code": code }
+        })
+    }
+}
+
+impl From<(StatusCode, String)> for OpenAiError {
+    fn from((status, message): (StatusCode, String)) -> Self {
+        Self { status, message }
+    }
+}
+
+impl IntoResponse for OpenAiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body())).into_response()
+    }
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+use crate::library::{Library, LibraryItem};
+
+/// A reusable agent role, e.g. a code reviewer or a test writer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Persona {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Replaces the CLI's default system prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    /// Appended to the CLI's default system prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub append_system_prompt: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PersonaRequest {
+    #[serde(default)]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+    #[serde(default)]
+    pub append_system_prompt: Option<String>,
+}
+
+fn builtin_personas() -> Vec<Persona> {
+    vec![
+        Persona {
+            id: "reviewer".to_string(),
+            name: "Code Reviewer".to_string(),
+            description: "Reviews changes for bugs, readability and missing tests without editing code".to_string(),
+            system_prompt: None,
+            append_system_prompt: Some(
+                "You are a meticulous code reviewer. Read the relevant code, point out bugs, risky changes, \
+                 unclear naming and missing tests, and suggest concrete fixes. Do not modify files unless explicitly asked."
+                    .to_string(),
+            ),
+        },
+        Persona {
+            id: "test-writer".to_string(),
+            name: "Test Writer".to_string(),
+            description: "Writes and runs tests that follow the project's existing test conventions".to_string(),
+            system_prompt: None,
+            append_system_prompt: Some(
+                "You write tests. Follow the project's existing test layout and style, cover edge cases and \
+                 failure paths, and run the test suite to confirm the new tests pass."
+                    .to_string(),
+            ),
+        },
+    ]
+}
+
+impl LibraryItem for Persona {
+    const FILE: &'static str = "personas.json";
+    const KIND: &'static str = "Persona";
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn set_id(&mut self, id: String) {
+        self.id = id;
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The library is seeded with the built-in personas on first start
+    fn defaults() -> Vec<Self> {
+        builtin_personas()
+    }
+}
+
+impl PersonaRequest {
+    /// The persona this request describes; `id` wins over the body's id, and without
+    /// either the library generates one
+    pub fn into_persona(self, id: Option<String>) -> Persona {
+        Persona {
+            id: id.or(self.id).unwrap_or_default(),
+            name: self.name,
+            description: self.description,
+            system_prompt: self.system_prompt.filter(|p| !p.is_empty()),
+            append_system_prompt: self.append_system_prompt.filter(|p| !p.is_empty()),
+        }
+    }
+}
+
+/// Persona library persisted as `personas.json` in the server data dir
+pub type PersonaStore = Library<Persona>;
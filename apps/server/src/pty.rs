@@ -1,6 +1,6 @@
 use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
 use serde::Serialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::{Read, Write};
 use std::sync::Arc;
 use tokio::sync::{broadcast, mpsc, Mutex};
@@ -86,6 +86,7 @@ impl TerminalManager {
         working_dir: &str,
         cols: u16,
         rows: u16,
+        env: &BTreeMap<String, String>,
     ) -> Result<String, String> {
         let terminal_id = id
             .map(|s| s.to_string())
@@ -123,6 +124,11 @@ impl TerminalManager {
         cmd.env("TERM", "xterm-256color");
         cmd.env("COLORTERM", "truecolor");
 
+        // Per-terminal variables, including resolved secrets
+        for (key, value) in env {
+            cmd.env(key, value);
+        }
+
         // Spawn the shell in the PTY
         let child = pair
             .slave
@@ -0,0 +1,296 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Why a path was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SandboxErrorKind {
+    OutsideAllowedRoots,
+    NotFound,
+}
+
+/// Structured error body for rejected paths
+#[derive(Debug, Clone, Serialize)]
+pub struct SandboxError {
+    pub error: SandboxErrorKind,
+    pub message: String,
+    pub path: String,
+    pub allowed_roots: Vec<String>,
+}
+
+impl SandboxError {
+    pub fn status(&self) -> StatusCode {
+        match self.error {
+            SandboxErrorKind::OutsideAllowedRoots => StatusCode::FORBIDDEN,
+            SandboxErrorKind::NotFound => StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+impl IntoResponse for SandboxError {
+    fn into_response(self) -> Response {
+        (self.status(), Json(self)).into_response()
+    }
+}
+
+/// Directories agents, terminals, file operations and the directory browser are confined to
+#[derive(Debug, Clone)]
+pub struct AllowedRoots {
+    /// Canonical paths
+    roots: Vec<PathBuf>,
+}
+
+impl AllowedRoots {
+    /// Canonicalize the configured roots; roots that don't exist are skipped with a warning
+    pub fn new(roots: &[PathBuf]) -> Result<Self, String> {
+        let roots: Vec<PathBuf> = roots
+            .iter()
+            .filter_map(|root| match root.canonicalize() {
+                Ok(root) => Some(root),
+                Err(e) => {
+                    tracing::warn!("[sandbox] Ignoring allowed root {}: {}", root.display(), e);
+                    None
+                }
+            })
+            .collect();
+
+        if roots.is_empty() {
+            return Err("None of the allowed roots exist".to_string());
+        }
+        Ok(Self { roots })
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// The directory the browser opens when no path is given
+    pub fn default_root(&self) -> &Path {
+        &self.roots[0]
+    }
+
+    pub fn contains(&self, canonical: &Path) -> bool {
+        self.roots.iter().any(|root| canonical.starts_with(root))
+    }
+
+    /// Resolve an existing path, following symlinks, and require it to be inside a root
+    pub fn check(&self, path: impl AsRef<Path>) -> Result<PathBuf, SandboxError> {
+        let path = path.as_ref();
+        let canonical = path
+            .canonicalize()
+            .map_err(|e| refusal(SandboxErrorKind::NotFound, path, format!("Path not found: {}", e), &self.roots))?;
+
+        if !self.contains(&canonical) {
+            return Err(outside(path, &canonical, &self.roots));
+        }
+        Ok(canonical)
+    }
+}
+
+fn refusal(kind: SandboxErrorKind, path: &Path, message: String, roots: &[PathBuf]) -> SandboxError {
+    SandboxError {
+        error: kind,
+        message,
+        path: path.to_string_lossy().to_string(),
+        allowed_roots: roots.iter().map(|r| r.to_string_lossy().to_string()).collect(),
+    }
+}
+
+fn outside(requested: &Path, resolved: &Path, roots: &[PathBuf]) -> SandboxError {
+    tracing::warn!("[sandbox] Refused {} outside the allowed roots", resolved.display());
+    refusal(
+        SandboxErrorKind::OutsideAllowedRoots,
+        requested,
+        "Path is outside the allowed roots".to_string(),
+        roots,
+    )
+}
+
+/// Resolve `.` and `..` without touching the disk. `..` never climbs above the root of an
+/// absolute path; leading `..` of a relative path is kept.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => normalized.push(component.as_os_str()),
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            Component::Normal(name) => normalized.push(name),
+        }
+    }
+    normalized
+}
+
+/// Join a client-supplied path onto `base` and normalize it, refusing anything that lands
+/// outside `base` before the filesystem is consulted. Absolute paths are accepted if they
+/// fall inside `base`.
+fn join_within(base: &Path, path: &str) -> Result<PathBuf, SandboxError> {
+    let joined = normalize(&base.join(path));
+    if !joined.starts_with(base) {
+        return Err(outside(Path::new(path), &joined, &[base.to_path_buf()]));
+    }
+    Ok(joined)
+}
+
+/// Resolve `path` relative to the canonical directory `base` to an existing file or
+/// directory, following symlinks, and require the result to stay inside `base`
+pub fn resolve_existing(base: &Path, path: &str) -> Result<PathBuf, SandboxError> {
+    let roots = [base.to_path_buf()];
+    let joined = join_within(base, path)?;
+    let canonical = joined.canonicalize().map_err(|e| {
+        refusal(SandboxErrorKind::NotFound, Path::new(path), format!("Path not found: {}", e), &roots)
+    })?;
+
+    if !canonical.starts_with(base) {
+        return Err(outside(Path::new(path), &canonical, &roots));
+    }
+    Ok(canonical)
+}
+
+/// Resolve `path` relative to the canonical directory `base` for writing. The path may not
+/// exist yet: its nearest existing ancestor is resolved and must be inside `base`, and the
+/// missing components are appended to it. An existing leaf, including a symlink, must
+/// resolve inside `base`; dangling symlinks are refused. Nothing is created.
+pub fn resolve_for_write(base: &Path, path: &str) -> Result<PathBuf, SandboxError> {
+    let roots = [base.to_path_buf()];
+    let joined = join_within(base, path)?;
+
+    let mut existing = joined.as_path();
+    let mut missing = Vec::new();
+    while fs::symlink_metadata(existing).is_err() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => break,
+        }
+    }
+
+    let mut resolved = existing
+        .canonicalize()
+        .map_err(|_| outside(Path::new(path), existing, &roots))?;
+    if !resolved.starts_with(base) {
+        return Err(outside(Path::new(path), &resolved, &roots));
+    }
+
+    resolved.extend(missing.into_iter().rev());
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// A workspace with a file, a nested dir, a symlink inside it, a symlink out of it and
+    /// a dangling symlink, next to a directory it must never reach
+    struct Fixture {
+        _dir: tempfile::TempDir,
+        base: PathBuf,
+        outside: PathBuf,
+    }
+
+    fn fixture() -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let base = root.join("ws");
+        let outside = root.join("outside");
+        fs::create_dir_all(base.join("a/b")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(base.join("a/file.txt"), "inside").unwrap();
+        fs::write(outside.join("secret.txt"), "outside").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::symlink;
+            symlink(base.join("a"), base.join("link_in")).unwrap();
+            symlink(&outside, base.join("link_out")).unwrap();
+            symlink(outside.join("secret.txt"), base.join("a/leaf_out")).unwrap();
+            symlink(outside.join("missing.txt"), base.join("dangling")).unwrap();
+        }
+        Fixture { _dir: dir, base, outside }
+    }
+
+    fn segment() -> impl Strategy<Value = &'static str> {
+        prop::sample::select(vec![
+            "a", "b", "file.txt", "new", ".", "..", "", "ws", "outside", "secret.txt",
+            "link_in", "link_out", "leaf_out", "dangling",
+        ])
+    }
+
+    fn relative_path() -> impl Strategy<Value = String> {
+        (any::<bool>(), prop::collection::vec(segment(), 0..8)).prop_map(|(absolute, segments)| {
+            let path = segments.join("/");
+            if absolute { format!("/{}", path) } else { path }
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn normalize_removes_dot_components(path in relative_path()) {
+            let normalized = normalize(Path::new(&path));
+            prop_assert!(!normalized.components().any(|c| c == Component::CurDir));
+            if path.starts_with('/') {
+                prop_assert!(!normalized.components().any(|c| c == Component::ParentDir));
+            }
+            prop_assert_eq!(normalize(&normalized), normalized);
+        }
+
+        #[test]
+        fn resolved_paths_stay_inside_base(path in relative_path()) {
+            let fx = fixture();
+            if let Ok(resolved) = resolve_existing(&fx.base, &path) {
+                prop_assert!(resolved.starts_with(&fx.base), "{} -> {}", path, resolved.display());
+            }
+            if let Ok(resolved) = resolve_for_write(&fx.base, &path) {
+                prop_assert!(resolved.starts_with(&fx.base), "{} -> {}", path, resolved.display());
+            }
+        }
+
+        #[test]
+        fn parent_traversal_is_refused(depth in 1usize..6, tail in segment()) {
+            let fx = fixture();
+            let path = format!("{}outside/{}", "../".repeat(depth), tail);
+            prop_assert!(resolve_existing(&fx.base, &path).is_err());
+            prop_assert!(resolve_for_write(&fx.base, &path).is_err());
+            let absolute = fx.outside.join(tail).to_string_lossy().to_string();
+            prop_assert!(resolve_for_write(&fx.base, &absolute).is_err());
+        }
+    }
+
+    #[test]
+    fn resolves_paths_inside_base() {
+        let fx = fixture();
+        assert_eq!(resolve_existing(&fx.base, "a/./b/../file.txt").unwrap(), fx.base.join("a/file.txt"));
+        assert_eq!(resolve_for_write(&fx.base, "a/new/dir/f.txt").unwrap(), fx.base.join("a/new/dir/f.txt"));
+        assert!(!fx.base.join("a/new").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_cannot_escape() {
+        let fx = fixture();
+        assert_eq!(resolve_existing(&fx.base, "link_in/file.txt").unwrap(), fx.base.join("a/file.txt"));
+        assert_eq!(resolve_for_write(&fx.base, "link_in/new.txt").unwrap(), fx.base.join("a/new.txt"));
+
+        for path in ["link_out/secret.txt", "a/leaf_out"] {
+            let err = resolve_existing(&fx.base, path).unwrap_err();
+            assert_eq!(err.error, SandboxErrorKind::OutsideAllowedRoots);
+            assert!(resolve_for_write(&fx.base, path).is_err());
+        }
+        assert!(resolve_for_write(&fx.base, "link_out/new/x.txt").is_err());
+        assert!(resolve_for_write(&fx.base, "dangling").is_err());
+    }
+}
@@ -0,0 +1,253 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Secret metadata returned by list endpoints; the value is never included
+#[derive(Debug, Clone, Serialize)]
+pub struct SecretInfo {
+    pub name: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+/// A secret as stored on disk, encrypted with the local key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct EncryptedSecret {
+    nonce: String,
+    ciphertext: String,
+    created_at: u64,
+    updated_at: u64,
+}
+
+/// Secret values encrypted at rest in `secrets.json` with the AES-256-GCM key in `secret.key`
+#[derive(Clone)]
+pub struct SecretStore {
+    path: PathBuf,
+    cipher: Arc<Aes256Gcm>,
+    secrets: Arc<RwLock<HashMap<String, EncryptedSecret>>>,
+}
+
+/// Secret names are referenced from agent settings, so keep them simple
+pub fn is_valid_secret_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 128
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Environment variable names as accepted by POSIX shells
+pub fn is_valid_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Check a plain env map and a secret reference map before they're stored on an agent or terminal
+pub fn validate_env(env: &BTreeMap<String, String>, secret_env: &BTreeMap<String, String>) -> Result<(), String> {
+    for name in env.keys().chain(secret_env.keys()) {
+        if !is_valid_env_name(name) {
+            return Err(format!("Invalid environment variable name: {}", name));
+        }
+    }
+    for secret in secret_env.values() {
+        if !is_valid_secret_name(secret) {
+            return Err(format!("Invalid secret name: {}", secret));
+        }
+    }
+    Ok(())
+}
+
+/// A new key is only generated when there are no secrets it would have to decrypt;
+/// otherwise a lost key would silently orphan every stored secret
+fn load_or_create_key(path: &Path, has_secrets: bool) -> Result<Key<Aes256Gcm>, String> {
+    if path.exists() {
+        let bytes = fs::read(path).map_err(|e| format!("Failed to read secret key: {}", e))?;
+        if bytes.len() != 32 {
+            return Err(format!("Secret key {} is corrupt", path.display()));
+        }
+        return Ok(*Key::<Aes256Gcm>::from_slice(&bytes));
+    }
+    if has_secrets {
+        return Err(format!(
+            "Secret key {} is missing but secrets are stored; restore the key or move secrets.json",
+            path.display()
+        ));
+    }
+
+    let key = Aes256Gcm::generate_key(OsRng);
+    write_private(path, key.as_slice()).map_err(|e| format!("Failed to write secret key: {}", e))?;
+    tracing::info!("[SecretStore] Generated new secret key at {}", path.display());
+    Ok(key)
+}
+
+/// Write a file readable only by the current user
+pub fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents)
+}
+
+/// Bind a ciphertext to its secret's name, so entries swapped in `secrets.json` fail to decrypt
+fn sealed<'a>(name: &'a str, msg: &'a [u8]) -> Payload<'a, 'a> {
+    Payload {
+        msg,
+        aad: name.as_bytes(),
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl SecretStore {
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let path = data_dir.join("secrets.json");
+        let secrets: HashMap<String, EncryptedSecret> = if path.exists() {
+            let contents = fs::read_to_string(&path).map_err(|e| format!("Failed to read secrets file: {}", e))?;
+            serde_json::from_str(&contents).map_err(|e| format!("Failed to parse secrets file: {}", e))?
+        } else {
+            HashMap::new()
+        };
+        let key = load_or_create_key(&data_dir.join("secret.key"), !secrets.is_empty())?;
+
+        Ok(Self {
+            path,
+            cipher: Arc::new(Aes256Gcm::new(&key)),
+            secrets: Arc::new(RwLock::new(secrets)),
+        })
+    }
+
+    pub fn list(&self) -> Vec<SecretInfo> {
+        let mut secrets: Vec<SecretInfo> = self
+            .secrets
+            .read()
+            .map(|s| {
+                s.iter()
+                    .map(|(name, secret)| SecretInfo {
+                        name: name.clone(),
+                        created_at: secret.created_at,
+                        updated_at: secret.updated_at,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        secrets.sort_by(|a, b| a.name.cmp(&b.name));
+        secrets
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.secrets.read().map(|s| s.contains_key(name)).unwrap_or(false)
+    }
+
+    /// Create or replace a secret
+    pub fn set(&self, name: &str, value: &str) -> Result<SecretInfo, String> {
+        if !is_valid_secret_name(name) {
+            return Err(format!("Invalid secret name: {}", name));
+        }
+
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, sealed(name, value.as_bytes()))
+            .map_err(|_| "Failed to encrypt secret".to_string())?;
+
+        let now = unix_secs();
+        let info = {
+            let mut secrets = self.secrets.write().map_err(|e| e.to_string())?;
+            let created_at = secrets.get(name).map(|s| s.created_at).unwrap_or(now);
+            secrets.insert(
+                name.to_string(),
+                EncryptedSecret {
+                    nonce: STANDARD.encode(nonce),
+                    ciphertext: STANDARD.encode(ciphertext),
+                    created_at,
+                    updated_at: now,
+                },
+            );
+            SecretInfo {
+                name: name.to_string(),
+                created_at,
+                updated_at: now,
+            }
+        };
+        self.save()?;
+        Ok(info)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), String> {
+        {
+            let mut secrets = self.secrets.write().map_err(|e| e.to_string())?;
+            if secrets.remove(name).is_none() {
+                return Err(format!("Secret not found: {}", name));
+            }
+        }
+        self.save()
+    }
+
+    /// Decrypt a secret's value; only used when injecting into a child process
+    fn reveal(&self, name: &str) -> Result<String, String> {
+        let secret = self
+            .secrets
+            .read()
+            .map_err(|e| e.to_string())?
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("Secret not found: {}", name))?;
+
+        let nonce = STANDARD
+            .decode(&secret.nonce)
+            .map_err(|_| format!("Secret {} is corrupt", name))?;
+        let ciphertext = STANDARD
+            .decode(&secret.ciphertext)
+            .map_err(|_| format!("Secret {} is corrupt", name))?;
+        if nonce.len() != 12 {
+            return Err(format!("Secret {} is corrupt", name));
+        }
+
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(&nonce), sealed(name, &ciphertext))
+            .map_err(|_| format!("Failed to decrypt secret {}", name))?;
+        String::from_utf8(plaintext).map_err(|_| format!("Secret {} is not valid UTF-8", name))
+    }
+
+    /// Build the environment for a child process: plain variables plus decrypted secret references
+    pub fn resolve_env(
+        &self,
+        env: &BTreeMap<String, String>,
+        secret_env: &BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, String>, String> {
+        let mut resolved = env.clone();
+        for (var, secret) in secret_env {
+            resolved.insert(var.clone(), self.reveal(secret)?);
+        }
+        Ok(resolved)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let json = {
+            let secrets = self.secrets.read().map_err(|e| e.to_string())?;
+            serde_json::to_string_pretty(&*secrets).map_err(|e| format!("Failed to serialize secrets: {}", e))?
+        };
+        write_private(&self.path, json.as_bytes()).map_err(|e| format!("Failed to write secrets file: {}", e))
+    }
+}
@@ -0,0 +1,253 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// Summary of a past Claude CLI session, as shown in the session browser
+#[derive(Debug, Serialize)]
+pub struct SessionSummary {
+    pub session_id: String,
+    pub first_prompt: Option<String>,
+    pub summary: Option<String>,
+    pub started_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub message_count: usize,
+}
+
+/// A single rendered message from a session transcript
+#[derive(Debug, Serialize)]
+pub struct SessionMessage {
+    pub uuid: Option<String>,
+    pub role: String,
+    pub timestamp: Option<String>,
+    pub text: String,
+    pub tool_calls: Vec<ToolCall>,
+    pub tool_results: Vec<ToolResult>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ToolCall {
+    pub id: Option<String>,
+    pub name: String,
+    pub input: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ToolResult {
+    pub tool_use_id: Option<String>,
+    pub content: String,
+    pub is_error: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionDetail {
+    #[serde(flatten)]
+    pub summary: SessionSummary,
+    pub messages: Vec<SessionMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SessionQuery {
+    pub working_dir: String,
+}
+
+/// Root of the CLI's config directory, honouring CLAUDE_CONFIG_DIR like the CLI does
+fn claude_config_dir() -> Option<PathBuf> {
+    std::env::var("CLAUDE_CONFIG_DIR")
+        .ok()
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".claude")))
+}
+
+/// The CLI stores transcripts under `projects/<cwd with non-alphanumerics replaced by '-'>`
+fn project_dir(working_dir: &str) -> Option<PathBuf> {
+    let working_dir = Path::new(working_dir)
+        .canonicalize()
+        .unwrap_or_else(|_| PathBuf::from(working_dir));
+    let encoded: String = working_dir
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    claude_config_dir().map(|dir| dir.join("projects").join(encoded))
+}
+
+/// Session ids are UUIDs; reject anything that could escape the project directory
+pub fn is_valid_session_id(session_id: &str) -> bool {
+    !session_id.is_empty() && session_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+pub async fn list_sessions(working_dir: &str) -> Result<Vec<SessionSummary>, String> {
+    let Some(dir) = project_dir(working_dir) else {
+        return Err("Cannot determine Claude config directory".to_string());
+    };
+
+    // Parsing every transcript is blocking file IO
+    tokio::task::spawn_blocking(move || read_sessions(&dir))
+        .await
+        .map_err(|e| format!("Failed to read sessions: {}", e))?
+}
+
+fn read_sessions(dir: &Path) -> Result<Vec<SessionSummary>, String> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let entries = fs::read_dir(dir).map_err(|e| format!("Cannot read session store: {}", e))?;
+
+    let mut sessions = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("jsonl") {
+            continue;
+        }
+        let Some(session_id) = path.file_stem().and_then(|s| s.to_str()).map(|s| s.to_string()) else {
+            continue;
+        };
+        match parse_transcript(&path, session_id) {
+            Ok(detail) if detail.summary.message_count > 0 => sessions.push(detail.summary),
+            Ok(_) => {}
+            Err(e) => tracing::warn!("[sessions] Skipping unreadable transcript {}: {}", path.display(), e),
+        }
+    }
+
+    // Most recently active first
+    sessions.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+
+    Ok(sessions)
+}
+
+pub async fn get_session(working_dir: &str, session_id: &str) -> Result<Option<SessionDetail>, String> {
+    if !is_valid_session_id(session_id) {
+        return Err("Invalid session id".to_string());
+    }
+
+    let Some(dir) = project_dir(working_dir) else {
+        return Err("Cannot determine Claude config directory".to_string());
+    };
+
+    let path = dir.join(format!("{}.jsonl", session_id));
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let session_id = session_id.to_string();
+    tokio::task::spawn_blocking(move || parse_transcript(&path, session_id).map(Some))
+        .await
+        .map_err(|e| format!("Failed to read session: {}", e))?
+}
+
+fn parse_transcript(path: &Path, session_id: String) -> Result<SessionDetail, String> {
+    let file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let reader = BufReader::new(file);
+
+    let mut summary = SessionSummary {
+        session_id,
+        first_prompt: None,
+        summary: None,
+        started_at: None,
+        updated_at: None,
+        message_count: 0,
+    };
+    let mut messages = Vec::new();
+
+    for line in reader.lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        let Ok(entry) = serde_json::from_str::<serde_json::Value>(&line) else {
+            continue;
+        };
+
+        let entry_type = entry.get("type").and_then(|v| v.as_str()).unwrap_or_default();
+        if entry_type == "summary" {
+            if let Some(text) = entry.get("summary").and_then(|v| v.as_str()) {
+                summary.summary = Some(text.to_string());
+            }
+            continue;
+        }
+
+        // Sidechains are sub-agent transcripts and meta entries are CLI bookkeeping
+        let is_sidechain = entry.get("isSidechain").and_then(|v| v.as_bool()).unwrap_or(false);
+        let is_meta = entry.get("isMeta").and_then(|v| v.as_bool()).unwrap_or(false);
+        if !matches!(entry_type, "user" | "assistant") || is_sidechain || is_meta {
+            continue;
+        }
+
+        let timestamp = entry.get("timestamp").and_then(|v| v.as_str()).map(|s| s.to_string());
+        if summary.started_at.is_none() {
+            summary.started_at = timestamp.clone();
+        }
+        if timestamp.is_some() {
+            summary.updated_at = timestamp.clone();
+        }
+
+        let message = render_message(&entry, entry_type, timestamp);
+        if summary.first_prompt.is_none() && message.role == "user" && !message.text.is_empty() {
+            summary.first_prompt = Some(message.text.clone());
+        }
+        summary.message_count += 1;
+        messages.push(message);
+    }
+
+    Ok(SessionDetail { summary, messages })
+}
+
+fn render_message(entry: &serde_json::Value, entry_type: &str, timestamp: Option<String>) -> SessionMessage {
+    let message = entry.get("message");
+    let role = message
+        .and_then(|m| m.get("role"))
+        .and_then(|v| v.as_str())
+        .unwrap_or(entry_type)
+        .to_string();
+
+    let mut rendered = SessionMessage {
+        uuid: entry.get("uuid").and_then(|v| v.as_str()).map(|s| s.to_string()),
+        role,
+        timestamp,
+        text: String::new(),
+        tool_calls: Vec::new(),
+        tool_results: Vec::new(),
+    };
+
+    let mut text_parts = Vec::new();
+    match message.and_then(|m| m.get("content")) {
+        Some(serde_json::Value::String(text)) => text_parts.push(text.clone()),
+        Some(serde_json::Value::Array(blocks)) => {
+            for block in blocks {
+                match block.get("type").and_then(|v| v.as_str()) {
+                    Some("text") => {
+                        if let Some(text) = block.get("text").and_then(|v| v.as_str()) {
+                            text_parts.push(text.to_string());
+                        }
+                    }
+                    Some("tool_use") => rendered.tool_calls.push(ToolCall {
+                        id: block.get("id").and_then(|v| v.as_str()).map(|s| s.to_string()),
+                        name: block.get("name").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+                        input: block.get("input").cloned().unwrap_or(serde_json::Value::Null),
+                    }),
+                    Some("tool_result") => rendered.tool_results.push(ToolResult {
+                        tool_use_id: block.get("tool_use_id").and_then(|v| v.as_str()).map(|s| s.to_string()),
+                        content: tool_result_text(block.get("content")),
+                        is_error: block.get("is_error").and_then(|v| v.as_bool()).unwrap_or(false),
+                    }),
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
+
+    rendered.text = text_parts.join("\n");
+    rendered
+}
+
+fn tool_result_text(content: Option<&serde_json::Value>) -> String {
+    match content {
+        Some(serde_json::Value::String(text)) => text.clone(),
+        Some(serde_json::Value::Array(blocks)) => blocks
+            .iter()
+            .filter_map(|b| b.get("text").and_then(|v| v.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
@@ -0,0 +1,289 @@
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Lifetime of a share link when the request doesn't say
+pub const DEFAULT_SHARE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Longest lifetime a share link can be given
+pub const MAX_SHARE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A read-only link to one agent. The token itself is never stored; it is the share id
+/// and expiry signed with the key in `share.key`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Share {
+    pub id: String,
+    pub agent_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+/// Returned once, when a share is created
+#[derive(Debug, Serialize)]
+pub struct CreatedShare {
+    #[serde(flatten)]
+    pub share: Share,
+    pub token: String,
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn load_or_create_key(path: &Path) -> Result<Vec<u8>, String> {
+    if path.exists() {
+        let key = fs::read(path).map_err(|e| format!("Failed to read share key: {}", e))?;
+        if key.len() < 32 {
+            return Err(format!("Share key {} is corrupt", path.display()));
+        }
+        return Ok(key);
+    }
+
+    use aes_gcm::aead::rand_core::RngCore;
+    let mut key = vec![0u8; 32];
+    aes_gcm::aead::OsRng.fill_bytes(&mut key);
+    crate::secrets::write_private(path, &key).map_err(|e| format!("Failed to write share key: {}", e))?;
+    Ok(key)
+}
+
+/// Share links persisted as `shares.json` in the server data dir
+#[derive(Clone)]
+pub struct ShareStore {
+    path: PathBuf,
+    key: Arc<Vec<u8>>,
+    shares: Arc<RwLock<HashMap<String, Share>>>,
+}
+
+impl ShareStore {
+    pub fn load(data_dir: &Path) -> Result<Self, String> {
+        let key = load_or_create_key(&data_dir.join("share.key"))?;
+        let path = data_dir.join("shares.json");
+
+        let mut shares: Vec<Share> = if path.exists() {
+            match fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string()))
+            {
+                Ok(shares) => shares,
+                Err(e) => {
+                    tracing::error!("[ShareStore] Failed to load {}: {}", path.display(), e);
+                    Vec::new()
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        let now = unix_secs();
+        shares.retain(|s| s.expires_at > now);
+
+        Ok(Self {
+            path,
+            key: Arc::new(key),
+            shares: Arc::new(RwLock::new(shares.into_iter().map(|s| (s.id.clone(), s)).collect())),
+        })
+    }
+
+    fn mac(&self, share_id: &str, agent_id: &str, expires_at: u64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(format!("{}.{}.{}", share_id, agent_id, expires_at).as_bytes());
+        mac
+    }
+
+    fn token_for(&self, share: &Share) -> String {
+        let signature: String = self
+            .mac(&share.id, &share.agent_id, share.expires_at)
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        format!("{}.{}.{}", share.id, share.expires_at, signature)
+    }
+
+    pub fn create(&self, agent_id: &str, ttl: Duration, label: Option<String>) -> Result<CreatedShare, String> {
+        let ttl = ttl.min(MAX_SHARE_TTL);
+        let now = unix_secs();
+        let share = Share {
+            id: uuid::Uuid::new_v4().to_string(),
+            agent_id: agent_id.to_string(),
+            label: label.filter(|l| !l.trim().is_empty()),
+            created_at: now,
+            expires_at: now + ttl.as_secs(),
+        };
+
+        {
+            let mut shares = self.shares.write().map_err(|e| e.to_string())?;
+            shares.insert(share.id.clone(), share.clone());
+        }
+        self.save()?;
+
+        let token = self.token_for(&share);
+        Ok(CreatedShare { share, token })
+    }
+
+    /// Resolve a token to its share if the signature is valid, it hasn't expired
+    /// and it hasn't been revoked
+    pub fn verify(&self, token: &str) -> Option<Share> {
+        let mut parts = token.splitn(3, '.');
+        let (id, expires_at, signature) = (parts.next()?, parts.next()?, parts.next()?);
+        let expires_at: u64 = expires_at.parse().ok()?;
+        if expires_at <= unix_secs() {
+            return None;
+        }
+
+        let share = self.shares.read().ok()?.get(id).cloned()?;
+        if share.expires_at != expires_at {
+            return None;
+        }
+
+        let signature: Vec<u8> = (0..signature.len())
+            .step_by(2)
+            .map(|i| signature.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect::<Option<_>>()?;
+        self.mac(&share.id, &share.agent_id, share.expires_at)
+            .verify_slice(&signature)
+            .ok()?;
+
+        Some(share)
+    }
+
+    /// Unexpired shares, optionally for one agent
+    pub fn list(&self, agent_id: Option<&str>) -> Vec<Share> {
+        let now = unix_secs();
+        let mut shares: Vec<Share> = self
+            .shares
+            .read()
+            .map(|s| {
+                s.values()
+                    .filter(|s| s.expires_at > now)
+                    .filter(|s| agent_id.is_none_or(|id| s.agent_id == id))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        shares.sort_by_key(|s| s.created_at);
+        shares
+    }
+
+    pub fn revoke(&self, id: &str) -> Result<(), String> {
+        {
+            let mut shares = self.shares.write().map_err(|e| e.to_string())?;
+            if shares.remove(id).is_none() {
+                return Err(format!("Share not found: {}", id));
+            }
+        }
+        self.save()
+    }
+
+    /// Drop every share of an agent, e.g. when it is killed
+    pub fn revoke_agent(&self, agent_id: &str) -> Result<(), String> {
+        let removed = {
+            let mut shares = self.shares.write().map_err(|e| e.to_string())?;
+            let before = shares.len();
+            shares.retain(|_, s| s.agent_id != agent_id);
+            before != shares.len()
+        };
+        if removed {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create data dir: {}", e))?;
+        }
+        let json = serde_json::to_string_pretty(&self.list(None))
+            .map_err(|e| format!("Failed to serialize shares: {}", e))?;
+        fs::write(&self.path, json).map_err(|e| format!("Failed to write shares file: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> (tempfile::TempDir, ShareStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ShareStore::load(dir.path()).unwrap();
+        (dir, store)
+    }
+
+    #[test]
+    fn valid_token_resolves_to_its_share() {
+        let (_dir, store) = store();
+        let created = store.create("agent-a", DEFAULT_SHARE_TTL, None).unwrap();
+
+        let share = store.verify(&created.token).unwrap();
+        assert_eq!(share.id, created.share.id);
+        assert_eq!(share.agent_id, "agent-a");
+    }
+
+    #[test]
+    fn tampered_signature_is_rejected() {
+        let (_dir, store) = store();
+        let created = store.create("agent-a", DEFAULT_SHARE_TTL, None).unwrap();
+
+        let mut token = created.token.clone();
+        let last = if token.ends_with('0') { '1' } else { '0' };
+        token.pop();
+        token.push(last);
+        assert!(store.verify(&token).is_none());
+
+        // The signature covers the expiry, so extending it invalidates the token
+        let (id, rest) = created.token.split_once('.').unwrap();
+        let (_, signature) = rest.split_once('.').unwrap();
+        let extended = format!("{}.{}.{}", id, created.share.expires_at + 60, signature);
+        assert!(store.verify(&extended).is_none());
+    }
+
+    #[test]
+    fn expired_token_is_rejected() {
+        let (_dir, store) = store();
+        let created = store.create("agent-a", Duration::ZERO, None).unwrap();
+
+        assert!(store.verify(&created.token).is_none());
+    }
+
+    #[test]
+    fn revoked_share_is_rejected() {
+        let (_dir, store) = store();
+        let created = store.create("agent-a", DEFAULT_SHARE_TTL, None).unwrap();
+        store.revoke(&created.share.id).unwrap();
+
+        assert!(store.verify(&created.token).is_none());
+
+        let created = store.create("agent-b", DEFAULT_SHARE_TTL, None).unwrap();
+        store.revoke_agent("agent-b").unwrap();
+
+        assert!(store.verify(&created.token).is_none());
+    }
+
+    #[test]
+    fn token_is_bound_to_its_agent() {
+        let (_dir, store) = store();
+        let a = store.create("agent-a", DEFAULT_SHARE_TTL, None).unwrap();
+        let b = store.create("agent-b", DEFAULT_SHARE_TTL, None).unwrap();
+
+        // Another share's signature doesn't carry over to a's id
+        let (_, b_signature) = b.token.rsplit_once('.').unwrap();
+        let forged = format!("{}.{}.{}", a.share.id, a.share.expires_at, b_signature);
+        assert!(store.verify(&forged).is_none());
+
+        // A share moved to another agent, e.g. by editing shares.json, no longer verifies
+        store.shares.write().unwrap().get_mut(&a.share.id).unwrap().agent_id = "agent-b".to_string();
+        assert!(store.verify(&a.token).is_none());
+    }
+}
@@ -0,0 +1,336 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Broadcast kinds a `/ws` client can subscribe to as a whole
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventKind {
+    AgentOutput,
+    AgentStatus,
+    TerminalOutput,
+    ConflictDetected,
+}
+
+const ALL_EVENT_KINDS: [EventKind; 4] = [
+    EventKind::AgentOutput,
+    EventKind::AgentStatus,
+    EventKind::TerminalOutput,
+    EventKind::ConflictDetected,
+];
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::AgentOutput => "agent-output",
+            EventKind::AgentStatus => "agent-status",
+            EventKind::TerminalOutput => "terminal-output",
+            EventKind::ConflictDetected => "conflict-detected",
+        }
+    }
+}
+
+impl std::str::FromStr for EventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ALL_EVENT_KINDS
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| format!("Unknown event kind: {}", s))
+    }
+}
+
+/// Ids and kinds named in a `subscribe` or `unsubscribe` message
+#[derive(Debug, Default, Deserialize)]
+pub struct SubscriptionChange {
+    #[serde(default)]
+    pub agents: Vec<String>,
+    #[serde(default)]
+    pub terminals: Vec<String>,
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+}
+
+impl SubscriptionChange {
+    /// Parse comma-separated lists, as given in query strings
+    pub fn from_lists(agents: Option<&str>, terminals: Option<&str>, events: Option<&str>) -> Result<Self, String> {
+        let split = |list: Option<&str>| -> Vec<String> {
+            list.unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        Ok(Self {
+            agents: split(agents),
+            terminals: split(terminals),
+            events: split(events)
+                .iter()
+                .map(|kind| kind.parse())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.agents.is_empty() && self.terminals.is_empty() && self.events.is_empty()
+    }
+}
+
+/// One `subscribe` message. Its kinds and ids narrow each other: `agent-status` with
+/// agent X is X's status only. Without kinds, the ids bring every kind of their events;
+/// without ids, the kinds cover every agent and terminal.
+#[derive(Debug)]
+struct Filter {
+    agents: HashSet<String>,
+    terminals: HashSet<String>,
+    events: HashSet<EventKind>,
+}
+
+impl Filter {
+    fn everything() -> Self {
+        Self {
+            agents: HashSet::new(),
+            terminals: HashSet::new(),
+            events: ALL_EVENT_KINDS.into_iter().collect(),
+        }
+    }
+
+    fn names_ids(&self) -> bool {
+        !self.agents.is_empty() || !self.terminals.is_empty()
+    }
+
+    fn matches(&self, kind: EventKind, agent_ids: &[&str], terminal_id: Option<&str>) -> bool {
+        let concerned = match terminal_id {
+            Some(id) => self.terminals.contains(id),
+            None => agent_ids.iter().any(|id| self.agents.contains(*id)),
+        };
+        self.events.contains(&kind) && (!self.names_ids() || concerned)
+    }
+}
+
+/// What one `/ws` or event stream connection receives: messages matching any of its
+/// subscriptions, so "everything about agent A" and "status of every agent" can be
+/// combined, minus agents and terminals it unsubscribed from. Connections start out
+/// receiving everything, so clients that never subscribe keep the old behaviour; the
+/// first `subscribe` replaces that with exactly what it names.
+#[derive(Debug)]
+pub struct Subscriptions {
+    explicit: bool,
+    filters: Vec<Filter>,
+    excluded_agents: HashSet<String>,
+    excluded_terminals: HashSet<String>,
+    /// Agent every delivered message must concern, whatever is subscribed
+    scope: Option<String>,
+}
+
+impl Default for Subscriptions {
+    fn default() -> Self {
+        Self {
+            explicit: false,
+            filters: vec![Filter::everything()],
+            excluded_agents: HashSet::new(),
+            excluded_terminals: HashSet::new(),
+            scope: None,
+        }
+    }
+}
+
+impl Subscriptions {
+    /// Subscriptions of a per-agent stream, which never delivers another agent's events
+    pub fn for_agent(agent_id: String) -> Self {
+        Self {
+            scope: Some(agent_id),
+            ..Self::default()
+        }
+    }
+
+    pub fn subscribe(&mut self, change: SubscriptionChange) {
+        if !self.explicit {
+            self.filters.clear();
+            self.explicit = true;
+        }
+        for agent in &change.agents {
+            self.excluded_agents.remove(agent);
+        }
+        for terminal in &change.terminals {
+            self.excluded_terminals.remove(terminal);
+        }
+
+        let events = if change.events.is_empty() {
+            ALL_EVENT_KINDS.into_iter().collect()
+        } else {
+            change.events.into_iter().collect()
+        };
+        self.filters.push(Filter {
+            agents: change.agents.into_iter().collect(),
+            terminals: change.terminals.into_iter().collect(),
+            events,
+        });
+    }
+
+    /// Stop receiving the named agents' and terminals' events under any subscription,
+    /// and the named kinds altogether. On a connection that never subscribed, this
+    /// leaves everything else.
+    pub fn unsubscribe(&mut self, change: SubscriptionChange) {
+        for filter in &mut self.filters {
+            // A filter whose last id goes would otherwise widen to every agent and terminal
+            let named_ids = filter.names_ids();
+            for agent in &change.agents {
+                filter.agents.remove(agent);
+            }
+            for terminal in &change.terminals {
+                filter.terminals.remove(terminal);
+            }
+            if named_ids && !filter.names_ids() {
+                filter.events.clear();
+            }
+            for kind in &change.events {
+                filter.events.remove(kind);
+            }
+        }
+        self.filters.retain(|filter| !filter.events.is_empty());
+        self.excluded_agents.extend(change.agents);
+        self.excluded_terminals.extend(change.terminals);
+    }
+
+    /// Terminal events are those with a `terminal_id`; the rest belong to agents
+    pub fn wants(&self, kind: EventKind, agent_ids: &[&str], terminal_id: Option<&str>) -> bool {
+        if terminal_id.is_some_and(|id| self.excluded_terminals.contains(id)) {
+            return false;
+        }
+        let included: Vec<&str> = agent_ids
+            .iter()
+            .copied()
+            .filter(|id| !self.excluded_agents.contains(*id))
+            .collect();
+        if included.len() < agent_ids.len() && included.is_empty() {
+            return false;
+        }
+        let agent_ids = included;
+        let in_scope = self.scope.as_deref().is_none_or(|scope| agent_ids.contains(&scope));
+
+        in_scope && self.filters.iter().any(|filter| filter.matches(kind, &agent_ids, terminal_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(agents: &[&str], terminals: &[&str], events: &[EventKind]) -> SubscriptionChange {
+        SubscriptionChange {
+            agents: agents.iter().map(|id| id.to_string()).collect(),
+            terminals: terminals.iter().map(|id| id.to_string()).collect(),
+            events: events.to_vec(),
+        }
+    }
+
+    #[test]
+    fn unsubscribed_connections_receive_everything() {
+        let subscriptions = Subscriptions::default();
+        assert!(subscriptions.wants(EventKind::AgentOutput, &["a"], None));
+        assert!(subscriptions.wants(EventKind::TerminalOutput, &[], Some("t")));
+    }
+
+    #[test]
+    fn kinds_and_agents_narrow_each_other() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.subscribe(change(&["a"], &[], &[EventKind::AgentStatus]));
+
+        assert!(subscriptions.wants(EventKind::AgentStatus, &["a"], None));
+        assert!(!subscriptions.wants(EventKind::AgentStatus, &["b"], None));
+        assert!(!subscriptions.wants(EventKind::AgentOutput, &["a"], None));
+        assert!(!subscriptions.wants(EventKind::TerminalOutput, &[], Some("t")));
+    }
+
+    #[test]
+    fn ids_alone_bring_every_kind_of_their_events() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.subscribe(change(&["a"], &["t"], &[]));
+
+        assert!(subscriptions.wants(EventKind::AgentOutput, &["a"], None));
+        assert!(subscriptions.wants(EventKind::ConflictDetected, &["b", "a"], None));
+        assert!(!subscriptions.wants(EventKind::AgentOutput, &["b"], None));
+        assert!(subscriptions.wants(EventKind::TerminalOutput, &[], Some("t")));
+        assert!(!subscriptions.wants(EventKind::TerminalOutput, &[], Some("u")));
+    }
+
+    #[test]
+    fn kinds_alone_cover_every_agent() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.subscribe(change(&[], &[], &[EventKind::AgentOutput]));
+
+        assert!(subscriptions.wants(EventKind::AgentOutput, &["a"], None));
+        assert!(subscriptions.wants(EventKind::AgentOutput, &["b"], None));
+        assert!(!subscriptions.wants(EventKind::AgentStatus, &["a"], None));
+    }
+
+    #[test]
+    fn subscriptions_add_up() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.subscribe(change(&["a"], &[], &[]));
+        subscriptions.subscribe(change(&[], &[], &[EventKind::AgentStatus]));
+
+        assert!(subscriptions.wants(EventKind::AgentOutput, &["a"], None));
+        assert!(subscriptions.wants(EventKind::AgentStatus, &["a"], None));
+        assert!(subscriptions.wants(EventKind::AgentStatus, &["b"], None));
+        assert!(!subscriptions.wants(EventKind::AgentOutput, &["b"], None));
+    }
+
+    #[test]
+    fn unsubscribing_before_subscribing_excludes_from_everything() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.unsubscribe(change(&["a"], &["t"], &[]));
+
+        assert!(!subscriptions.wants(EventKind::AgentOutput, &["a"], None));
+        assert!(!subscriptions.wants(EventKind::AgentStatus, &["a"], None));
+        assert!(subscriptions.wants(EventKind::AgentOutput, &["b"], None));
+        assert!(!subscriptions.wants(EventKind::TerminalOutput, &[], Some("t")));
+        assert!(subscriptions.wants(EventKind::TerminalOutput, &[], Some("u")));
+
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.unsubscribe(change(&[], &[], &[EventKind::AgentOutput]));
+
+        assert!(!subscriptions.wants(EventKind::AgentOutput, &["a"], None));
+        assert!(subscriptions.wants(EventKind::AgentStatus, &["a"], None));
+        assert!(subscriptions.wants(EventKind::TerminalOutput, &[], Some("t")));
+    }
+
+    #[test]
+    fn unsubscribing_the_last_id_does_not_widen() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.subscribe(change(&["a"], &[], &[EventKind::AgentOutput]));
+        subscriptions.subscribe(change(&[], &[], &[EventKind::AgentStatus]));
+        subscriptions.unsubscribe(change(&["a"], &[], &[]));
+
+        assert!(!subscriptions.wants(EventKind::AgentOutput, &["a"], None));
+        assert!(!subscriptions.wants(EventKind::AgentOutput, &["b"], None));
+        assert!(!subscriptions.wants(EventKind::AgentStatus, &["a"], None));
+        assert!(subscriptions.wants(EventKind::AgentStatus, &["b"], None));
+
+        // Subscribing again lifts the exclusion
+        subscriptions.subscribe(change(&["a"], &[], &[]));
+        assert!(subscriptions.wants(EventKind::AgentOutput, &["a"], None));
+    }
+
+    #[test]
+    fn agent_streams_only_deliver_their_agent() {
+        let subscriptions = Subscriptions::for_agent("a".to_string());
+        assert!(subscriptions.wants(EventKind::AgentOutput, &["a"], None));
+        assert!(!subscriptions.wants(EventKind::AgentOutput, &["b"], None));
+        assert!(!subscriptions.wants(EventKind::TerminalOutput, &[], Some("t")));
+
+        // Kind filters apply on top, still limited to the stream's agent
+        let mut subscriptions = Subscriptions::for_agent("a".to_string());
+        subscriptions.subscribe(change(&[], &[], &[EventKind::AgentOutput]));
+        assert!(subscriptions.wants(EventKind::AgentOutput, &["a"], None));
+        assert!(!subscriptions.wants(EventKind::AgentOutput, &["b"], None));
+        assert!(!subscriptions.wants(EventKind::AgentStatus, &["a"], None));
+
+        // Naming another agent can't widen the stream
+        let mut subscriptions = Subscriptions::for_agent("a".to_string());
+        subscriptions.subscribe(change(&["b"], &[], &[]));
+        assert!(!subscriptions.wants(EventKind::AgentOutput, &["b"], None));
+    }
+}
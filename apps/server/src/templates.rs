@@ -0,0 +1,23 @@
+pub use virtual_agency_templates::{AgentTemplate, TemplateFile};
+
+use crate::library::{Library, LibraryItem};
+
+impl LibraryItem for AgentTemplate {
+    const FILE: &'static str = "templates.json";
+    const KIND: &'static str = "Template";
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn set_id(&mut self, id: String) {
+        self.id = id;
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Template library persisted as `templates.json` in the server data dir
+pub type TemplateStore = Library<AgentTemplate>;
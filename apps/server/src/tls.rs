@@ -0,0 +1,96 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Certificate and key in PEM form, plus the certificate's SHA-256 fingerprint
+pub struct TlsMaterial {
+    pub cert_pem: Vec<u8>,
+    pub key_pem: Vec<u8>,
+    /// Colon-separated uppercase hex, as shown by browsers and `openssl x509 -fingerprint`
+    pub fingerprint: String,
+    /// Where the certificate came from, for the startup log
+    pub cert_path: PathBuf,
+}
+
+/// Read user-supplied PEM files, or the self-signed pair in `<data dir>/tls`,
+/// generating that pair on first use
+pub fn load_or_generate(
+    cert_path: Option<&Path>,
+    key_path: Option<&Path>,
+    data_dir: &Path,
+    hostnames: &[String],
+) -> Result<TlsMaterial, String> {
+    let (cert_path, key_path) = match (cert_path, key_path) {
+        (Some(cert), Some(key)) => (cert.to_path_buf(), key.to_path_buf()),
+        (None, None) => {
+            let dir = data_dir.join("tls");
+            let cert = dir.join("cert.pem");
+            let key = dir.join("key.pem");
+            if !cert.exists() || !key.exists() {
+                generate_self_signed(&cert, &key, hostnames)?;
+            }
+            (cert, key)
+        }
+        _ => return Err("TLS needs both a certificate and a key".to_string()),
+    };
+
+    let cert_pem = fs::read(&cert_path)
+        .map_err(|e| format!("Failed to read certificate {}: {}", cert_path.display(), e))?;
+    let key_pem = fs::read(&key_path)
+        .map_err(|e| format!("Failed to read private key {}: {}", key_path.display(), e))?;
+    let fingerprint = fingerprint(&cert_pem)?;
+
+    Ok(TlsMaterial {
+        cert_pem,
+        key_pem,
+        fingerprint,
+        cert_path,
+    })
+}
+
+fn generate_self_signed(cert_path: &Path, key_path: &Path, hostnames: &[String]) -> Result<(), String> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()];
+    for name in hostnames {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+
+    let certified = rcgen::generate_simple_self_signed(names.clone())
+        .map_err(|e| format!("Failed to generate certificate: {}", e))?;
+
+    if let Some(parent) = cert_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create TLS dir: {}", e))?;
+    }
+    fs::write(cert_path, certified.cert.pem()).map_err(|e| format!("Failed to write certificate: {}", e))?;
+    crate::secrets::write_private(key_path, certified.key_pair.serialize_pem().as_bytes())
+        .map_err(|e| format!("Failed to write private key: {}", e))?;
+
+    tracing::info!(
+        "[tls] Generated self-signed certificate for {} at {}",
+        names.join(", "),
+        cert_path.display()
+    );
+    Ok(())
+}
+
+/// SHA-256 over the DER of the first certificate in a PEM file
+fn fingerprint(cert_pem: &[u8]) -> Result<String, String> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let pem = std::str::from_utf8(cert_pem).map_err(|_| "Certificate is not PEM".to_string())?;
+    let start = pem.find(BEGIN).ok_or_else(|| "No certificate found in PEM file".to_string())? + BEGIN.len();
+    let end = pem[start..].find(END).ok_or_else(|| "Truncated certificate in PEM file".to_string())? + start;
+    let body: String = pem[start..end].chars().filter(|c| !c.is_whitespace()).collect();
+    let der = STANDARD
+        .decode(body)
+        .map_err(|e| format!("Invalid certificate encoding: {}", e))?;
+
+    Ok(Sha256::digest(&der)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":"))
+}
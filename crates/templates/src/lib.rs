@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+/// Identifies exported template files so unrelated JSON isn't imported by mistake
+pub const TEMPLATE_FILE_FORMAT: &str = "virtual-agency-template";
+pub const TEMPLATE_FILE_VERSION: u32 = 1;
+
+static DEFAULT_MODEL: OnceLock<String> = OnceLock::new();
+
+/// Make the configured default model available to serde defaults, which can't take state
+pub fn set_default_model(model: String) {
+    let _ = DEFAULT_MODEL.set(model);
+}
+
+pub fn default_model() -> String {
+    DEFAULT_MODEL.get().cloned().unwrap_or_else(|| "sonnet".to_string())
+}
+
+/// A named, reusable agent configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentTemplate {
+    /// Generated when empty
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_model")]
+    pub model: String,
+    #[serde(default)]
+    pub thinking_enabled: bool,
+    #[serde(default)]
+    pub mcp_servers: Vec<String>,
+    #[serde(default)]
+    pub allowed_tools: Vec<String>,
+    #[serde(default)]
+    pub disallowed_tools: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub append_system_prompt: Option<String>,
+    /// Only used by the web server, which hosts the persona library
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persona_id: Option<String>,
+}
+
+/// Standalone JSON file a template is exported to and imported from
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TemplateFile {
+    pub format: String,
+    pub version: u32,
+    pub template: AgentTemplate,
+}
+
+impl TemplateFile {
+    pub fn new(template: AgentTemplate) -> Self {
+        Self {
+            format: TEMPLATE_FILE_FORMAT.to_string(),
+            version: TEMPLATE_FILE_VERSION,
+            template,
+        }
+    }
+
+    pub fn into_template(self) -> Result<AgentTemplate, String> {
+        if self.format != TEMPLATE_FILE_FORMAT {
+            return Err(format!("Not a template file (format: {})", self.format));
+        }
+        if self.version > TEMPLATE_FILE_VERSION {
+            return Err(format!("Unsupported template file version: {}", self.version));
+        }
+        Ok(self.template)
+    }
+}
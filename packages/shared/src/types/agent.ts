@@ -120,4 +120,7 @@ export interface Agent {
   avatarId?: AvatarId;
   mcpServers?: MCPServerId[]; // List of enabled MCP server IDs
   sessionId?: string; // Claude CLI session ID for conversation continuity
+  systemPrompt?: string; // Replaces the CLI's default system prompt
+  appendSystemPrompt?: string; // Appended to the CLI's default system prompt
+  personaId?: string; // Persona from the server library
 }